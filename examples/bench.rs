@@ -0,0 +1,78 @@
+//times the operations split across threads by the multicore feature,
+//run it with and without the feature to see the speedup:
+//
+//    cargo run --release --example bench
+//    cargo run --release --example bench --features multicore
+
+use orbclient::Color;
+use orbimage::Image;
+
+use std::time::Instant;
+
+use pastel::document::Document;
+use pastel::layer::{BlendMode, Layer};
+use pastel::parallel;
+
+const SIZE: u32 = 2048;
+
+//(operation, a, b) as given to Document::transformation
+const OPERATIONS: [(&str, f32, i32); 5] = [
+    ("blur", 4.0, 0),
+    ("unsharpen", 4.0, 0),
+    ("contrast", 20.0, 0),
+    ("rotate", 30.0, 0),
+    ("resize", 3000.0, 3000),
+];
+
+fn main() {
+    println!("{}x{} pixels, {} threads", SIZE, SIZE, parallel::threads());
+
+    for &(cod, a, b) in OPERATIONS.iter() {
+        let document = Document::from_image(pattern(SIZE, SIZE, 0));
+        let start = Instant::now();
+        document.transformation(cod, a, b);
+        report(cod, start);
+    }
+
+    let layers = vec![
+        Layer::new("Background", pattern(SIZE, SIZE, 0)),
+        blended("Multiply", BlendMode::Multiply, 1),
+        blended("Screen", BlendMode::Screen, 2),
+        blended("Overlay", BlendMode::Overlay, 3),
+    ];
+    let document = Document::from_layers(layers, 0, Image::new(0, 0));
+    let start = Instant::now();
+    document.flattened();
+    report("composite", start);
+
+    let path = std::env::temp_dir().join("pastel_bench.png");
+    let start = Instant::now();
+    if let Err(e) = document.save(path.to_str().unwrap()) {
+        println!("save failed: {}", e);
+    }
+    report("save", start);
+}
+
+fn report(name: &str, start: Instant) {
+    let elapsed = start.elapsed();
+    println!("{:<12}{:>8.1} ms", name, elapsed.as_secs() as f64 * 1000.0 + elapsed.subsec_nanos() as f64 / 1e6);
+}
+
+fn blended(name: &str, blend: BlendMode, seed: u32) -> Layer {
+    let mut layer = Layer::new(name, pattern(SIZE, SIZE, seed));
+    layer.blend = blend;
+    layer.opacity = 60;
+    layer
+}
+
+///some non uniform content, so no operation can take shortcuts
+fn pattern(width: u32, height: u32, seed: u32) -> Image {
+    let mut data = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let v = (x * 7 + y * 13 + seed * 31) ^ (x * y + seed);
+            data.push(Color::rgba(v as u8, (v >> 3) as u8, (x ^ y) as u8, 128 + (v >> 5) as u8 % 128));
+        }
+    }
+    Image::from_data(width, height, data.into_boxed_slice()).unwrap()
+}
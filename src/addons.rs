@@ -1,237 +1,27 @@
 use orbimage;
-use orbimage::ResizeType;
 use orbclient;
 use orbtk::{Color, Rect, Renderer, Window}; 
-use orbclient::EventOption;
+use orbclient::{EventOption, K_ENTER};
+use pastel::selection::Selection;
+use pastel::stabilizer::Stabilizer;
 
 use std::mem::swap;
 
 use crate::CANVASOFFSET;
 
-//  which ones to be added directly to orbclient ?
-pub trait AddOnsToOrbimage {
-    fn fill(&mut self, x: i32 , y: i32, color: Color);
-    fn flood_fill4(&mut self, x:i32, y:i32, new_color: u32 , old_color: u32);
-    fn flood_fill_scanline(&mut self, x:i32, y:i32, new_color: u32 , old_color: u32);
-    fn flood_fill_line(&mut self, x:i32, y:i32, new_color: u32 , old_color: u32);
-    fn pixcol(&self, x:i32, y:i32) -> Color;
-    fn pixraw(&self, x:i32, y:i32) -> u32;
+//interactive helpers, they draw previews directly into the window
+pub trait InteractOrbimage {
     fn interact_line(&mut self, x: i32 , y: i32, color: Color,width: i32, antialias: bool, window: &mut Window) ->Option<(i32, i32, i32, i32)>;
     fn interact_circle(&mut self, x: i32 , y: i32, color: Color, window: &mut Window) -> Option<(i32,f32)>;
     fn interact_paste(&mut self, x: i32 , y: i32, opacity: u8, buffer: orbimage::Image, window: &mut Window) -> Option<(i32,i32)>;
     fn select_rect(&mut self, x: i32 , y: i32, window: &mut Window) ->Option<Rect>;
     fn new_select_rect(&mut self, x: i32 , y: i32, color: Color, pattern: i32, window: &mut Window) ->Option<Rect>;
-    fn copy_selection(&self, x: i32,y: i32,w: u32, h: u32) -> orbimage::Image;
-    fn paste_selection(&mut self, x: i32, y:i32, opacity: u8, buffer: orbimage::Image);
-    fn smooth_circle(&mut self, x: i32, y:i32, size: u32, color: Color);
-    fn colorize (&self, color: Color) -> orbimage::Image;
-    
+    fn select_ellipse(&mut self, x: i32 , y: i32, window: &mut Window) ->Option<Rect>;
+    fn select_lasso(&mut self, x: i32 , y: i32, stabilizer: f32, window: &mut Window) ->Option<Vec<(i32, i32)>>;
+    fn select_polygon(&mut self, x: i32 , y: i32, window: &mut Window) ->Option<Vec<(i32, i32)>>;
 }
 
-impl AddOnsToOrbimage for orbimage::Image {
-    ///return pixel color safely
-    fn pixcol(&self, x:i32, y:i32) -> Color {
-        let p = (self.width()as i32 * y + x) as usize;
-        if p < self.data().len() {
-            self.data()[p] //return rgba value if exist
-        }else { Color::rgba(0,0,0,0)}
-    }
-    
-    fn pixraw (&self, x:i32, y:i32) -> u32 {
-        self.pixcol(x,y).data 
-    }
-    
-    ///wrapper for flood fill 
-    fn fill(&mut self, x: i32, y: i32 , color: Color) {
-        //get current pixel color 
-        let rgba = self.pixcol(x,y);
-        //self.flood_fill4(x,y,color.data,rgba.data);  //use rgba and color as i32 values 
-        //println!("Old color {}", rgba.data);
-        self.flood_fill_scanline(x,y,color.data,rgba.data);  //use rgba and color as i32 values 
-    }
-
-    /*Recursive 4-way floodfill works with transparency but be aware of stack overflow !! 
-    credits to http://lodev.org/cgtutor/floodfill.html
-    Added stacker crate to mitigate overflow issue , does it work also for Redox?
-    */
-    fn flood_fill4(&mut self, x:i32, y:i32, new_color: u32 , old_color: u32) {
-        //stacker::maybe_grow(32 * 1024, 1024 * 1024, || {
-        
-        if x >= 0 && x < self.width()as i32 && y >= 0 && y < self.height() as i32 
-            && self.pixcol(x,y).data == old_color && self.pixcol(x,y).data != new_color {
-            
-            self.pixel(x,y, Color{data:new_color});
-            
-            self.flood_fill4(x+1,y,new_color,old_color);
-            self.flood_fill4(x-1,y,new_color,old_color);
-            self.flood_fill4(x,y+1,new_color,old_color);
-            self.flood_fill4(x,y-1,new_color,old_color);
-        }
-        //});
-    }
-
-    ///stack friendly and fast floodfill algorithm that works with transparency too 
-    fn flood_fill_scanline( &mut self, x:i32, y:i32, new_color: u32, old_color:u32) {
-        if old_color == new_color {
-            return;
-        }
-        if self.pixcol(x,y).data  != old_color  {
-            return;
-        }
-
-        let w = self.width() as i32;
-        let h = self.height() as i32;
-
-        //draw current scanline from start position to the right
-        let mut x1 = x;
-
-        while x1 < w && self.pixcol(x1,y).data  == old_color  {
-            self.pixel(x1,y,Color{data:new_color});
-            x1 +=1;
-        }
-        //get resulted color because of transparency and use this for comparison 
-        let res_color = self.pixcol(x,y).data;
-        
-        //draw current scanline from start position to the left
-        x1 = x -1;
-        
-        while x1 >= 0 && self.pixcol(x1,y).data  == old_color  {
-            self.pixel(x1,y,Color{data:new_color});
-            x1 += -1;
-          }
-        
-        //test for new scanlines above
-        x1 = x;
-        
-        while x1 < w && self.pixcol(x1,y).data  == res_color  { 
-            if y > 0 && self.pixcol(x1,y-1).data  == old_color  {
-              self.flood_fill_scanline(x1, y - 1, new_color, old_color);
-            }
-            x1 += 1;
-          }
-        x1 = x - 1;
-        while x1 >= 0 && self.pixcol(x1,y).data == res_color {
-            if y > 0 && self.pixcol(x1,y - 1).data  == old_color  {
-              self.flood_fill_scanline(x1, y - 1, new_color, old_color);
-            }
-            x1 += -1;
-          }
-         
-         //test for new scanlines below
-        x1 = x;
-        while x1 < w && self.pixcol(x1,y).data == res_color  {
-            //println!("Test below {} {} ", self.pixcol(x1,y).data,old_color);
-            if y < (h - 1) && self.pixcol(x1,y + 1).data == old_color {
-                self.flood_fill_scanline(x1, y + 1, new_color, old_color);
-            }
-            x1 +=1;
-        }
-        x1 = x - 1;
-        while x1 >= 0 && self.pixcol(x1,y).data == res_color {
-            if y < (h - 1) && self.pixcol(x1,y + 1).data == old_color {
-                self.flood_fill_scanline(x1, y + 1, new_color, old_color);
-            }
-            x1 += -1;
-        }
-    }
-
-    fn flood_fill_line(&mut self, x:i32, y:i32, new_color: u32 , old_color: u32) {
-        if x >= 1 && x < self.width()as i32 && y >= 0 && y < self.height() as i32 
-            && self.pixcol(x,y).data == old_color && self.pixcol(x,y).data != new_color {
-           
-           let mut x1=x; 
-           loop {
-                if x1>=0 && x1< self.width() as i32 && self.pixcol(x1,y).data == old_color{
-                    self.pixel(x1,y, Color{data:new_color});
-                    x1 +=1;
-                } else {break}  
-            }
-           x1=x-1; 
-           loop {
-                if x1>=0 && x1< self.width() as i32 && self.pixcol(x1,y).data == old_color{
-                    self.pixel(x1,y, Color{data:new_color});
-                    x1 +=-1;
-                } else {break}  
-            }
-            self.flood_fill_line(x,y+1,new_color,old_color);
-            self.flood_fill_line(x,y-1,new_color,old_color);
-        }
-    }
-
-    ///crop new image from current image (copy) tranforming pure white into transparent
-    fn copy_selection(&self, x: i32,y: i32,w: u32, h: u32) -> orbimage::Image {
-
-        let mut vec = vec![];
-        let mut col : Color;
-        
-        for y1 in y..y+h as i32 {
-            for x1 in x..x+w as i32 {
-                col=self.pixcol(x1,y1);
-                if col.r()==255 && col.g()==255 && col.b()==255 {
-                    col = Color::rgba(0,0,0,0);
-                }
-                vec.push(col);
-            }
-        }
-        //println!("buffer {:?}",&vec);
-        orbimage::Image::from_data(w ,h ,vec.into_boxed_slice()).unwrap()
-    }
-
-    ///draws an image into current image starting at x,y (paste) with transparency
-    fn paste_selection (&mut self, x: i32, y:i32, opacity: u8, buffer: orbimage::Image, ){
-        
-        let w = buffer.width() as i32;
-        let h = buffer.height() as i32;
-        let xc=x-w/2; //center buffer at cursor 
-        let yc=y-h/2;
-        let data = buffer.into_data();
-        let mut i:usize = 0;
-        let mut r;
-        let mut g;
-        let mut b;
-        let mut a;
-        let x1:i32;
-        let y1:i32;
-        
-        for y1 in yc..yc+h {
-            for x1 in xc..xc+w {
-                if i < data.len(){
-                    r = data[i].r();
-                    g = data[i].g();
-                    b = data[i].b();
-                    a = data[i].a();
-                    if a != 0 {a = opacity}
-                    self.pixel(x1,y1,Color::rgba(r,g,b,a));
-                }
-                i += 1;
-            }
-        }
-    }
-    
-    //experimental smooth brush : work in progress....
-    fn smooth_circle (&mut self, x: i32, y:i32, size: u32, color: Color) {
-        //let mut sb= orbimage::Image::from_color(2*size, 2*size, Color::rgba(255,255,255,0));
-        let sb = orbimage::Image::from_path("smooth_circle_black.png").unwrap();
-        let rb = sb.resize(size, size, ResizeType::Lanczos3).unwrap();
-        
-        let r = color.r();
-        let g = color.g();
-        let b = color.b();
-        let a = color.a();
-        
-        
-        /*
-        for n in 0..size {
-            //sb.circle(size as i32 , size as i32 , ((size -n) as i32), Color::rgba(r,g,b,(2*n) as u8));
-            sb.pixel(n as i32,n as i32, Color::rgba(r,g,b,(4*n)as u8)); //Does NOT work as intended!!
-            //sb.pixel(n as i32,n as i32, Color::rgba(r,g,b,(4*n)as u8));
-        }
-        */
-        //self.paste_selection(x,y,80,sb);
-        self.image(x,y,rb.width(),rb.height(),rb.data());
-        //println!("{:?}",sb.data());
-        
-    }
+impl InteractOrbimage for orbimage::Image {
 
     /// interactive selection (rectangle)  
     fn select_rect(&mut self, x: i32 , y: i32, window: &mut Window) ->Option<Rect> {
@@ -377,6 +167,159 @@ impl AddOnsToOrbimage for orbimage::Image {
         None  
     }
 
+    /// interactive elliptical selection, the ellipse fits the rectangle from x,y to the cursor
+    fn select_ellipse(&mut self, x: i32 , y: i32, window: &mut Window) ->Option<Rect> {
+        let mut orbclient = window.inner.borrow_mut();
+        let color = Color::rgba(100, 100, 100, 0);
+        //outline drawn on screen, drawing it again erases it
+        let mut shown: Vec<(i32, i32)> = Vec::new();
+        let mut lx = x;
+        let mut ly = y;
+        'events: loop{
+            for event in orbclient.events() {
+                match event.to_option() {
+                    EventOption::Key(_key_event) => break 'events,
+                    EventOption::Quit(_quit_event) => break 'events,
+                    EventOption::Mouse(evt) => {
+                                                if evt.y < CANVASOFFSET{
+                                                    break 'events;
+                                                };
+                                                orbclient.poly_marquee(&shown, color, 3);
+                                                lx = evt.x;
+                                                ly = evt.y - CANVASOFFSET;
+                                                let rect = pastel::Rect::new(x.min(lx), y.min(ly) + CANVASOFFSET,
+                                                    (lx - x).abs() as u32, (ly - y).abs() as u32);
+                                                shown = Selection::ellipse(rect).outline();
+                                                orbclient.poly_marquee(&shown, color, 3);
+                                                orbclient.sync();
+                                                },
+                    EventOption::Button(btn) => {
+                                                if btn.left {
+                                                    return Some(Rect::new(x.min(lx), y.min(ly),
+                                                        (lx - x).abs() as u32, (ly - y).abs() as u32))
+                                                }
+                                                if btn.right{
+                                                    break 'events;
+                                                }
+                                                },
+                    event_option => if cfg!(feature = "debug"){
+                                        println!("{:?}", event_option)
+                                    }else{}
+                }
+            }
+        }
+        None
+    }
+
+    /// interactive freehand selection: drag with the left button down, releasing it closes the outline;
+    /// with a stabilizer strength above 0 the outline follows the pointer smoothed
+    fn select_lasso(&mut self, x: i32 , y: i32, stabilizer: f32, window: &mut Window) ->Option<Vec<(i32, i32)>> {
+        let mut orbclient = window.inner.borrow_mut();
+        let mut points = vec![(x, y)];
+        let mut stabilizer = if stabilizer > 0.0 { Some(Stabilizer::new(stabilizer, 1.0, x as f32, y as f32)) } else { None };
+        'events: loop{
+            for event in orbclient.events() {
+                match event.to_option() {
+                    EventOption::Key(_key_event) => break 'events,
+                    EventOption::Quit(_quit_event) => break 'events,
+                    EventOption::Mouse(evt) => {
+                                                if evt.y < CANVASOFFSET{
+                                                    break 'events;
+                                                };
+                                                let next = match stabilizer {
+                                                    Some(ref mut stabilizer) => stabilizer.to(evt.x as f32, (evt.y - CANVASOFFSET) as f32),
+                                                    None => vec![(evt.x as f32, (evt.y - CANVASOFFSET) as f32)],
+                                                };
+                                                for (x, y) in next {
+                                                    let (lx, ly) = points[points.len() - 1];
+                                                    let (x, y) = (x.round() as i32, y.round() as i32);
+                                                    if (x, y) != (lx, ly) {
+                                                        orbclient.ant_line(lx, ly + CANVASOFFSET, x, y + CANVASOFFSET, Color::rgba(100, 100, 100, 0), 1);
+                                                        points.push((x, y));
+                                                    }
+                                                }
+                                                orbclient.sync();
+                                                },
+                    EventOption::Button(btn) => {
+                                                if btn.right {
+                                                    break 'events;
+                                                }
+                                                if !btn.left {
+                                                    if let Some(ref mut stabilizer) = stabilizer {
+                                                        points.extend(stabilizer.finish().into_iter().map(|(x, y)| (x.round() as i32, y.round() as i32)));
+                                                    }
+                                                    return if points.len() > 2 { Some(points) } else { None };
+                                                }
+                                                },
+                    event_option => if cfg!(feature = "debug"){
+                                        println!("{:?}", event_option)
+                                    }else{}
+                }
+            }
+        }
+        None
+    }
+
+    /// interactive polygonal selection: each click places a corner, clicking on the first one
+    /// or pressing Enter closes the outline, other keys and the right button cancel
+    fn select_polygon(&mut self, x: i32 , y: i32, window: &mut Window) ->Option<Vec<(i32, i32)>> {
+        let mut orbclient = window.inner.borrow_mut();
+        let color = Color::rgba(100, 100, 100, 0);
+        let mut points = vec![(x, y)];
+        let mut cursor = (x, y);
+        //a line from the last corner to the cursor is on screen
+        let mut rubber = false;
+        'events: loop{
+            for event in orbclient.events() {
+                match event.to_option() {
+                    EventOption::Key(key_event) => {
+                                                if !key_event.pressed {
+                                                    continue;
+                                                }
+                                                if key_event.scancode == K_ENTER && points.len() > 2 {
+                                                    return Some(points);
+                                                }
+                                                break 'events;
+                                                },
+                    EventOption::Quit(_quit_event) => break 'events,
+                    EventOption::Mouse(evt) => {
+                                                if evt.y < CANVASOFFSET{
+                                                    break 'events;
+                                                };
+                                                let (lx, ly) = points[points.len() - 1];
+                                                if rubber {
+                                                    orbclient.ant_line(lx, ly + CANVASOFFSET, cursor.0, cursor.1 + CANVASOFFSET, color, 1);
+                                                }
+                                                cursor = (evt.x, evt.y - CANVASOFFSET);
+                                                orbclient.ant_line(lx, ly + CANVASOFFSET, cursor.0, cursor.1 + CANVASOFFSET, color, 1);
+                                                rubber = true;
+                                                orbclient.sync();
+                                                },
+                    EventOption::Button(btn) => {
+                                                if btn.right {
+                                                    break 'events;
+                                                }
+                                                if btn.left {
+                                                    let (fx, fy) = points[0];
+                                                    if points.len() > 2 && (cursor.0 - fx).abs() <= 4 && (cursor.1 - fy).abs() <= 4 {
+                                                        return Some(points);
+                                                    }
+                                                    //the line to the cursor stays as a side
+                                                    if cursor != points[points.len() - 1] {
+                                                        points.push(cursor);
+                                                        rubber = false;
+                                                    }
+                                                }
+                                                },
+                    event_option => if cfg!(feature = "debug"){
+                                        println!("{:?}", event_option)
+                                    }else{}
+                }
+            }
+        }
+        None
+    }
+
     /// by drawing an interactive circle in preview window , return a tuple with radius and cursor angular position  
     fn interact_circle(&mut self, x: i32 , y: i32, color: Color, window: &mut Window) -> Option<(i32,f32)>{
     
@@ -609,22 +552,6 @@ impl AddOnsToOrbimage for orbimage::Image {
         }
     }
 */
-    /// colorize a grayscale image 
-    fn colorize (&self, color: Color) -> orbimage::Image {
-        let w = self.width();
-        let h = self.height();
-        let mut data = self.clone().into_data();
-        let mut a;
-        for i in 0..data.len() {
-            a = data[i].a();
-            data[i]=Color::rgba(color.r(),color.g(),color.b(),a);
-        }
-        orbimage::Image::from_data(w ,h ,data).unwrap()
-    }
-
-
-
-
 }
 
 pub trait AddOnsToOrbclient {
@@ -632,6 +559,7 @@ pub trait AddOnsToOrbclient {
     fn ant_line(&mut self, argx1: i32, argy1: i32, argx2: i32, argy2: i32, color: Color, style: i32);
     fn rect_marquee(&mut self , argx1: i32, argy1: i32, argx2: i32, argy2: i32, color: Color, style: i32);
     fn circle_marquee(&mut self, x0: i32, y0: i32 , radius: i32 , color: Color);
+    fn poly_marquee(&mut self, points: &[(i32, i32)], color: Color, style: i32);
     fn rect_hollow(&mut self , argx1: i32, argy1: i32, argx2: i32, argy2: i32, color: Color);
 }
 
@@ -695,6 +623,14 @@ impl AddOnsToOrbclient for orbclient::Window{
         //self.sync();
     }
     
+    ///draws closed marquee through points
+    fn poly_marquee(&mut self, points: &[(i32, i32)], color: Color, style: i32) {
+        for (n, &(x1, y1)) in points.iter().enumerate() {
+            let (x2, y2) = points[(n + 1) % points.len()];
+            self.ant_line(x1, y1, x2, y2, color, style);
+        }
+    }
+
     ///draws hollow rectangle
     fn rect_hollow(&mut self , argx1: i32, argy1: i32, argx2: i32, argy2: i32, color: Color) {
         self.line(argx1,argy1,argx2,argy1,color);
@@ -738,29 +674,3 @@ impl AddOnsToOrbclient for orbclient::Window{
     }
 
 }
-
-pub trait AddOnsToOrbclientColor {
-    fn compare(first_color: Color, second_color: Color, thresold: u8) -> bool;
-    fn eq(&self, other: Color) -> bool ;
-}
-
-impl AddOnsToOrbclientColor for orbclient::Color{
-        /// Compare between two colors, return Bool indicating if they are similar considering thresold value
-    fn compare(first_color: Color, second_color: Color, thresold: u8) -> bool {
-        let r_check = first_color.r() - second_color.r();
-        let g_check = first_color.g() - second_color.g();
-        let b_check = first_color.b() - second_color.b();
-        //let a_check = first_color.a() - second_color.a();
-        //Do not check for alpha
-        r_check < thresold && g_check < thresold && b_check < thresold
-    }
-
-    fn eq(&self, other: Color) -> bool {
-        self.r() == other.r() &&
-        self.g() == other.g() &&
-        self.b() == other.b()
-    }
-}
-
-
-
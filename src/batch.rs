@@ -0,0 +1,270 @@
+//command line batch mode: apply Image menu operations to many files without a window
+//
+//    pastel --batch in/*.png --op blur:2 --op resize:256x256 --op grayscale -o out/
+//
+//pastel-batch takes the same arguments and is built on the library alone
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::document::Document;
+use crate::project::{self, Project};
+
+//exit codes
+pub const EXIT_OK: i32 = 0;
+//at least one file could not be loaded, processed or saved
+pub const EXIT_FAILED: i32 = 1;
+//bad command line
+pub const EXIT_USAGE: i32 = 2;
+
+const USAGE: &str = "usage: pastel --batch FILE... [--op OPERATION]... -o OUTPUT
+       pastel-batch FILE... [--op OPERATION]... -o OUTPUT
+
+OUTPUT is a directory (files keep their names) or, for a single input, a file name.
+The output format follows the extension (png, jpg, bmp ...), projects are flattened before the operations.
+
+operations:
+    blur:SIGMA          unsharpen:SIGMA      contrast:VALUE
+    rotate:DEGREES      resize:WIDTHxHEIGHT
+    flip_vertical       flip_horizontal      rotate90
+    brighten            darken               invert
+    grayscale           edge";
+
+///one transformation as understood by Document::transformation
+#[derive(Debug)]
+struct Operation {
+    cod: String,
+    a: f32,
+    b: i32,
+}
+
+impl Operation {
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut parts = text.splitn(2, ':');
+        let cod = parts.next().unwrap_or("");
+        let arg = parts.next();
+        let number = |arg: Option<&str>| -> Result<f32, String> {
+            arg.ok_or(format!("{} needs a value, like {}:2", cod, cod))?
+                .parse::<f32>()
+                .map_err(|_| format!("invalid value for {}: {}", cod, arg.unwrap_or("")))
+        };
+        let (a, b) = match cod {
+            "blur" | "unsharpen" | "contrast" | "rotate" => (number(arg)?, 0),
+            "resize" => {
+                let size = arg.ok_or("resize needs a size, like resize:256x256".to_string())?;
+                let k: Vec<_> = size.split('x').collect();
+                match (k.get(0).and_then(|w| w.parse::<u32>().ok()), k.get(1).and_then(|h| h.parse::<u32>().ok())) {
+                    (Some(width), Some(height)) if k.len() == 2 && width > 0 && height > 0 => (width as f32, height as i32),
+                    _ => return Err(format!("invalid size for resize: {}", size)),
+                }
+            },
+            "flip_vertical" | "flip_horizontal" | "rotate90" | "brighten" | "darken"
+            | "invert" | "grayscale" | "edge" => {
+                if arg.is_some() {
+                    return Err(format!("{} takes no value", cod));
+                }
+                (0.0, 0)
+            },
+            _ => return Err(format!("unknown operation: {}", text)),
+        };
+        Ok(Operation { cod: cod.to_string(), a, b })
+    }
+}
+
+///run batch mode with the command line arguments (program name excluded), returns the exit code
+pub fn run(args: &[String]) -> i32 {
+    let mut inputs = Vec::new();
+    let mut operations = Vec::new();
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--batch" => (),
+            "--op" => match args.next().map(|op| Operation::parse(op)) {
+                Some(Ok(op)) => operations.push(op),
+                Some(Err(e)) => return usage(&e),
+                None => return usage("--op needs an operation"),
+            },
+            "-o" | "--output" => match args.next() {
+                Some(path) => output = Some(PathBuf::from(path)),
+                None => return usage("-o needs an output path"),
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return EXIT_OK;
+            },
+            _ if arg.starts_with('-') => return usage(&format!("unknown option: {}", arg)),
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+
+    let output = match output {
+        Some(output) => output,
+        None => return usage("missing output, use -o"),
+    };
+    if inputs.is_empty() {
+        return usage("no input files");
+    }
+    //several inputs always go to a directory
+    let to_dir = inputs.len() > 1 || output.is_dir() || output.to_string_lossy().ends_with('/');
+    if to_dir && !output.is_dir() {
+        if let Err(e) = fs::create_dir_all(&output) {
+            eprintln!("Cannot create {}: {}", output.display(), e);
+            return EXIT_FAILED;
+        }
+    }
+
+    let mut code = EXIT_OK;
+    for input in &inputs {
+        let target = if to_dir {
+            match input.file_name() {
+                Some(name) => {
+                    let mut target = output.join(name);
+                    //projects are saved flattened
+                    if project::is_project(input) {
+                        target.set_extension("png");
+                    }
+                    target
+                },
+                None => {
+                    eprintln!("{}: not a file", input.display());
+                    code = EXIT_FAILED;
+                    continue;
+                },
+            }
+        } else {
+            output.clone()
+        };
+        match process(input, &target, &operations) {
+            Ok(_) => println!("{} -> {}", input.display(), target.display()),
+            Err(e) => {
+                eprintln!("{}: {}", input.display(), e);
+                code = EXIT_FAILED;
+            },
+        }
+    }
+    code
+}
+
+///load input, apply operations in order and save to target
+fn process(input: &Path, target: &Path, operations: &[Operation]) -> Result<(), String> {
+    let document = load(input)?;
+    for op in operations {
+        if cfg!(feature = "debug"){println!("{}: {:?}", input.display(), op);}
+        document.transformation(&op.cod, op.a, op.b);
+    }
+    let target = target.to_str().ok_or("output path is not valid utf8".to_string())?;
+    document.save(target).map(|_| ()).map_err(|e| format!("cannot save {}: {}", target, e))
+}
+
+fn load(input: &Path) -> Result<Document, String> {
+    if !input.is_file() {
+        return Err("no such file".to_string());
+    }
+    if project::is_project(input) {
+        let project = Project::load(input).map_err(|e| format!("cannot load project: {}", e))?;
+        let document = Document::from_layers(project.layers, project.active_layer, project.mask);
+        document.enable_mask(project.mask_enabled);
+        //operations work on the active layer, the output is flat anyway
+        document.flatten();
+        Ok(document)
+    } else {
+        Document::from_path(input).map_err(|e| format!("cannot load image: {}", e))
+    }
+}
+
+fn usage(error: &str) -> i32 {
+    eprintln!("pastel: {}\n{}", error, USAGE);
+    EXIT_USAGE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use orbclient::Color;
+    use orbimage::Image;
+    use crate::layer::Layer;
+
+    use std::env;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_valid_operations() {
+        let op = Operation::parse("blur:2.5").unwrap();
+        assert_eq!((op.cod.as_str(), op.a, op.b), ("blur", 2.5, 0));
+        let op = Operation::parse("resize:256x128").unwrap();
+        assert_eq!((op.cod.as_str(), op.a, op.b), ("resize", 256.0, 128));
+        let op = Operation::parse("edge").unwrap();
+        assert_eq!((op.cod.as_str(), op.a, op.b), ("edge", 0.0, 0));
+    }
+
+    #[test]
+    fn parse_invalid_operations() {
+        assert!(Operation::parse("resize:0x5").is_err());
+        assert!(Operation::parse("resize:5").is_err());
+        assert!(Operation::parse("resize").is_err());
+        assert!(Operation::parse("blur").is_err());
+        assert!(Operation::parse("blur:much").is_err());
+        assert!(Operation::parse("invert:3").is_err());
+        assert!(Operation::parse("sharpen").is_err());
+        assert!(Operation::parse("").is_err());
+    }
+
+    #[test]
+    fn projects_are_flattened() {
+        let path = env::temp_dir().join(format!("pastel-batch-layers-{}.pastel", std::process::id()));
+        let mut top = Layer::new("Top", Image::from_color(2, 2, Color::rgba(0, 0, 255, 255)));
+        top.opacity = 50;
+        Project {
+            width: 2,
+            height: 2,
+            layers: vec![Layer::new("Background", Image::from_color(2, 2, Color::rgb(255, 0, 0))), top],
+            active_layer: 1,
+            mask: Image::from_color(2, 2, Color::rgba(255, 0, 0, 25)),
+            mask_enabled: false,
+            palette: Vec::new(),
+            tools: Vec::new(),
+            copy_buffer: None,
+        }.save(&path).unwrap();
+        let document = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(document.layers().len(), 1);
+        let color = document.image.borrow().data()[0];
+        assert!(color.r() > 100 && color.b() > 100);
+    }
+
+    #[test]
+    fn exit_codes() {
+        let dir = env::temp_dir().join(format!("pastel-batch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("in.pastel");
+        Project {
+            width: 4,
+            height: 3,
+            layers: vec![Layer::new("Background", Image::from_color(4, 3, Color::rgb(255, 0, 0)))],
+            active_layer: 0,
+            mask: Image::from_color(4, 3, Color::rgba(255, 0, 0, 25)),
+            mask_enabled: false,
+            palette: Vec::new(),
+            tools: Vec::new(),
+            copy_buffer: None,
+        }.save(&input).unwrap();
+        let output = dir.join("out.png");
+        let (input, output) = (input.to_str().unwrap(), output.to_str().unwrap());
+
+        assert_eq!(run(&args(&["--help"])), EXIT_OK);
+        assert_eq!(run(&args(&[input])), EXIT_USAGE);
+        assert_eq!(run(&args(&["-o", output])), EXIT_USAGE);
+        assert_eq!(run(&args(&[input, "--op", "sharpen", "-o", output])), EXIT_USAGE);
+        assert_eq!(run(&args(&[input, "--frobnicate", "-o", output])), EXIT_USAGE);
+        assert_eq!(run(&args(&[dir.join("missing.png").to_str().unwrap(), "-o", output])), EXIT_FAILED);
+        assert_eq!(run(&args(&[input, "--op", "invert", "-o", output])), EXIT_OK);
+        assert!(Path::new(output).is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
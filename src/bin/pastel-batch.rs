@@ -0,0 +1,11 @@
+//batch mode without the orbtk front-end, same arguments as pastel --batch
+
+use std::env;
+use std::process;
+
+use pastel::batch;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    process::exit(batch::run(&args));
+}
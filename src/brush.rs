@@ -0,0 +1,333 @@
+//brush engine: dabs of a procedural tip placed along the stroke at even spacing,
+//built up per stroke so crossing it again does not darken past its opacity;
+//the eraser is the same stroke taking pixels back instead of painting
+
+use orbclient::Color;
+use orbimage::Image;
+
+use std::sync::Arc;
+
+use crate::layer::{self, BlendMode};
+use crate::stamp::{Pick, Stamp};
+
+///what a single dab looks like
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tip {
+    Round,
+    ///turned by the brush angle
+    Square,
+    ///the copy buffer, stamped with its own colors
+    Buffer,
+    ///the stamp of the brush, scaled to its diameter and turned by its angle
+    Stamp,
+}
+
+impl Tip {
+    ///0 round, 1 square, 2 copy buffer, 3 stamp
+    pub fn from_i32(n: i32) -> Self {
+        match n {
+            1 => Tip::Square,
+            2 => Tip::Buffer,
+            3 => Tip::Stamp,
+            _ => Tip::Round,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Brush {
+    pub tip: Tip,
+    ///pixels across
+    pub diameter: f32,
+    ///0.0 fades from the center, 1.0 solid up to an antialiased edge
+    pub hardness: f32,
+    ///distance between dabs, fraction of the diameter
+    pub spacing: f32,
+    ///how much each dab lays down, 0..1; opacity (alpha of the color) caps the whole stroke
+    pub flow: f32,
+    ///radians, clockwise
+    pub angle: f32,
+    ///random changes per dab, 0..1 of the size, of the flow and of half a turn
+    pub size_jitter: f32,
+    pub opacity_jitter: f32,
+    pub angle_jitter: f32,
+    ///random shift of each dab, fraction of the diameter
+    pub scatter: f32,
+    ///image for Tip::Stamp, without one the tip is round
+    pub stamp: Option<Arc<Stamp>>,
+}
+
+impl Brush {
+    ///hard brush with no jitter
+    pub fn new(tip: Tip, diameter: f32) -> Self {
+        Brush {
+            tip,
+            diameter,
+            hardness: 1.0,
+            spacing: 0.25,
+            flow: 1.0,
+            angle: 0.0,
+            size_jitter: 0.0,
+            opacity_jitter: 0.0,
+            angle_jitter: 0.0,
+            scatter: 0.0,
+            stamp: None,
+        }
+    }
+
+    ///how much of dab covers the pixel centered at x,y
+    fn coverage(&self, dab: &Dab, x: f32, y: f32) -> f32 {
+        let (dx, dy) = (x - dab.x, y - dab.y);
+        if let (Tip::Stamp, Some(ref stamp)) = (self.tip, &self.stamp) {
+            return stamp_color(stamp, dab, dx, dy, Color::rgb(0, 0, 0)).a() as f32 / 255.0;
+        }
+        let radius = dab.diameter / 2.0;
+        let distance = match self.tip {
+            Tip::Square => {
+                let (sin, cos) = dab.angle.sin_cos();
+                (dx * cos + dy * sin).abs().max((dy * cos - dx * sin).abs())
+            },
+            _ => (dx * dx + dy * dy).sqrt(),
+        };
+        //half a pixel of soft edge even for the hardest brush
+        let outer = radius + 0.5;
+        let inner = (self.hardness * radius).min(radius - 0.5).max(0.0);
+        if distance <= inner {
+            1.0
+        } else if distance >= outer {
+            0.0
+        } else {
+            let t = (outer - distance) / (outer - inner);
+            t * t * (3.0 - 2.0 * t)
+        }
+    }
+}
+
+///one stamp of the brush
+#[derive(Clone, Copy, Debug)]
+pub struct Dab {
+    pub x: f32,
+    pub y: f32,
+    pub diameter: f32,
+    pub angle: f32,
+    ///0..1
+    pub flow: f32,
+    ///cell of a stamp with more than one
+    pub cell: usize,
+}
+
+///what the eraser takes pixels back to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Erase {
+    ///background color on a layer without transparency, else transparency
+    Auto,
+    Transparent,
+    ///what clearing leaves
+    Background,
+    ///pixels as they were this many undo steps ago
+    History(usize),
+}
+
+impl Erase {
+    ///0 auto, 1 transparent, 2 background, 3 history steps back
+    pub fn from_i32(n: i32, steps: usize) -> Self {
+        match n {
+            1 => Erase::Transparent,
+            2 => Erase::Background,
+            3 => Erase::History(steps),
+            _ => Erase::Auto,
+        }
+    }
+}
+
+//what a stroke lays down
+enum Ink {
+    Color(Color),
+    //up to opacity towards the pixels of an image, transparency without one
+    Erase(u8, Option<Image>),
+}
+
+///a brush stroke in progress on one layer
+pub struct Stroke {
+    brush: Brush,
+    ink: Ink,
+    last: (f32, f32),
+    //distance gone since the last dab
+    gone: f32,
+    //radians the stroke last went towards and dabs so far, to pick stamp cells
+    direction: f32,
+    count: usize,
+    seed: u32,
+    //layer before the stroke, how much the stroke covers each pixel and how much may be painted
+    base: Image,
+    covered: Vec<u16>,
+    clip: Option<Vec<u8>>,
+}
+
+impl Stroke {
+    ///start painting color on layer with brush at x,y; clip is how much each pixel can be
+    ///painted, None for all of it; the first dab is returned, not painted
+    pub fn new(brush: Brush, color: Color, layer: &Image, clip: Option<Vec<u8>>, x: f32, y: f32) -> (Self, Dab) {
+        Stroke::start(brush, Ink::Color(color), layer, clip, x, y)
+    }
+
+    ///like new, but taking the pixels of layer back up to opacity towards those of
+    ///to (the same size), or towards transparency without it; the tip is only a shape
+    pub fn eraser(brush: Brush, opacity: u8, layer: &Image, to: Option<Image>, clip: Option<Vec<u8>>, x: f32, y: f32) -> (Self, Dab) {
+        Stroke::start(brush, Ink::Erase(opacity, to), layer, clip, x, y)
+    }
+
+    fn start(brush: Brush, ink: Ink, layer: &Image, clip: Option<Vec<u8>>, x: f32, y: f32) -> (Self, Dab) {
+        let mut stroke = Stroke {
+            brush,
+            ink,
+            last: (x, y),
+            gone: 0.0,
+            direction: 0.0,
+            count: 0,
+            seed: (0x9e37_79b9 ^ x.to_bits() ^ y.to_bits().rotate_left(16)) | 1,
+            base: layer.clone(),
+            covered: vec![0; (layer.width() * layer.height()) as usize],
+            clip,
+        };
+        let dab = stroke.dab(x, y);
+        (stroke, dab)
+    }
+
+    pub fn tip(&self) -> Tip {
+        self.brush.tip
+    }
+
+    pub fn erasing(&self) -> bool {
+        match self.ink {
+            Ink::Erase(..) => true,
+            Ink::Color(_) => false,
+        }
+    }
+
+    ///move the stroke on to x,y, giving the dabs along the way
+    pub fn to(&mut self, x: f32, y: f32) -> Vec<Dab> {
+        let (dx, dy) = (x - self.last.0, y - self.last.1);
+        let length = (dx * dx + dy * dy).sqrt();
+        let step = (self.brush.spacing * self.brush.diameter).max(0.5);
+        if length > 0.0 {
+            self.direction = dy.atan2(dx);
+        }
+        let mut dabs = Vec::new();
+        let mut along = step - self.gone;
+        while along <= length {
+            let k = along / length;
+            dabs.push(self.dab(self.last.0 + dx * k, self.last.1 + dy * k));
+            along += step;
+        }
+        self.gone = length - (along - step);
+        self.last = (x, y);
+        dabs
+    }
+
+    ///put dab on layer, it must be the layer the stroke started on;
+    ///colour stamps are laid over what is there, dab after dab
+    pub fn paint(&mut self, layer: &mut Image, dab: &Dab) {
+        let width = layer.width() as i32;
+        let height = layer.height() as i32;
+        //a turned square or stamp reaches further out
+        let reach = (dab.diameter / 2.0 * if self.brush.tip == Tip::Round { 1.0 } else { 1.5 } + 1.0).ceil() as i32;
+        let (cx, cy) = (dab.x.floor() as i32, dab.y.floor() as i32);
+        let colored = match (self.brush.tip, &self.brush.stamp, &self.ink) {
+            (Tip::Stamp, Some(ref stamp), Ink::Color(color)) if !stamp.greyscale => Some((stamp.clone(), *color)),
+            _ => None,
+        };
+        let data = layer.data_mut();
+        for y in (cy - reach).max(0)..(cy + reach + 1).min(height) {
+            for x in (cx - reach).max(0)..(cx + reach + 1).min(width) {
+                let i = (y * width + x) as usize;
+                if let Some((ref stamp, color)) = colored {
+                    let c = stamp_color(stamp, dab, x as f32 + 0.5 - dab.x, y as f32 + 0.5 - dab.y, color);
+                    let open = self.clip.as_ref().map_or(1.0, |clip| clip[i] as f32 / 255.0);
+                    let alpha = (c.a() as f32 * dab.flow * color.a() as f32 / 255.0 * open + 0.5) as u8;
+                    if alpha > 0 {
+                        data[i] = layer::blend(BlendMode::Normal, data[i], Color::rgba(c.r(), c.g(), c.b(), alpha), 100);
+                    }
+                    continue;
+                }
+                let c = self.brush.coverage(dab, x as f32 + 0.5, y as f32 + 0.5) * dab.flow;
+                if c <= 0.0 {
+                    continue;
+                }
+                let old = self.covered[i] as f32 / 65535.0;
+                let covered = old + (1.0 - old) * c;
+                self.covered[i] = (covered * 65535.0 + 0.5) as u16;
+                let open = self.clip.as_ref().map_or(1.0, |clip| clip[i] as f32 / 255.0);
+                let base = self.base.data()[i];
+                data[i] = match self.ink {
+                    Ink::Color(color) => {
+                        let alpha = (color.a() as f32 * covered * open + 0.5) as u8;
+                        layer::blend(BlendMode::Normal, base, Color::rgba(color.r(), color.g(), color.b(), alpha), 100)
+                    },
+                    Ink::Erase(opacity, ref to) => {
+                        //transparent pixels take the color of the other side, so edges do not darken
+                        let mut target = to.as_ref().map_or(Color::rgba(0, 0, 0, 0), |to| to.data()[i]);
+                        if target.a() == 0 {
+                            target = Color::rgba(base.r(), base.g(), base.b(), 0);
+                        }
+                        let from = if base.a() == 0 { Color::rgba(target.r(), target.g(), target.b(), 0) } else { base };
+                        layer::mix(from, target, (opacity as f32 * covered * open + 0.5) as u8)
+                    },
+                };
+            }
+        }
+    }
+
+    ///dab at x,y with the jitter of the brush
+    fn dab(&mut self, x: f32, y: f32) -> Dab {
+        let brush = self.brush.clone();
+        let mut dab = Dab { x, y, diameter: brush.diameter, angle: brush.angle, flow: brush.flow, cell: 0 };
+        if let Some(ref stamp) = brush.stamp {
+            let cells = stamp.cells.len();
+            dab.cell = match stamp.pick {
+                Pick::Incremental => self.count % cells,
+                Pick::Random => (self.random() * cells as f32) as usize % cells,
+                Pick::Angular => {
+                    let turn = self.direction / (2.0 * std::f32::consts::PI);
+                    ((turn - turn.floor()) * cells as f32 + 0.5) as usize % cells
+                },
+            };
+        }
+        self.count += 1;
+        if brush.size_jitter > 0.0 {
+            dab.diameter *= 1.0 - brush.size_jitter * self.random();
+        }
+        if brush.opacity_jitter > 0.0 {
+            dab.flow *= 1.0 - brush.opacity_jitter * self.random();
+        }
+        if brush.angle_jitter > 0.0 {
+            dab.angle += brush.angle_jitter * std::f32::consts::PI * (2.0 * self.random() - 1.0);
+        }
+        if brush.scatter > 0.0 {
+            let spread = brush.scatter * brush.diameter;
+            dab.x += spread * (2.0 * self.random() - 1.0);
+            dab.y += spread * (2.0 * self.random() - 1.0);
+        }
+        dab.diameter = dab.diameter.max(1.0);
+        dab
+    }
+
+    ///0..1, xorshift
+    fn random(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        (self.seed >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+///color of the stamp under dab at dx,dy from its center, the larger side of the stamp
+///as long as the dab diameter
+fn stamp_color(stamp: &Stamp, dab: &Dab, dx: f32, dy: f32, tint: Color) -> Color {
+    let (w, h) = stamp.size();
+    let scale = w.max(h) as f32 / dab.diameter;
+    let (sin, cos) = dab.angle.sin_cos();
+    let u = (dx * cos + dy * sin) * scale + w as f32 / 2.0 - 0.5;
+    let v = (dy * cos - dx * sin) * scale + h as f32 / 2.0 - 0.5;
+    stamp.color(dab.cell, u, v, tint)
+}
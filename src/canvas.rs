@@ -1,18 +1,8 @@
-//canvas widget based on image widget
+//canvas widget based on image widget, the document itself lives in the pastel library
 
-extern crate rusttype;
-//extern crate resize;
+use orbclient::{Color, Renderer};
 
-use self::rusttype::{FontCollection, Scale, point};
-//use self::resize::Filter;
-
-use image;
-use image::{GenericImage, ImageBuffer, Pixel};
-
-use orbclient::{Color, Renderer, Mode};
-
-
-use orbimage::{self, Image, ResizeType};
+use orbimage::{Image, ResizeType};
 
 use orbtk::Window;
 use orbtk::event::Event;
@@ -25,369 +15,88 @@ use orbtk::theme::{Theme};
 use std::cell::{Cell, RefCell};
 use std::path::Path;
 use std::sync::Arc;
-use std::slice;
-use std::io::Error;
-use std::f32::consts::PI;
-use std::io::prelude::*;
-use std::fs::File;
 use std::cmp;
 use std::ops::Deref;
 
-use crate::AddOnsToOrbimage;
-//use addons::AddOnsToRenderer;
-//use addons::AddOnsToOrbclientColor;
+use pastel::document::{Document, Done, Job};
+use pastel::brush::{Brush, Erase};
+use pastel::gradient::Gradient;
+use pastel::layer::Layer;
+use pastel::region::ColorMatch;
+use pastel::resample::{Filter, Fit, Interpolation};
+use pastel::selection::{Combine, Refine, Selection};
 
-use crate::UNDODEPTH;
-use crate::CANVASOFFSET;
-use crate::ZOOMSTEP;
+use crate::addons::InteractOrbimage;
 
-static SMOOTH_BRUSH: &[u8; 4242] = include_bytes!("../res/smooth_circle_black.png");
+use crate::ZOOMSTEP;
+use crate::ZOOMMIN;
+use crate::ZOOMMAX;
 
 pub struct Canvas {
+    document: Document,
     pub rect: Cell<Rect>,
+    //scroll offset of the zoomed document, in screen pixels
     view: Cell<Rect>,
-    pub image: RefCell<Image>,
-    undo_image: RefCell<Vec<Image>>,
-    undo_pos: Cell<usize>,
-    mask: RefCell<Image>,
-    mask_flag: Cell<bool>,
-    mask_enabled: Cell<bool>,
-    mask_changed: Cell<bool>,
-    mask_return: Cell<bool>,
-    pub copy_buffer: RefCell<Image>,
+    //max size of the widget on screen
+    viewport: Cell<Option<(u32, u32)>>,
+    //composited layers, only the visible part is kept up to date
+    composite: RefCell<Vec<Color>>,
     click_callback: RefCell<Option<Arc<dyn Fn(&Canvas, Point)>>>,
     right_click_callback: RefCell<Option<Arc<dyn Fn(&Canvas, Point)>>>,
     clear_click_callback: RefCell<Option<Arc<dyn Fn(&Canvas, Point)>>>,
     shortcut_callback: RefCell<Option<Arc<dyn Fn(&Canvas, char)>>>,
+    view_callback: RefCell<Option<Arc<dyn Fn(&Canvas)>>>,
     pub zoom_factor: Cell<f32>,
-    brush: RefCell<Image>,
-    old_color: Cell<Color>,
-    old_size: Cell<u32>,
+}
+
+//drawing, layers, mask and undo are document operations
+impl Deref for Canvas {
+    type Target = Document;
+
+    fn deref(&self) -> &Document {
+        &self.document
+    }
 }
 
 impl Canvas {
     pub fn new(width: u32, height: u32) -> Arc<Self> {
-        Self::from_image(Image::new(width, height))
+        Self::from_document(Document::new(width, height))
     }
 
     pub fn from_color(width: u32, height: u32, color: Color) -> Arc<Self> {
-        Self::from_image(Image::from_color(width, height, color))
+        Self::from_document(Document::from_color(width, height, color))
     }
 
     pub fn from_image(image: Image) -> Arc<Self> {
-        Arc::new(Canvas {
-            rect: Cell::new(Rect::new(0, 0, image.width(), image.height())),
-            view: Cell::new(Rect::new(0, 0, image.width(), image.height())),
-            undo_image: RefCell::new(vec!(Image::new(image.width(),image.height()))),
-            undo_pos: Cell::new(0),
-            mask: RefCell::new(Image::from_color(image.width(), image.height(), Color::rgba(255,0,0,25))),
-            mask_flag: Cell::new(false),
-            mask_enabled: Cell::new(false),
-            mask_changed: Cell::new(false),
-            mask_return: Cell::new(false),
-            image: RefCell::new(image),
-            copy_buffer: RefCell::new(Image::new(0,0)),
-            click_callback: RefCell::new(None),
-            right_click_callback: RefCell::new(None),
-            clear_click_callback: RefCell::new(None),
-            shortcut_callback:RefCell::new(None),
-            zoom_factor: Cell::new(1.0),
-            brush: RefCell::new(Image::new(0,0)),
-            old_color: Cell::new(Color::rgb(0,0,0)),
-            old_size: Cell::new(0),
-        })
+        Self::from_document(Document::from_image(image))
     }
 
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Arc<Self>, String> {
-        Ok(Self::from_image(Image::from_path(path)?))
-    }
-    
-    pub fn save(&self, filename: &str) -> Result <i32, Error>{
-        let width = self.width() as u32;
-        let height = self.height() as u32;
-
-        //get image data in form of [Color] slice
-        let image_data = self.image.clone().into_inner().into_data();
-
-        // convert u32 values to 4 * u8 (r g b a) values
-        let image_buffer = unsafe {
-            slice::from_raw_parts(image_data.as_ptr() as *const u8, 4 * image_data.len())
-        };
-        //let () = image_buffer;
-        //To save corectly the image with image::save_buffer
-        // we have to take care of correct byte order (rgba <-> abgr)
-        let mut new_image_buffer = Vec::new();
-        let mut i = 0;
-        while i <= image_buffer.len() - 4 {
-            new_image_buffer.push(image_buffer[i + 2]);
-            new_image_buffer.push(image_buffer[i + 1]);
-            new_image_buffer.push(image_buffer[i]);
-            new_image_buffer.push(image_buffer[i + 3]);
-            i += 4;
-        }
-
-        if cfg!(feature = "debug"){
-            println!("Saving {}", &filename);
-            println!("x{} y{} len={}", width, height, image_data.len());
-        }
-        
-        match image::save_buffer(&Path::new(&filename),
-                           &new_image_buffer,
-                           width,
-                           height,
-                           image::RGBA(8)){
-                Ok(_)   => {
-                            if cfg!(feature = "debug"){println!("Saved");}
-                            Ok(0)
-                            },               
-                Err(e) => {
-                            if cfg!(feature = "debug"){println!("Error: {}",e);}
-                            Err(e)
-                            },
-        }
+        Ok(Self::from_document(Document::from_path(path)?))
     }
 
-    pub fn clear(&self){
-        if self.mask_flag.get(){
-            let mut image = self.mask.borrow_mut();
-            image.set(Color::rgba(255,0,0,25));
-        }else{
-            //first prepare for undo 
-            self.undo_save();
-            let mut image = self.image.borrow_mut();
-            image.set(Color::rgba(255, 255, 255,255));
-        }
-    }
-    
-    pub fn height(&self) -> u32 {
-        self.image.borrow().height()
+    ///new canvas from a stack of layers, all of the same size
+    pub fn from_layers(layers: Vec<Layer>, active: usize, mask: Image) -> Arc<Self> {
+        Self::from_document(Document::from_layers(layers, active, mask))
     }
-    
-    pub fn width(&self) -> u32 {
-        self.image.borrow().width()
-    }
-
-    ///apply some transformations to entire canvas or mask
-    pub fn transformation(&self, cod: &str, a: f32, b:i32){
-        let mut width = self.rect.get().width as u32;
-        let mut height = self.rect.get().height as u32;
-        let image_data;
-        let new_slice;
-        let mut image;
-        if self.mask_flag.get() {
-            //get image data in form of [Color] slice
-            image_data = self.mask.clone().into_inner().into_data();
-            new_slice = self.trans_from_slice(&image_data,width,height,cod,a,b);
-            image = self.mask.borrow_mut();
-        }else{
-            //first prepare for undo 
-            self.undo_save();
-            //get image data in form of [Color] slice
-            image_data = self.image.clone().into_inner().into_data();
-            new_slice = self.trans_from_slice(&image_data,width,height,cod,a,b);
-            image = self.image.borrow_mut();
-        }
-        if cod == "resize" {
-            width = a as u32;
-            height = b as u32;
-            self.rect.set(Rect::new(0,CANVASOFFSET,width,height));
-        }
-        image.clear();
-        if cod == "rotate90" {
-            image.image(0, 0, height, width, &new_slice[..]);
-        }else{
-            image.image(0, 0, width, height, &new_slice[..]);
-        }
-    }
-
-    ///apply some transformations to canvas selection (in place)
-    pub fn trans_selection(&self, selection: Rect, cod: &str, a: f32, b:i32){
-        //first prepare for undo 
-        self.undo_save();
-        
-        let mut width = selection.width;
-        let mut height = selection.height;
-        let x = selection.x;
-        let y = selection.y;
-        let mut image = if self.mask_flag.get() {self.mask.borrow_mut()}else{self.image.borrow_mut()};
-        let image_selection = image.copy_selection(x, y, width, height);
-        let new_image = self.trans_image(image_selection, cod,a,b);
-        //clear only under selection
-        if self.mask_flag.get(){
-            image.rect(x,y,width,height,Color::rgba(255,255,255,25));
-            image.rect(x,y,width,height,Color::rgba(0,0,0,25));
-            }else{image.rect(x,y,width,height,Color::rgba(255,255,255,255))}; 
-        
-        if cod == "resize" {
-            width = a as u32;
-            height = b as u32;
-        }
-        if cod == "rotate90" {
-            image.image(x, y, height, width, &new_image[..]);
-        }else{
-            image.image(x,y, width, height, &new_image[..]);
-        }
-    }
-
-    /// apply some transformation to an image 
-    pub fn trans_image (&self, image_selection: Image, cod: &str, a: f32, b: i32) -> Vec<Color> {
-        let width = image_selection.width() as u32;
-        let height = image_selection.height() as u32;
-        //get image data in form of [Color] slice
-        let image_data = image_selection.into_data();
-        //apply transformation to slice
-        self.trans_from_slice(&image_data,width,height,cod,a,b)
-        //here only return new_slice instead of render because of borrowing issue 
-    }
-
-    /// apply some transformation to an image slice
-    fn trans_from_slice (&self, image_data: &[Color], width: u32, height: u32, cod: &str, a: f32, b:i32) -> Vec<Color> {
-        let image_buffer = unsafe {
-            slice::from_raw_parts(image_data.as_ptr() as *const u8, 4 * image_data.len())
-        };
-        let mut imgbuf : image::ImageBuffer<image::Rgba<u8>, _> = image::ImageBuffer::from_raw(width as u32, height as u32, image_buffer.to_vec()).unwrap();
-        let vec_image_buffer:Vec<u8> = image::ImageBuffer::into_raw ( 
-            match cod {
-            
-             "blur"            => image::imageops::blur(&imgbuf,a),
-             "unsharpen"       => image::imageops::unsharpen(&imgbuf,a,10),
-             "flip_vertical"   => image::imageops::flip_vertical(&imgbuf),
-             "flip_horizontal" => image::imageops::flip_horizontal(&imgbuf),
-             "rotate90"        => image::imageops::rotate90(&imgbuf),
-             "rotate"          => self.rotate_center(&imgbuf, a as f32 * PI/180.0),
-             "brighten"        => image::imageops::colorops::brighten(&imgbuf, 10),
-             "darken"          => image::imageops::colorops::brighten(&imgbuf, -10),
-             "contrast"        => image::imageops::colorops::contrast(&imgbuf, a),
-             "invert"          => {image::imageops::colorops::invert(&mut imgbuf);
-                                    imgbuf},
-             "grayscale"       => self.gray2rgba(image::imageops::colorops::grayscale(&imgbuf),
-                                            1.2,1.2,1.2),
-             "resize"          => { 
-                                    self.image.borrow_mut().clear();
-                                    image::imageops::resize(&imgbuf,a as u32,b as u32,image::FilterType::Nearest)
-                                    },
-             "edge"            => {let kernel = [-1.0f32, -1.0, -1.0,
-                                                 -1.0, 8.0, -1.0,
-                                                 -1.0, -1.0, -1.0];
-                                image::imageops::filter3x3(&imgbuf,&kernel);
-                                imgbuf
-                                },
-                             _ => imgbuf,
-         });
-        
-        //convert rgba u8 image buffer back into Color slice
-        let flg = self.mask_flag.get();
-        let mut i = 0 ;
-        let mut r: u8 ;
-        let mut g: u8 ;
-        let mut b: u8 ;
-        let mut a: u8 ;
-        let mut new_slice = Vec::new();
-        while i <= vec_image_buffer.len() - 4 {
-            r = vec_image_buffer[i];
-            g = vec_image_buffer[i+1];
-            b = vec_image_buffer[i+2];
-            a = vec_image_buffer[i+3];
-            new_slice.push(Color::rgba(b, g, r, a)); //taking care of byte order
-            i += 4;
-        }
-        new_slice
-    }
-
-    /// convert grayscale format image to rgba format
-    fn gray2rgba (&self, 
-                    grayimage: image::ImageBuffer<image::Luma<u8>, Vec<u8>>,
-                    r_factor : f32,
-                    g_factor : f32,
-                    b_factor : f32
-                    )
-                    -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
-        let mut r: u8 ;
-        let mut g: u8 ;
-        let mut b: u8 ;
-        let mut a: u8 ;
-        let mut new_buffer = Vec::new();
-        let width = grayimage.width();
-        let height = grayimage.height();
-
-        for luma in image::ImageBuffer::into_raw (grayimage) {
-            if luma == 255 {
-                r=255;
-                g=255;
-                b=255;
-            }else{
-            r = (luma as f32 / r_factor) as u8;
-            g = (luma as f32 / g_factor) as u8;
-            b = (luma as f32 / b_factor) as u8;
-            }
-            a = 255;
-            new_buffer.push(b);
-            new_buffer.push(g);
-            new_buffer.push(r);
-            new_buffer.push(a);
-        }
-        let imgbuf : image::ImageBuffer<image::Rgba<u8>, _> = image::ImageBuffer::from_raw(width as u32, height as u32, new_buffer).unwrap();
-            imgbuf
-    }
-
-    ///rotate image about center
-    fn rotate_center<I: GenericImage + 'static>(&self, image: &I, theta: f32) 
-        -> ImageBuffer<I::Pixel, Vec<<I::Pixel as Pixel>::Subpixel>>
-        where I::Pixel: 'static,
-          <I::Pixel as Pixel>::Subpixel: 'static  {
-        let (width, height) = image.dimensions();
-        let center = ((width/2) as f32, (height/2) as f32);
-        self.rotate_nearest(image, center, theta)
-    }
-    
-    ///rotate image using nearest interpolation
-    fn rotate_nearest<I: GenericImage + 'static>(&self, image: &I, center: (f32, f32), theta: f32) 
-        -> ImageBuffer<I::Pixel, Vec<<I::Pixel as Pixel>::Subpixel>>
-        where I::Pixel: 'static,
-          <I::Pixel as Pixel>::Subpixel: 'static  {
-        let default :<I as image::GenericImageView>::Pixel = unsafe { image.unsafe_get_pixel(0,0) };
-        let (width, height) = image.dimensions();
-        //#TODO calculate new dimensions to fit rotated image; change canvas dimensions too! 
-        let mut out = ImageBuffer::new(width, height);
-
-        let cos_theta = theta.cos();
-        let sin_theta = theta.sin();
-        let center_x = center.0;
-        let center_y = center.1;
-
-        for y in 0..height {
-            let dy = y as f32 - center_y;
-            let mut px = center_x + sin_theta * dy - cos_theta * center_x;
-            let mut py = center_y + cos_theta * dy + sin_theta * center_x;
-
-            for x in 0..width {
-
-                unsafe {
-                    let pix = self.nearest(image, px, py, default);
-                    out.unsafe_put_pixel(x, y, pix);
-                }
-
-                px += cos_theta;
-                py -= sin_theta;
-            }
-        }
 
-        out
+    pub fn from_document(document: Document) -> Arc<Self> {
+        let (width, height) = (document.width(), document.height());
+        Arc::new(Canvas {
+            document,
+            rect: Cell::new(Rect::new(0, 0, width, height)),
+            view: Cell::new(Rect::new(0, 0, width, height)),
+            viewport: Cell::new(None),
+            composite: RefCell::new(Vec::new()),
+            click_callback: RefCell::new(None),
+            right_click_callback: RefCell::new(None),
+            clear_click_callback: RefCell::new(None),
+            shortcut_callback:RefCell::new(None),
+            view_callback: RefCell::new(None),
+            zoom_factor: Cell::new(1.0),
+        })
     }
 
-    fn nearest<P: Pixel + 'static, I: GenericImage + 'static>(&self, image: &I, x: f32, y: f32, default: P)
-        -> <I as image::GenericImageView>::Pixel {
-        let rx = x.round();
-        let ry = y.round();
-
-        // default if out of bound
-        let (width, height) = image.dimensions();
-        if rx < 0f32 || rx >= width as f32 || ry < 0f32 || ry >= height as f32 {
-            unsafe { image.unsafe_get_pixel(0,0) }  //#FIXME default pixel has to be transparent !
-        } else {
-           unsafe { image.unsafe_get_pixel(rx as u32, ry as u32) }
-        }
-    }
 
     pub fn on_right_click<T: Fn(&Self, Point) + 'static>(&self, func: T) -> &Self {
         *self.right_click_callback.borrow_mut() = Some(Arc::new(func));
@@ -421,252 +130,138 @@ impl Canvas {
             shortcut_callback(self, c);
         }
     }
-    
-    pub fn zoom_in(&self) {
-        let mut image = self.image.borrow_mut();
-        
-        //define custom filter
-        //fn kernel(x: f32) ->f32 { f32::max(1.0 - x.abs(),0.0) }
-        //let filter = Filter::new(Box::new(kernel), 1.0);
-        
-        //limits zoom in to factor 3
-        if self.zoom_factor.get() < 2.0 {
-            let zoomed = image.resize((image.width() as f32 * (1.0 + ZOOMSTEP )) as u32,
-                                      (image.height() as f32* (1.0 + ZOOMSTEP )) as u32,
-                                      ResizeType::Mitchell).unwrap();  //Mitchell
-            *image = zoomed;
-            self.rect.set(Rect::new(0,CANVASOFFSET,image.width(),image.height()));
-            self.view.set(Rect::new(0,0,image.width(),image.height()));
-            self.zoom_factor.set(self.zoom_factor.get() + ZOOMSTEP);
-        }
-    }
+    ///called every time zoom or pan change what part of the document is shown
 
-    pub fn zoom_out(&self) {
-        let mut image = self.image.borrow_mut();
-        //define custom filter
-        //fn kernel(x: f32) ->f32 { f32::max(1.0 - x.abs(),0.0) }
-        //let filter = Filter::new(Box::new(kernel), 1.0);
-
-        //limits zoom out to factor 1.0
-        if self.zoom_factor.get() > 1.0 {
-            let zoomed = image.resize((image.width() as f32 / (1.0 + ZOOMSTEP)) as u32,
-                                      (image.height() as f32 / (1.0 + ZOOMSTEP)) as u32,
-                                      //ResizeType::Custom(filter)).unwrap();  //Triangle
-                                      ResizeType::Lanczos3).unwrap();
-            *image = zoomed;
-            self.rect.set(Rect::new(0,CANVASOFFSET,image.width(),image.height()));
-            self.view.set(Rect::new(0,0,image.width(),image.height()));
-            self.zoom_factor.set(self.zoom_factor.get() - ZOOMSTEP);
-        }
+    pub fn on_view_change<T: Fn(&Self) + 'static>(&self, func: T) -> &Self {
+        *self.view_callback.borrow_mut() = Some(Arc::new(func));
+        self
     }
 
-    pub fn pan(&self,pan_x: i32, pan_y: i32) {
-        let image = self.image.borrow_mut();
-        let mut pan_x = self.view.get().x - pan_x;
-        let mut pan_y = self.view.get().y - pan_y;
-        if pan_y < 0 { pan_y = 0;}
-        if pan_x < 0 { pan_x = 0;}
-        self.view.set(Rect::new(pan_x, pan_y, image.width(),image.height()));
-    }
-
-    /// save image state to undo stack 
-    pub fn undo_save(&self) {
-        let image = self.image.borrow_mut();
-        self.undo_image.borrow_mut().push(image.clone());
-        // prevents undo stack to grow too much!!
-        self.undo_pos.set(self.undo_pos.get()+1);
-        if self.undo_image.borrow_mut().len() > UNDODEPTH {
-            self.undo_image.borrow_mut().remove(0);
-            self.undo_pos.set(self.undo_pos.get()-1);
+    pub fn emit_view_change(&self) {
+        if let Some(ref view_callback) = *self.view_callback.borrow() {
+            view_callback(self);
         }
     }
 
-    pub fn mode_set(&self, mode: Mode) {
-        self.image.borrow().mode().set(mode);
-        self.mask.borrow().mode().set(mode);
+    ///map a point from canvas widget coordinates to document (image) coordinates
+    pub fn to_document(&self, point: Point) -> Point {
+        let zoom = self.zoom_factor.get();
+        let view = self.view.get();
+        Point::new(((point.x + view.x) as f32 / zoom).floor() as i32,
+                   ((point.y + view.y) as f32 / zoom).floor() as i32)
     }
 
-    /// retrieve image from undo stack
-    pub fn undo (&self) {
-        let l = self.undo_image.borrow_mut().len();
-        let i = self.undo_pos.get();
-        if l-1 == i {self.undo_save();}
-        if i > 1 {
-            let mut image = self.image.borrow_mut();
-            *image = self.undo_image.borrow_mut()[i].clone();  //l-1
-            self.undo_pos.set(i-1);
-            if l-i > 1 {self.undo_image.borrow_mut().pop();}
-        }
+    ///map a point from document coordinates to canvas widget coordinates
+    pub fn to_screen(&self, point: Point) -> Point {
+        let zoom = self.zoom_factor.get();
+        let view = self.view.get();
+        Point::new((point.x as f32 * zoom) as i32 - view.x,
+                   (point.y as f32 * zoom) as i32 - view.y)
     }
-    
-    pub fn redo (&self) {
-        let undo_image = self.undo_image.borrow_mut();
-        let l = undo_image.len();
-        let i = self.undo_pos.get() + 2;
-        //println!("redo{}",i);
-        if i < l {
-            let mut image = self.image.borrow_mut();
-            *image = undo_image[i].clone();
-            self.undo_pos.set(i);
-            
-        }
-    }
-    
-    pub fn magicwand (& self, x: i32 , y: i32){
-        //take care of panning
-        let Rect {x: panx, y: pany, ..} = self.view.get();
-        let x = x + panx;
-        let y =  y + pany;
-        let rgba = self.pixcol(x,y);
-        self.magicwand_scanline(x,y,Color::rgba(0,0,0,255).data, rgba.data);
-        self.mask_enabled.set(true); //enable and redraw mask
-        self.mask_changed.set(true);
-    }
-
-    fn magicwand_scanline( &self, x:i32, y:i32, new_color:u32, old_color:u32) {
-        
-        if old_color == new_color {
-            return;
-        }
-        if self.pixcol(x,y).data  != old_color  {
-            return;
-        }
 
-        let w = self.width() as i32;
-        let h = self.height() as i32;
+    ///to_document without rounding to whole pixels
+    pub fn to_document_f32(&self, point: Point) -> (f32, f32) {
+        let zoom = self.zoom_factor.get();
+        let view = self.view.get();
+        ((point.x + view.x) as f32 / zoom, (point.y + view.y) as f32 / zoom)
+    }
 
-        //draw current scanline from start position to the right
-        let mut x1 = x;
+    ///to_screen of a point between document pixels
+    pub fn to_screen_f32(&self, x: f32, y: f32) -> Point {
+        let zoom = self.zoom_factor.get();
+        let view = self.view.get();
+        Point::new((x * zoom).round() as i32 - view.x, (y * zoom).round() as i32 - view.y)
+    }
 
-        while x1 < w && self.pixcol(x1,y).data  == old_color  {
-            self.mask.borrow_mut().pixel(x1,y,Color{data:new_color});
-            x1 +=1;
-        }
-        //get resulted color because of transparency and use this for comparison 
-        let res_color = self.pixcol(x,y).data;
-        
-        //draw current scanline from start position to the left
-        x1 = x -1;
-        
-        while x1 >= 0 && self.pixcol(x1,y).data  == old_color  {
-            self.mask.borrow_mut().pixel(x1,y,Color{data:new_color});
-            x1 += -1;
-          }
-        
-        //test for new scanlines above
-        x1 = x;
-        
-        while x1 < w && self.pixcol(x1,y).data  == res_color  { 
-            if y > 0 && self.pixcol(x1,y-1).data  == old_color  {
-              self.magicwand_scanline(x1, y - 1, new_color, old_color);
-            }
-            x1 += 1;
-          }
-        x1 = x - 1;
-        while x1 >= 0 && self.pixcol(x1,y).data == res_color {
-            if y > 0 && self.pixcol(x1,y - 1).data  == old_color  {
-              self.magicwand_scanline(x1, y - 1, new_color, old_color);
-            }
-            x1 += -1;
-          }
-         
-         //test for new scanlines below
-        x1 = x;
-        while x1 < w && self.pixcol(x1,y).data == res_color  {
-            //println!("Test below {} {} ", self.pixcol(x1,y).data,old_color);
-            if y < (h - 1) && self.pixcol(x1,y + 1).data == old_color {
-                self.magicwand_scanline(x1, y + 1, new_color, old_color);
-            }
-            x1 +=1;
-        }
-        x1 = x - 1;
-        while x1 >= 0 && self.pixcol(x1,y).data == res_color {
-            if y < (h - 1) && self.pixcol(x1,y + 1).data == old_color {
-                self.magicwand_scanline(x1, y + 1, new_color, old_color);
-            }
-            x1 += -1;
-        }
+    ///map a rectangle from canvas widget coordinates to document coordinates
+    pub fn to_document_rect(&self, rect: Rect) -> Rect {
+        let p1 = self.to_document(Point::new(rect.x, rect.y));
+        let p2 = self.to_document(Point::new(rect.x + rect.width as i32, rect.y + rect.height as i32));
+        Rect::new(p1.x, p1.y, (p2.x - p1.x) as u32, (p2.y - p1.y) as u32)
     }
 
+    ///map a rectangle from document coordinates to canvas widget coordinates
+    pub fn to_screen_rect(&self, rect: Rect) -> Rect {
+        let p1 = self.to_screen(Point::new(rect.x, rect.y));
+        let p2 = self.to_screen(Point::new(rect.x + rect.width as i32, rect.y + rect.height as i32));
+        Rect::new(p1.x, p1.y, (p2.x - p1.x) as u32, (p2.y - p1.y) as u32)
+    }
 
+    ///limit the size of the widget on screen, so it does not cover other widgets
+    pub fn viewport(&self, width: u32, height: u32) {
+        self.viewport.set(Some((width, height)));
+        self.update_rect();
+    }
 
-   ///wrapper for filling an image within a canvas with pan support
-    pub fn fill (&self, x: i32 , y: i32, color: Color){
-        self.undo_save();  //save state for undo
-        let mut image = if self.mask_flag.get() { self.mask.borrow_mut()} else {self.image.borrow_mut()};
-        //take care of panning
-        let Rect {x: panx, y: pany, ..} = self.view.get();
-        
-        image.fill(x + panx, y + pany, color);
-    }
-
-    ///paste an image into current canvas starting at x,y with transparency , mask and view support
-    #[inline]
-    pub fn paste_image (&self, x: i32, y:i32, opacity: u8, buffer: Image, ){
-        let w = buffer.width() as i32;
-        let h = buffer.height() as i32;
-        let xc=x-w/2; //center buffer at cursor 
-        let yc=y-h/2;
-        let data = buffer.into_data();
-        let mut i:usize = 0;
-        let mut r;
-        let mut g;
-        let mut b;
-        let mut a;
-        let x1:i32;
-        let y1:i32;
-        let op = opacity as f32;
-        for y1 in yc..yc+h {
-            for x1 in xc..xc+w {
-                if i < data.len(){
-                    r = data[i].r();
-                    g = data[i].g();
-                    b = data[i].b();
-                    a = data[i].a();
-                    if a > 0 {a = (a as f32 /100.0 * op) as u8}
-                    self.pixel(x1,y1,Color::rgba(r,g,b,a));
-                }
-                i += 1;
-            }
+    ///resize widget rect to the visible part of the zoomed document
+    fn update_rect(&self) {
+        let zoom = self.zoom_factor.get();
+        let view = self.view.get();
+        let mut rect = self.rect.get();
+        let mut width = cmp::max((self.width() as f32 * zoom) as i32 - view.x, 0) as u32;
+        let mut height = cmp::max((self.height() as f32 * zoom) as i32 - view.y, 0) as u32;
+        if let Some((max_width, max_height)) = self.viewport.get() {
+            width = cmp::min(width, max_width);
+            height = cmp::min(height, max_height);
         }
+        rect.width = width;
+        rect.height = height;
+        self.rect.set(rect);
+    }
+
+    ///set a new zoom factor keeping the document point at the view origin in place
+    fn set_zoom(&self, zoom: f32) {
+        let old = self.zoom_factor.get();
+        let view = self.view.get();
+        self.zoom_factor.set(zoom);
+        self.view.set(Rect::new((view.x as f32 * zoom / old) as i32,
+                                (view.y as f32 * zoom / old) as i32,
+                                view.width, view.height));
+        //clamp scroll offset to the new document size
+        self.pan(0, 0);
     }
     
-    ///paste internal copy buffer into current canvas starting at x,y with transparency , mask and view support
-    #[inline]
-    pub fn paste_buffer (&self, x: i32, y:i32, opacity: u8 ){
-        let buffer = self.copy_buffer.borrow().clone();
-        let w = buffer.width() as i32;
-        let h = buffer.height() as i32;
-        let xc=x-w/2; //center buffer at cursor 
-        let yc=y-h/2;
-        let data = buffer.into_data();
-        let mut i:usize = 0;
-        let mut r;
-        let mut g;
-        let mut b;
-        let mut a;
-        let x1:i32;
-        let y1:i32;
-        
-        for y1 in yc..yc+h {
-            for x1 in xc..xc+w {
-                if i < data.len(){
-                    r = data[i].r();
-                    g = data[i].g();
-                    b = data[i].b();
-                    a = data[i].a();
-                    if a > 0 {a = opacity}
-                    self.pixel(x1,y1,Color::rgba(r,g,b,a));
-                }
-                i += 1;
-            }
+    pub fn zoom_in(&self) {
+        let zoom = self.zoom_factor.get() * ZOOMSTEP;
+        if zoom <= ZOOMMAX {
+            self.set_zoom(zoom);
         }
     }
 
-    /// wrapper interactive paste
+    pub fn zoom_out(&self) {
+        let zoom = self.zoom_factor.get() / ZOOMSTEP;
+        if zoom >= ZOOMMIN {
+            self.set_zoom(zoom);
+        }
+    }
+
+    ///scroll the view, pan_x and pan_y are in screen pixels
+    pub fn pan(&self,pan_x: i32, pan_y: i32) {
+        let zoom = self.zoom_factor.get();
+        let max_x = cmp::max((self.width() as f32 * zoom) as i32 - 1, 0);
+        let max_y = cmp::max((self.height() as f32 * zoom) as i32 - 1, 0);
+        let view = self.view.get();
+        let pan_x = cmp::min(cmp::max(view.x - pan_x, 0), max_x);
+        let pan_y = cmp::min(cmp::max(view.y - pan_y, 0), max_y);
+        self.view.set(Rect::new(pan_x, pan_y, self.width(), self.height()));
+        self.update_rect();
+        self.emit_view_change();
+    }
+
+    /// wrapper interactive paste, x y and result are in canvas widget coordinates
     pub fn interact_paste (&self, x: i32, y:i32, opacity: u8, window: &mut Window) -> Option<(i32,i32)>{
         let mut image = self.image.borrow_mut();
-        if let Some(tuple) = image.interact_paste(x, y, opacity, self.copy_buffer.borrow().clone(), window) {
+        let mut buffer = self.copy_buffer.borrow().clone();
+        //preview the buffer at the current zoom, when it stays reasonably small
+        let zoom = self.zoom_factor.get();
+        let width = (buffer.width() as f32 * zoom) as u32;
+        let height = (buffer.height() as f32 * zoom) as u32;
+        if zoom != 1.0 && width > 0 && height > 0 && width <= 2048 && height <= 2048 {
+            if let Ok(zoomed) = buffer.resize(width, height, ResizeType::Point) {
+                buffer = zoomed;
+            }
+        }
+        if let Some(tuple) = image.interact_paste(x, y, opacity, buffer, window) {
             return Some(tuple);}
         None
     }
@@ -679,556 +274,223 @@ impl Canvas {
         image.interact_circle(x,y,color,window);
     }
 */
-
-    pub fn paint_on_mask(&self) {
-        self.mask_changed.set(true);
-        if self.mask_flag.get(){
-            self.mask_flag.set(false);
-            self.mask_return.set(true);
-        }else{
-            self.mask_flag.set(true);
-            self.enable_mask(true);
-        }
-    }
-/*
-    pub fn switch_mask(&self) {
-        let mut image = self.image.borrow_mut();
-        let image2 = image.clone();
-        let mut mask = self.mask.borrow_mut();
-        *image = mask.clone();
-        *mask = image2;
-        self.mask_changed.set(true);
-        if self.mask_flag.get(){
-            self.mask_flag.set(false); 
-            self.enable_mask(true);
-        }else{
-            self.mask_flag.set(true);
-            self.enable_mask(false);
+    
+    ///render visible part of image into the canvas rect scaled by zoom factor:
+    ///nearest neighbour when zoomed in, box filtered when zoomed out
+    fn draw_view(&self, renderer: &mut dyn Renderer, data: &[Color], image_w: u32, image_h: u32) {
+        let rect = self.rect.get();
+        let view = self.view.get();
+        let zoom = self.zoom_factor.get();
+        let image_w = image_w as i32;
+        let image_h = image_h as i32;
+
+        //clip to renderer
+        let width = cmp::min(rect.width as i32, renderer.width() as i32 - rect.x);
+        let height = cmp::min(rect.height as i32, renderer.height() as i32 - rect.y);
+        if width <= 0 || height <= 0 {
+            return;
         }
-    }
-*/
-    pub fn clear_mask(& self) {
-        self.mask.borrow_mut().set(Color::rgba(255, 0, 0,25));
-    }
 
-    pub fn enable_mask(& self, status: bool){
-        self.mask_enabled.set(status);
-    }
-
-    pub fn mask_flag(& self) -> bool {
-        self.mask_flag.get()
-    }
-
-    pub fn invert_mask(&self) {
-        let width = self.rect.get().width as u32;
-        let height = self.rect.get().height as u32;
-
-        if self.mask_flag.get() {return}
-        
-        //get mask data in form of [Color] slice
-        let mask_data = self.mask.clone().into_inner().into_data();
-        let mut mask = self.mask.borrow_mut();
-        let new_slice = self.trans_from_slice(&mask_data,width,height,"invert",0.0,0);
-        mask.clear();
-        mask.image(0, 0, width, height, &new_slice[..]);
-    }
-
-    ///Draw some text on canvas
-    pub fn text(&self, text: &str, font_path: &str, x0: i32, y0: i32, color: Color, size: i32){
-        //self.undo_save();  //save state for undo
-        let text = text;
-        let size = size as f32;
-        //using rusttype to render text
-
-/*        // Load the font at compile time !
-        #[cfg(target_os = "linux")]
-        let font_data = include_bytes!("/usr/share/fonts/gnu-free/FreeMonoBold.ttf");
-        #[cfg(target_os = "redox")]
-        let font_data = include_bytes!("/ui/fonts/Mono/Fira/Bold.ttf");
-        let collection = FontCollection::from_bytes(font_data as &[u8]);
-*/        
-        //Load font at runtime
-        let mut f = match File::open(font_path.to_owned()) {
-            Err(e) => return,
-            Ok(f) =>f,
+        //document span covered by screen pixel n, starting from scroll offset
+        let span = |n: i32, offset: i32, max: i32| -> (i32, i32) {
+            let start = ((n + offset) as f32 / zoom) as i32;
+            let end = (((n + offset + 1) as f32 / zoom).ceil() as i32).max(start + 1);
+            (cmp::min(start, max - 1), cmp::min(end, max))
         };
-        let mut buffer = Vec::new();
-        
-        f.read_to_end(&mut buffer).unwrap();
-        
-        let collection = FontCollection::from_bytes(buffer);
-
-        let font = collection.into_font().unwrap();
-        let scale = Scale {x: size, y: size};
-        let start = point(x0 as f32, (y0 + CANVASOFFSET) as f32);
-        let opacity = color.a() as f32;
-        for glyph in font.layout(text, scale, start) {
-            if let Some(bounding_box) = glyph.pixel_bounding_box() {
-                glyph.draw(|x, y, v| self.pixel(
-                    x as i32 + bounding_box.min.x ,
-                    y as i32 + bounding_box.min.y ,
-                    Color::rgba(color.r(), color.g(), color.b(), (v * opacity) as u8)
-                ));
+        let columns: Vec<(i32, i32)> = (0..width).map(|x| span(x, view.x, image_w)).collect();
+
+        let mut row = vec![Color::rgba(0, 0, 0, 0); width as usize];
+        let mut last_y = -1;
+        for y in 0..height {
+            let (y0, y1) = span(y, view.y, image_h);
+            //same document row as the previous screen row (zoomed in), reuse it
+            if y0 != last_y || zoom < 1.0 {
+                for (x, &(x0, x1)) in columns.iter().enumerate() {
+                    row[x] = if zoom >= 1.0 {
+                        data[(y0 * image_w + x0) as usize]
+                    } else {
+                        box_average(data, image_w, x0, y0, x1, y1)
+                    };
+                }
+                last_y = y0;
             }
+            renderer.image_fast(rect.x, rect.y + y, width as u32, 1, &row);
         }
     }
 
-/* Here unfortunately I have to reimplement not only the pixel function to
-   take care of mask but also the other graphics functions
-   because in rust I cannot override the pixel function !! 
-*/ 
-    ///pixel function with mask and pan support
-    #[inline]
-    pub fn pixel(&self , x: i32, y: i32, color: Color) {
-        let mut color = color;
-        let Rect {x: panx, y: pany, ..} = self.view.get();
-        if self.mask_flag.get(){
-            self.mask.borrow_mut().pixel(x + panx, y + pany, color);
-        }else{
-            //if we are not painting on the mask, apply mask to pixel
-            if self.mask_enabled.get(){
-                //read from mask red channel value and use it as alpha value 
-                let alpha_mask = self.mask.borrow().pixcol(x,y).r();
-                // add alpha mask to color
-                color = Color::rgba(color.r(),color.g(),color.b(),alpha_mask & color.a());
-            }
-            self.image.borrow_mut().pixel(x + panx, y + pany, color);
+    /// retrieve image from undo stack
+    pub fn undo (&self) {
+        if self.document.undo() {
+            self.pan(0, 0);
         }
     }
 
-    ///return rgba color of image pixel at position (x,y)
-    //  NOT SAFE if x & y are out of bounds, but fast.
-    //  With mask support 
-    fn pixcol(&self, x:i32, y:i32) -> Color {
-        let p = self.width()as i32 * y + x;
-        if self.mask.borrow().data()[p as usize].r() > 0 {
-            self.image.borrow().data()[p as usize]}
-        else {Color::rgba(255,255,255,255)}
-    }
-
-    ///circle with mask support
-    pub fn circle(&self , x0: i32, y0: i32, radius: i32, color: Color) {
-        //self.image.borrow_mut().circle(x0, y0, radius, color);
-        let mut x = radius.abs();
-        let mut y = 0;
-        let mut err = -radius.abs();
-        
-        match radius {
-            radius if radius > 0 => {
-                err = 0;
-                while x >= y {
-                    self.pixel(x0 - x, y0 + y, color);
-                    self.pixel(x0 + x, y0 + y, color);
-                    self.pixel(x0 - y, y0 + x, color);
-                    self.pixel(x0 + y, y0 + x, color);
-                    self.pixel(x0 - x, y0 - y, color);
-                    self.pixel(x0 + x, y0 - y, color);
-                    self.pixel(x0 - y, y0 - x, color);
-                    self.pixel(x0 + y, y0 - x, color);
-                
-                    y += 1;
-                    err += 1 + 2*y;
-                    if 2*(err-x) + 1 > 0 {
-                        x -= 1;
-                        err += 1 - 2*x;
-                    }
-                }      
-            },
-            
-            radius if radius < 0 => {
-                while x >= y {
-                    let lasty = y;
-                    err +=y;
-                    y +=1;
-                    err += y;
-                    self.line4points(x0,y0,x,lasty,color);
-                    if err >=0 {
-                        if x != lasty{
-                           self.line4points(x0,y0,lasty,x,color);
-                        }
-                        err -= x;
-                        x -= 1;
-                        err -= x;
-                    }
-                }
-
-                },
-                     _ => {
-                            self.pixel(x0, y0, color);
-                            
-                        },
+    pub fn redo (&self) {
+        if self.document.redo() {
+            self.pan(0, 0);
         }
     }
-    
-    fn line4points(&self, x0: i32, y0: i32, x: i32, y: i32, color: Color){
-        self.line(x0 - x, y0 + y, x + x0, y0 + y, color);
-        //self.rect(x0 - x, y0 + y, x as u32 * 2 + 1, 1, color);
-        if y != 0 {
-            self.line(x0 - x, y0 - y, x + x0, y0 - y , color);
-            //self.rect(x0 - x, y0 - y, x as u32 * 2 + 1, 1, color);
-        }
+
+    pub fn resize(&self, width: u32, height: u32, filter: Filter) {
+        self.document.resize(width, height, filter);
+        self.pan(0, 0);
     }
 
-    ///Draws antialiased circle with mask support
-    pub fn wu_circle (&self, x0: i32, y0: i32, radius: i32, color: Color){
-        let r = color.r();
-        let g = color.g();
-        let b = color.b();
-        let a = color.a();
-        let mut y =0;
-        let mut x = radius;
-        let mut d =0_f64;
-        
-        self.pixel (x0+x,y0+y,color);
-        self.pixel (x0-x,y0-y,color);
-        self.pixel (x0+y,y0-x,color);
-        self.pixel (x0-y,y0+x,color);
-        
-        while x > y {
-            let di = dist(radius,y);
-            if di < d { x -= 1;}
-            let col = Color::rgba(r,g,b,(a as f64*(1.0-di)) as u8);
-            let col2 = Color::rgba(r,g,b,(a as f64*di) as u8);
-            
-            self.pixel(x0+x, y0+y, col);
-            self.pixel(x0+x-1, y0+y, col2);//-
-            self.pixel(x0-x, y0+y, col);
-            self.pixel(x0-x+1, y0+y, col2);//+
-            self.pixel(x0+x, y0-y, col);
-            self.pixel(x0+x-1, y0-y, col2);//-
-            self.pixel(x0-x, y0-y, col);
-            self.pixel(x0-x+1, y0-y, col2);//+
-            
-            self.pixel(x0+y, y0+x, col);
-            self.pixel(x0+y, y0+x-1, col2);
-            self.pixel(x0-y, y0+x, col);
-            self.pixel(x0-y, y0+x-1, col2);
-            self.pixel(x0+y, y0-x, col);
-            self.pixel(x0+y, y0-x+1, col2);
-            self.pixel(x0-y, y0-x, col);
-            self.pixel(x0-y, y0-x+1, col2);
-            d = di;
-            y += 1;
-        }
-        
-        fn dist(r: i32, y: i32) -> f64{
-            let x :f64 = ((r*r-y*y)as f64).sqrt();
-            x.ceil()-x
-        }
+    pub fn transformation(&self, cod: &str, a: f32, b: i32) {
+        self.document.transformation(cod, a, b);
+        self.pan(0, 0);
     }
-    
-    
-    pub fn smooth_circle( &self, x: i32, y: i32, size: u32, opacity: u8, color: Color){
-        let mut rb = self.brush.borrow_mut();
-        if self.old_size.get() != size || color.data != self.old_color.get().data {
-            if let Ok(sb) = orbimage::parse_png(SMOOTH_BRUSH) {
-                let qb = sb.resize(size*2, size*2, ResizeType::Lanczos3).unwrap().colorize(color);
-                *rb = qb;
-                self.old_size.set(size);
-                self.old_color.set(color);
-            }
-        }
-        let Rect {x: panx, y: pany, ..} = self.view.get();
-        //self.image.borrow_mut().image(x - size as i32 + panx, y - size as i32 + pany, 
-        //                              rb.width(), rb.height(), rb.data());
-        self.paste_image(x , y , opacity/5, rb.deref().to_owned());
-    }
-
-    //rectangle with mask support
-    pub fn rect(&self, x: i32, y: i32 ,lenght: u32, width: u32, color: Color){
-        //self.image.borrow_mut().rect(x ,y, lenght, width, color);
-        let lenght = lenght as i32;
-        let width = width as i32;
-        self.line(x, y, x+lenght, y, color);
-        self.line(x, y+1, x, y+width, color);
-        self.line(x+1 ,y+width, x+lenght-1, y+width, color);
-        self.line(x+lenght,y+width,x+lenght, y+1, color);
+
+    ///selected rectangle, in document coordinates
+    pub fn selection(&self) -> Option<Rect> {
+        self.document.selection().map(from_document_rect)
     }
-    
-    ///line with mask support
-    pub fn line(&self, argx1: i32, argy1: i32, argx2: i32, argy2: i32, color: Color) {
-        let mut x = argx1;
-        let mut y = argy1;
 
-        let dx = if argx1 > argx2 { argx1 - argx2 } else { argx2 - argx1 };
-        let dy = if argy1 > argy2 { argy1 - argy2 } else { argy2 - argy1 };
+    pub fn set_selection(&self, selection: Option<Rect>) {
+        self.document.set_selection(selection.map(to_document_rect));
+        self.emit_view_change();
+    }
 
-        let sx = if argx1 < argx2 { 1 } else { -1 };
-        let sy = if argy1 < argy2 { 1 } else { -1 };
+    ///select any shape, in document coordinates
+    pub fn select(&self, selection: Option<Selection>) {
+        self.document.select(selection);
+        self.emit_view_change();
+    }
 
-        let mut err = if dx > dy { dx } else {-dy} / 2;
-        let mut err_tolerance;
+    pub fn select_with(&self, selection: Selection, combine: Combine) {
+        self.document.select_with(selection, combine);
+        self.emit_view_change();
+    }
 
-        loop {
-            self.pixel(x, y, color);
+    pub fn deselect(&self) {
+        self.document.deselect();
+        self.emit_view_change();
+    }
 
-            if x == argx2 && y == argy2 { break };
+    pub fn select_all(&self) {
+        self.document.select_all();
+        self.emit_view_change();
+    }
 
-            err_tolerance = 2 * err;
+    pub fn invert_selection(&self) {
+        self.document.invert_selection();
+        self.emit_view_change();
+    }
 
-            if err_tolerance > -dx { err -= dy; x += sx; }
-            if err_tolerance < dy { err += dx; y += sy; }
-        }
+    pub fn selection_from_mask(&self) {
+        self.document.selection_from_mask();
+        self.emit_view_change();
     }
-    
-    /// wu_line with mask support
-    pub fn wu_line (&self, x0: i32, y0: i32, x1: i32, y1: i32, color: Color) {
-        
-        let mut x0 = x0 as f64;
-        let mut y0 = y0 as f64;
-        let mut x1 = x1 as f64;
-        let mut y1 = y1 as f64;
-        let r = color.r();
-        let g = color.g();
-        let b = color.b();
-        let a = color.a() as f64;
-        
-        fn ipart (x: f64) -> i32 {
-            x.trunc() as i32
-        }
-        fn round (x: f64) -> i32 {
-            ipart(x+0.5) as i32
-        }
-        fn fpart (x: f64) -> f64 {
-            if x <0.0 { return 1.0-(x-x.floor());}
-            x-x.floor() 
-        }
-        fn rfpart(x: f64) -> f64 {
-            1.0-fpart(x)
-        }
-        fn chkalpha (mut alpha :f64) -> u8 {
-             if alpha > 255.0 { alpha = 255.0};
-             if alpha < 0.0 {alpha = 0.0};
-             alpha as u8
-        }
-        
-        let steep :bool = (y1-y0).abs() > (x1-x0).abs();
-        let mut temp;
-        if steep {
-            temp = x0; x0 = y0; y0 = temp;
-            temp = x1; x1 = y1; y1 = temp;
-        }
-        if x0 > x1 {
-            temp = x0; x0 = x1; x1 = temp;
-            temp = y0; y0 = y1; y1 = temp;
-        }
-        let dx = x1 -x0;
-        let dy = y1- y0;
-        let gradient = dy/dx;
-        
-        let mut xend: f64 = (x0 as f64).round() ;
-        let mut yend: f64 = y0 + gradient * (xend - x0);
-        let mut xgap: f64 = rfpart(x0+0.5);
-        let xpixel1 = xend as i32;
-        let ypixel1 = (ipart (yend)) as i32;
-        
-        if steep {
-            self.pixel(ypixel1, xpixel1, Color::rgba(r,g,b,chkalpha(rfpart(yend)*xgap*a)));
-            self.pixel(ypixel1+1, xpixel1, Color::rgba(r,g,b,chkalpha(fpart(yend)*xgap*a)));
-        }else{
-            self.pixel(xpixel1, ypixel1, Color::rgba(r,g,b,chkalpha(rfpart(yend)*xgap*a)));
-            self.pixel(xpixel1+1, ypixel1, Color::rgba(r,g,b,chkalpha(fpart(yend)*xgap*a)));
-        }
-        let mut intery :f64 = yend + gradient;
-        xend = x1.round();
-        yend = y1 + gradient * (xend-x1);
-        xgap = fpart(x1 + 0.5);
-        let xpixel2 = xend as i32;
-        let ypixel2 = ipart(yend) as i32;
-        if steep {
-            self.pixel(ypixel2, xpixel2, Color::rgba(r,g,b,chkalpha(rfpart(yend)*xgap*a)));
-            self.pixel(ypixel2+1, xpixel2, Color::rgba(r,g,b,chkalpha(fpart(yend)*xgap*a)));
-        }else{
-            self.pixel(xpixel2, ypixel2, Color::rgba(r,g,b,chkalpha(rfpart(yend)*xgap*a)));
-            self.pixel(xpixel2+1, ypixel2, Color::rgba(r,g,b,chkalpha(fpart(yend)*xgap*a)));
-        }
-        if steep {
-            for x in (xpixel1+1)..(xpixel2) {
-                self.pixel(ipart(intery) as i32 , x, Color::rgba(r,g,b,chkalpha(a*rfpart(intery))));
-                self.pixel(ipart(intery) as i32 + 1, x, Color::rgba(r,g,b,chkalpha(a*fpart(intery))));
-                intery += gradient;
-            }
-        }else{
-            for x in (xpixel1+1)..(xpixel2) {
-                self.pixel(x, ipart(intery) as i32, Color::rgba(r,g,b,chkalpha(a*rfpart(intery))));
-                self.pixel(x, ipart(intery) as i32 + 1, Color::rgba(r,g,b,chkalpha(a*fpart(intery))));
-                intery += gradient;
-            } 
-        }           
+
+    pub fn selection_from_alpha(&self) {
+        self.document.selection_from_alpha();
+        self.emit_view_change();
     }
 
-    ///continuus brush circular shape with mask support
-    pub fn brush_line(&self, argx1: i32, argy1: i32, argx2: i32, argy2: i32, radius: i32, color: Color) {
-        let mut x = argx1;
-        let mut y = argy1;
+    pub fn refine_selection(&self, refine: Refine) {
+        self.document.refine_selection(refine);
+        self.emit_view_change();
+    }
 
-        let dx = if argx1 > argx2 { argx1 - argx2 } else { argx2 - argx1 };
-        let dy = if argy1 > argy2 { argy1 - argy2 } else { argy2 - argy1 };
+    pub fn magicwand(&self, x: i32, y: i32, matching: &ColorMatch, combine: Combine) {
+        self.document.magicwand(x, y, matching, combine);
+        self.emit_view_change();
+    }
 
-        let sx = if argx1 < argx2 { 1 } else { -1 };
-        let sy = if argy1 < argy2 { 1 } else { -1 };
+    ///leaving the quick mask turns it into the selection
+    pub fn paint_on_mask(&self) {
+        self.document.paint_on_mask();
+        self.emit_view_change();
+    }
 
-        let mut err = if dx > dy { dx } else {-dy} / 2;
-        let mut err_tolerance;
-        let r = (radius as f32 * self.zoom_factor.get()) as i32;
-        
+    pub fn clear_mask(&self) {
+        self.document.clear_mask();
+        self.emit_view_change();
+    }
 
-        loop {
-            self.circle(x, y, r, color);
+    pub fn invert_mask(&self) {
+        self.document.invert_mask();
+        self.emit_view_change();
+    }
 
-            if x == argx2 && y == argy2 { break };
+    ///lines of the selection outline in canvas widget coordinates
+    pub fn selection_outline(&self) -> Vec<(Point, Point)> {
+        self.document.selection_edges().into_iter()
+            .map(|((x1, y1), (x2, y2))| (self.to_screen(Point::new(x1, y1)), self.to_screen(Point::new(x2, y2))))
+            .collect()
+    }
 
-            err_tolerance = 2 * err;
+    pub fn trans_selection(&self, selection: Rect, cod: &str, a: f32, b: i32) {
+        self.document.trans_selection(to_document_rect(selection), cod, a, b);
+    }
 
-            if err_tolerance > -dx { err -= dy; x += sx; }
-            if err_tolerance < dy { err += dx; y += sy; }
-        }
+    ///transformation of selection, or of everything when None, to run on a worker thread
+    pub fn transformation_job(&self, selection: Option<Rect>, cod: &str, a: f32, b: i32) -> Option<Job> {
+        self.document.transformation_job(selection.map(to_document_rect), cod, a, b)
     }
-    
-    ///continuus brush rectangular shape not yet with mask support
-    pub fn rect_line(&self, argx1: i32, argy1: i32, argx2: i32, argy2: i32,lenght: u32, width: u32, color: Color) {
-        let mut x = argx1;
-        let mut y = argy1;
 
-        let dx = if argx1 > argx2 { argx1 - argx2 } else { argx2 - argx1 };
-        let dy = if argy1 > argy2 { argy1 - argy2 } else { argy2 - argy1 };
+    ///resample of the whole document, to run on a worker thread
+    pub fn resize_job(&self, width: u32, height: u32, filter: Filter) -> Option<Job> {
+        self.document.resize_job(width, height, filter)
+    }
 
-        let sx = if argx1 < argx2 { 1 } else { -1 };
-        let sy = if argy1 < argy2 { 1 } else { -1 };
+    ///new canvas size without scaling, the old one lands at x,y of the new one
+    pub fn canvas_size_job(&self, width: u32, height: u32, x: i32, y: i32, fill: Option<Color>) -> Option<Job> {
+        self.document.canvas_size_job(width, height, x, y, fill)
+    }
 
-        let mut err = if dx > dy { dx } else {-dy} / 2;
-        let mut err_tolerance;
+    ///cut the canvas down to rect, in canvas coordinates like selection
+    pub fn crop_job(&self, rect: Rect) -> Option<Job> {
+        self.document.crop_job(to_document_rect(rect))
+    }
 
-        loop {
-            self.rect(x, y, lenght, width, color);
+    ///cut away the uniform border around the image
+    pub fn autocrop_job(&self) -> Option<Job> {
+        self.document.autocrop_job()
+    }
 
-            if x == argx2 && y == argy2 { break };
+    ///rotation of selection, or of everything when None, to run on a worker thread
+    pub fn rotate_job(&self, selection: Option<Rect>, degrees: f32, interpolation: Interpolation, fit: Fit,
+                      fill: Option<Color>) -> Option<Job> {
+        self.document.rotate_job(selection.map(to_document_rect), degrees, interpolation, fit, fill)
+    }
 
-            err_tolerance = 2 * err;
+    pub fn finish_job(&self, done: Done) {
+        self.document.finish_job(done);
+        self.pan(0, 0);
+    }
 
-            if err_tolerance > -dx { err -= dy; x += sx; }
-            if err_tolerance < dy { err += dx; y += sy; }
-        }
+    ///start and end are in document coordinates
+    pub fn gradient(&self, gradient: &Gradient, start: (f32, f32), end: (f32, f32)) {
+        self.document.gradient(gradient, start, end);
     }
 
-     ///Draws a regular polygon with mask support
-    pub fn polygon(&self, x0: i32, y0: i32, r: i32, sides: u32, angle: f32, color: Color, antialias: bool ) {
-        let mut x:Vec<i32> = Vec::new();
-        let mut y:Vec<i32> = Vec::new();
-        let i :usize = 0;
-        let sides = sides as usize;
-        //find vertices
-        for i in 0..=sides {
-            let t :f32 =angle + 2.0*PI* i as f32 /sides as f32;
-            x.push((r as f32 * t.cos()) as i32 + x0);
-            y.push((r as f32 * t.sin()) as i32 + y0);
-        }
-        
-        if antialias {
-        for i in 0..sides {
-            self.wu_line(x[i],y[i],x[i+1],y[i+1],color);
-        }
-        self.wu_line(x[sides],y[sides],x[0],y[0],color);    
-        }else{
-        for i in 0..sides {
-            self.line(x[i],y[i],x[i+1],y[i+1],color);
-        }
-        self.line(x[sides],y[sides],x[0],y[0],color);
-        }
+    ///start a brush stroke at x,y in document coordinates
+    pub fn begin_stroke(&self, brush: Brush, color: Color, x: f32, y: f32) {
+        self.document.begin_stroke(brush, color, x, y);
     }
-    
-    ///#FIXME wrapper for flood fill with mask support DOES NOT WORK YET !!
-    pub fn fill_mask(&self, x: i32, y: i32 , color: Color) {
-        //get current pixel color 
-        let rgba = self.pixcol(x,y);
-        self.flood_fill_scanline(x,y,color.data,rgba.data);  //use rgba and color as i32 values 
+
+    ///start erasing at x,y in document coordinates
+    pub fn begin_erase(&self, brush: Brush, opacity: u8, erase: Erase, x: f32, y: f32) {
+        self.document.begin_erase(brush, opacity, erase, x, y);
     }
-    
-    ///stack friendly and fast floodfill algorithm that works with transparency too 
-    fn flood_fill_scanline( &self, x:i32, y:i32, new_color: u32, old_color:u32) {
-        if old_color == new_color {
-            return;
-        }
-        if self.pixcol(x,y).data  != old_color  {
-            return;
-        }
-        
-        let w = self.width() as i32;
-        let h = self.height() as i32;
-        
-        //draw current scanline from start position to the right
-        let mut x1 = x;
-        
-        while x1 < w && self.pixcol(x1,y).data  == old_color  {
-            self.pixel(x1,y,Color{data:new_color});
-            x1 +=1;
-        } 
-        //get resulted color because of transparency and use this for comparison 
-        let res_color = self.pixcol(x,y).data;
-        
-        //draw current scanline from start position to the left
-        x1 = x -1;
-        
-        while x1 >= 0 && self.pixcol(x1,y).data  == old_color  {
-            self.pixel(x1,y,Color{data:new_color});
-            x1 += -1;
-          }
-        
-        //test for new scanlines above
-        x1 = x;
-        
-        while x1 < w && self.pixcol(x1,y).data  == res_color  { 
-            if y > 0 && self.pixcol(x1,y-1).data  == old_color  {
-              self.flood_fill_scanline(x1, y - 1, new_color, old_color);
-            }
-            x1 += 1;
-          }
-        x1 = x - 1;
-        while x1 >= 0 && self.pixcol(x1,y).data == res_color {
-            if y > 0 && self.pixcol(x1,y - 1).data  == old_color  {
-              self.flood_fill_scanline(x1, y - 1, new_color, old_color);
-            }
-            x1 += -1;
-          }
-         
-         //test for new scanlines below
-        x1 = x;
-        while x1 < w && self.pixcol(x1,y).data == res_color  {
-            //println!("Test below {} {} ", self.pixcol(x1,y).data,old_color);
-            if y < (h - 1) && self.pixcol(x1,y + 1).data == old_color {
-                self.flood_fill_scanline(x1, y + 1, new_color, old_color);
-            }
-            x1 +=1;
-        }
-        x1 = x - 1;
-        while x1 >= 0 && self.pixcol(x1,y).data == res_color {
-            if y < (h - 1) && self.pixcol(x1,y + 1).data == old_color {
-                self.flood_fill_scanline(x1, y + 1, new_color, old_color);
-            }
-            x1 += -1;
-        }
+
+    pub fn stroke_to(&self, x: f32, y: f32) {
+        self.document.stroke_to(x, y);
     }
 
-    ///crop new image from current image (copy) tranforming pure white into transparent with mask support
-    pub fn copy_selection(&self, x: i32,y: i32,w: u32, h: u32) {
+    pub fn end_stroke(&self) {
+        self.document.end_stroke();
+    }
 
-        let mut vec = vec![];
-        let mut col : Color;
-        
-        for y1 in y..y+h as i32 {
-            for x1 in x..x+w as i32 {
-                col=self.pixcol(x1,y1);
-                if col.r()==255 && col.g()==255 && col.b()==255 {
-                    col = Color::rgba(0,0,0,0);
-                }
-                vec.push(col);
-            }
-        }
-        *self.copy_buffer.borrow_mut() = orbimage::Image::from_data(w ,h ,vec.into_boxed_slice()).unwrap();
+    ///draw a rectangle, the name is shadowed by Widget::rect
+    pub fn rect(&self, x: i32, y: i32, lenght: u32, width: u32, color: Color) {
+        self.document.rect(x, y, lenght, width, color);
     }
 
 }
 
+
 impl Click for Canvas {
     fn emit_click(&self, point: Point) {
         if let Some(ref click_callback) = *self.click_callback.borrow() {
@@ -1250,58 +512,35 @@ impl Widget for Canvas {
     }
 
     fn draw(&self, renderer:  &mut dyn Renderer, _focused: bool, _theme: &Theme) {
-        let rect = self.rect.get();
-        let image = self.image.borrow();
-        let mask = self.mask.borrow();
-
-        //render only the view of the image (ROI) so we can pan but only in normal drawing window 
-        if !self.mask_flag.get()  {
-            let x = rect.x;
-            let mut y = rect.y;
-            let width = self.view.get().width;
-            let stride = image.width() as usize;
-            let mut offset = self.view.get().y as usize * stride + self.view.get().x as usize;
-            let last_offset = cmp::min(self.view.get().y as usize + self.view.get().height as usize * stride + self.view.get().x as usize, image.data().len());
-            while offset < last_offset {
-                let next_offset = offset + stride;
-                renderer.image_fast(x, y, width, 1, &image.data()[offset..]);
-                /* zoomed rendering not working, yet....
-                if  self.zoom_factor.get() == 1.0 {
-                    renderer.image_fast(x, y, width, 1, &image.data()[offset..]);
-                } else {
-                    let wy = y * self.zoom_factor.get() as i32;
-                    let wx = x * self.zoom_factor.get() as i32;
-                    for ix in x..width as i32 -1 {
-                        
-                        let icolor = image.getpixel(ix,y);
-                        //let icolor = Color::rgb(100,200,100);
-                        for v in 0..self.zoom_factor.get() as i32 {
-                            for w in 0..self.zoom_factor.get() as i32 {
-                                renderer.pixel(wx+v,wy+w, icolor);
-                            }
-                        }
-                    }
-                }
-                */
-                offset = next_offset;
-                y += 1;
-            }
+        let width = self.width();
+        let height = self.height();
+
+        //render only the view of the document (ROI) scaled by zoom factor, so we can pan and zoom
+        let plain = {
+            let layers = self.layers();
+            layers.len() == 1 && layers[0].is_plain()
+        };
+        if plain {
+            self.draw_view(renderer, self.image.borrow().data(), width, height);
+        } else {
+            //composite only the part of the document that is on screen
+            let zoom = self.zoom_factor.get();
+            let view = self.view.get();
+            let rect = self.rect.get();
+            let x0 = cmp::min((view.x as f32 / zoom) as i32, width as i32);
+            let y0 = cmp::min((view.y as f32 / zoom) as i32, height as i32);
+            let x1 = cmp::min(((view.x + rect.width as i32) as f32 / zoom).ceil() as i32, width as i32);
+            let y1 = cmp::min(((view.y + rect.height as i32) as f32 / zoom).ceil() as i32, height as i32);
+            let mut composite = self.composite.borrow_mut();
+            composite.resize((width * height) as usize, Color::rgba(0, 0, 0, 0));
+            self.composite_layers(&mut composite, x0, y0, x1, y1);
+            self.draw_view(renderer, &composite, width, height);
         }
-        //to speed up render mask only if needed (changed) on top of image
-        if self.mask_flag.get() || self.mask_changed.get() {
-            //background immutable image
-            renderer.image_over(CANVASOFFSET, image.data());
-            //transparent editable mask
-            renderer.image_fast(rect.x, rect.y, image.width(), image.height(), mask.data());
-            self.mask_changed.set(false);
-            
-            }
-        //to refresh window immediately after a return from (quick)mask view
-        if self.mask_return.get(){
-            self.mask_return.set(false);
-            renderer.image_over(CANVASOFFSET, image.data());
+
+        //render mask on top of image while painting on it or when it has changed
+        if self.document.take_mask_changed() || self.mask_flag() {
+            self.draw_view(renderer, self.mask().data(), width, height);
         }
-        
     }
 
     fn event(&self, event: Event, focused: bool, redraw: &mut bool) -> bool {
@@ -1342,7 +581,7 @@ impl Widget for Canvas {
                     *redraw = true;
                 }
                 //dispatch shortcuts out of Canvas
-                if ['v','c','x','Q'].contains(&c) {
+                if ['v','c','x','Q','=','+','-','*'].contains(&c) {
                     self.emit_shortcut(c);
                 }
             },
@@ -1367,12 +606,11 @@ impl Widget for Canvas {
                 *redraw = true;
             },
             Event::Scroll {x,y} => {
-                if y == 1 && !self.mask_flag.get(){
+                if y == 1 {
                     self.zoom_in();
                     *redraw = true;
-                    
                 }
-                if y == -1 && !self.mask_flag.get(){
+                if y == -1 {
                     self.zoom_out();
                     *redraw = true;
                 }
@@ -1391,17 +629,34 @@ impl Widget for Canvas {
     }
 }
 
-pub fn colorize (color: Color, img: Image) -> Image {
-    let w = img.width();
-    let h = img.height();
-    let mut data = img.into_data();
-    //let i:usize = 0;
-    let mut a;
+///average of a block of pixels, weighting colors by alpha
+fn box_average(data: &[Color], stride: i32, x0: i32, y0: i32, x1: i32, y1: i32) -> Color {
+    let mut r = 0;
+    let mut g = 0;
+    let mut b = 0;
+    let mut a = 0;
+    let mut n = 0;
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let c = data[(y * stride + x) as usize];
+            let ca = c.a() as u32;
+            r += c.r() as u32 * ca;
+            g += c.g() as u32 * ca;
+            b += c.b() as u32 * ca;
+            a += ca;
+            n += 1;
+        }
+    }
+    if a == 0 {
+        return Color::rgba(0, 0, 0, 0);
+    }
+    Color::rgba((r / a) as u8, (g / a) as u8, (b / a) as u8, (a / n) as u8)
+}
 
-    for i in 0..data.len() {
-        a = data[i].a();
-        data[i]=Color::rgba(color.r(),color.g(),color.b(),a)
-    }
+fn to_document_rect(rect: Rect) -> pastel::Rect {
+    pastel::Rect::new(rect.x, rect.y, rect.width, rect.height)
+}
 
-    orbimage::Image::from_data(w ,h ,data).unwrap()
+fn from_document_rect(rect: pastel::Rect) -> Rect {
+    Rect::new(rect.x, rect.y, rect.width, rect.height)
 }
@@ -3,9 +3,13 @@ use orbtk::{ Button, Label, Point, Rect, TextBox, Window, InnerWindow}; //Color,
 use orbtk::traits::{Click, Enter, Place, Text};  //Border
 use orbtk::dialogs::FileDialog;
 
+use std::cell::Cell;
+use std::cmp;
 use std::ops::Deref;
 use std::path::PathBuf;
+use std::rc::Rc;
 
+use crate::toolbar::ToolbarIcon;
 use crate::DEFAULTFONT;
 
 //generic dialog window
@@ -222,6 +226,159 @@ pub fn text_dialog(title: &str, text: &str, suggestion: &str) -> Option<(String,
     }
 }
 
+//dialog window with text boxes and buttons that step through choices; each field is
+//(label, suggestion), each choice (name, options, option selected first);
+//the result has the text of each field and the option picked for each choice
+pub fn choice_dialog(title: &str, fields: &[(&str, &str)], choices: &[(&str, &[&str], usize)]) -> Option<(Vec<String>, Vec<usize>)> {
+    let height = 50 + 32 * (fields.len() + choices.len()) as u32;
+    let mut orb_window = Some(InnerWindow::new(100, 100, 320, height, title).unwrap());
+    let mut new_window = Box::new(Window::from_inner(orb_window.take().unwrap()));
+
+    let x = 10;
+    let mut y = 10;
+
+    let mut text_boxes = Vec::new();
+    for &(text, suggestion) in fields {
+        let label = Label::new();
+        label.position(x, y + 6).size(140, 16).text(text);
+        new_window.add(&label);
+
+        let text_box = TextBox::new();
+        text_box.position(x + 150, y)
+        .size(140, 28)
+        .text_offset(6, 6)
+        .text(suggestion);
+
+        //pressing enter in text_box closes popup window
+        {
+            let new_window_clone = new_window.deref() as *const Window;
+            text_box.on_enter(move |_| {
+                unsafe {
+                    (*new_window_clone).close();
+                }
+            });
+        }
+        new_window.add(&text_box);
+        text_boxes.push(text_box);
+        y += 32;
+    }
+    let text_box = text_boxes[0].clone();
+
+    //a click shows the next option
+    let mut picked = Vec::new();
+    for &(name, options, first) in choices {
+        let index = Rc::new(Cell::new(first));
+        let button = Button::new();
+        button
+            .position(x, y)
+            .size(290, 28)
+            .text(format!("{}: {}", name, options[first]))
+            .text_offset(6, 6);
+        {
+            let index = index.clone();
+            let options: Vec<String> = options.iter().map(|option| option.to_string()).collect();
+            let name = name.to_string();
+            button.on_click(move |button: &Button, _point: Point| {
+                index.set((index.get() + 1) % options.len());
+                button.text(format!("{}: {}", name, options[index.get()]));
+            });
+        }
+        new_window.add(&button);
+        picked.push(index);
+        y += 32;
+    }
+
+    y += 4;
+
+    //OK button
+    let ok_button = Button::new();
+    ok_button
+        .position(x, y)
+        .size(48 + 12, text_box.rect.get().height)
+        .text("OK")
+        .text_offset(6, 6);
+
+    {
+        let text_box = text_box.clone();
+        ok_button.on_click(move |_button: &Button, _point: Point| { text_box.emit_enter(); });
+    }
+    new_window.add(&ok_button);
+
+    //Cancell button
+    let cancel_button = Button::new();
+    cancel_button
+        .position(x + 64, y)
+        .size(48 + 12, text_box.rect.get().height)
+        .text("Cancel")
+        .text_offset(6, 6);
+
+    {
+        let text_box = text_box.clone();
+        cancel_button.on_click(move |_button: &Button, _point: Point| {
+                            text_box.emit_enter();
+                            text_box.text.set("".to_owned());
+                        });
+    }
+    new_window.add(&cancel_button);
+    new_window.exec();
+
+    match text_box.text.get().len() {
+        0 => None,
+        _ => Some((text_boxes.iter().map(|text_box| text_box.text.get()).collect(),
+                   picked.iter().map(|index| index.get()).collect())),
+    }
+}
+
+//dialog window showing images to click on, each (name shown as tooltip, image);
+//the result is the index of the one clicked
+pub fn image_dialog(title: &str, images: &[(String, orbimage::Image)]) -> Option<usize> {
+    const COLUMNS: usize = 6;
+    let cell = images.iter().map(|(_, image)| cmp::max(image.width(), image.height())).max().unwrap_or(32) + 4;
+    let rows = (images.len() + COLUMNS - 1) / COLUMNS;
+    let width = cmp::max(150, COLUMNS as u32 * cell + 20);
+    let height = rows as u32 * cell + 60;
+    let mut orb_window = Some(InnerWindow::new(100, 100, width, height, title).unwrap());
+    let mut new_window = Box::new(Window::from_inner(orb_window.take().unwrap()));
+
+    let picked = Rc::new(Cell::new(None));
+    for (n, (name, image)) in images.iter().enumerate() {
+        let icon = ToolbarIcon::from_image(image.clone());
+        icon.position(10 + (n % COLUMNS) as i32 * cell as i32, 10 + (n / COLUMNS) as i32 * cell as i32)
+            .tooltip(name.to_owned());
+        {
+            let picked = picked.clone();
+            let new_window_clone = new_window.deref() as *const Window;
+            icon.on_click(move |_icon: &ToolbarIcon, _point: Point| {
+                picked.set(Some(n));
+                unsafe {
+                    (*new_window_clone).close();
+                }
+            });
+        }
+        new_window.add(&icon);
+    }
+
+    //Cancel button
+    let cancel_button = Button::new();
+    cancel_button
+        .position(10, 10 + rows as i32 * cell as i32 + 8)
+        .size(48 + 12, 28)
+        .text("Cancel")
+        .text_offset(6, 6);
+    {
+        let new_window_clone = new_window.deref() as *const Window;
+        cancel_button.on_click(move |_button: &Button, _point: Point| {
+            unsafe {
+                (*new_window_clone).close();
+            }
+        });
+    }
+    new_window.add(&cancel_button);
+    new_window.exec();
+
+    picked.get()
+}
+
 pub fn new_dialog(title: &str) -> Option<String> {
     //Dialog to input dimensions of new image
     let mut new_window = Window::new(Rect::new(200, 300, 320, 200), title);
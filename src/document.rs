@@ -0,0 +1,2235 @@
+//document model: layers, mask, selection, undo history and the raster
+//operations working on them, without any window or widget
+
+extern crate rusttype;
+
+use self::rusttype::{FontCollection, Scale, point};
+
+use image;
+use image::ImageBuffer;
+
+use orbclient::{Color, Renderer, Mode};
+
+use orbimage::{self, Image};
+
+use std::cell::{Cell, Ref, RefCell};
+use std::path::Path;
+use std::sync::Arc;
+use std::slice;
+use std::io::Error;
+use std::f32::consts::PI;
+use std::io::prelude::*;
+use std::fs::File;
+use std::cmp;
+use std::mem;
+
+use crate::Rect;
+use crate::UNDOMEMORY;
+use crate::raster::AddOnsToOrbimage;
+use crate::region::{self, ColorMatch};
+use crate::selection::{self, Combine, Refine, Selection, Shape};
+use crate::floating::{Floating, Quad};
+use crate::brush::{Brush, Dab, Erase, Stroke, Tip};
+use crate::gradient::Gradient;
+use crate::resample::{self, Filter, Fit, Interpolation};
+use crate::layer::{self, BlendMode, Layer};
+use crate::undo::{History, State};
+use crate::parallel;
+use crate::task::Progress;
+
+
+pub struct Document {
+    //pixels of the active layer
+    pub image: RefCell<Image>,
+    layers: RefCell<Vec<Layer>>,
+    active_layer: Cell<usize>,
+    layer_count: Cell<usize>,
+    history: RefCell<History>,
+    //outline of the selection, how much each pixel is selected is in the mask
+    selection: RefCell<Option<Selection>>,
+    //ants around a free selection, made when first needed
+    selection_edges: RefCell<Option<Vec<((i32, i32), (i32, i32))>>>,
+    mask: RefCell<Image>,
+    mask_flag: Cell<bool>,
+    mask_enabled: Cell<bool>,
+    mask_changed: Cell<bool>,
+    pub copy_buffer: RefCell<Image>,
+    history_callback: RefCell<Option<Arc<dyn Fn(&Document)>>>,
+    //brush stroke being painted
+    stroke: RefCell<Option<Stroke>>,
+    //selected pixels being moved, drawn into the active layer until put down
+    floating: RefCell<Option<Floating>>,
+}
+
+impl Document {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self::from_image(Image::new(width, height))
+    }
+
+    pub fn from_color(width: u32, height: u32, color: Color) -> Self {
+        Self::from_image(Image::from_color(width, height, color))
+    }
+
+    pub fn from_image(image: Image) -> Self {
+        let mask = Image::from_color(image.width(), image.height(), Color::rgba(255,0,0,25));
+        Document {
+            layers: RefCell::new(vec![Layer::new("Background", Image::new(0, 0))]),
+            active_layer: Cell::new(0),
+            layer_count: Cell::new(1),
+            history: RefCell::new(History::new(&image, &mask, UNDOMEMORY)),
+            selection: RefCell::new(None),
+            selection_edges: RefCell::new(None),
+            mask: RefCell::new(mask),
+            mask_flag: Cell::new(false),
+            mask_enabled: Cell::new(false),
+            mask_changed: Cell::new(false),
+            image: RefCell::new(image),
+            copy_buffer: RefCell::new(Image::new(0,0)),
+            history_callback: RefCell::new(None),
+            stroke: RefCell::new(None),
+            floating: RefCell::new(None),
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        Ok(Self::from_image(Image::from_path(path)?))
+    }
+
+    ///new document from a stack of layers, all of the same size
+    pub fn from_layers(mut layers: Vec<Layer>, active: usize, mask: Image) -> Self {
+        let active = cmp::min(active, layers.len() - 1);
+        let image = mem::replace(&mut layers[active].image, Image::new(0, 0));
+        let document = Self::from_image(image);
+        document.layer_count.set(layers.len());
+        *document.layers.borrow_mut() = layers;
+        document.active_layer.set(active);
+        if mask.width() == document.width() && mask.height() == document.height() {
+            *document.mask.borrow_mut() = mask;
+        }
+        document.reset_undo();
+        document
+    }
+
+    ///copy of all layers, including the pixels of the active one
+    pub fn export_layers(&self) -> Vec<Layer> {
+        let mut layers = self.layers.borrow().clone();
+        layers[self.active_layer.get()].image = self.image.borrow().clone();
+        layers
+    }
+
+    pub fn mask(&self) -> Ref<'_, Image> {
+        self.mask.borrow()
+    }
+    
+    pub fn save(&self, filename: &str) -> Result <i32, Error>{
+        let width = self.width() as u32;
+        let height = self.height() as u32;
+
+        //get flattened image data in form of [Color] slice
+        let image_data = self.flattened().into_data();
+
+        //To save corectly the image with image::save_buffer
+        // we have to take care of correct byte order (rgba <-> abgr)
+        let mut new_image_buffer = vec![0; 4 * image_data.len()];
+        parallel::for_strips(&mut new_image_buffer, 4 * width as usize, |first, strip| {
+            let start = first * width as usize;
+            for (pixel, color) in strip.chunks_mut(4).zip(&image_data[start..]) {
+                pixel.copy_from_slice(&[color.r(), color.g(), color.b(), color.a()]);
+            }
+        });
+
+        if cfg!(feature = "debug"){
+            println!("Saving {}", &filename);
+            println!("x{} y{} len={}", width, height, image_data.len());
+        }
+        
+        match image::save_buffer(&Path::new(&filename),
+                           &new_image_buffer,
+                           width,
+                           height,
+                           image::RGBA(8)){
+                Ok(_)   => {
+                            if cfg!(feature = "debug"){println!("Saved");}
+                            Ok(0)
+                            },               
+                Err(e) => {
+                            if cfg!(feature = "debug"){println!("Error: {}",e);}
+                            Err(e)
+                            },
+        }
+    }
+
+    pub fn clear(&self){
+        if self.mask_flag.get(){
+            self.undo_save("Clear mask");
+            self.mask.borrow_mut().set(Color::rgba(255,0,0,25));
+            self.undo_commit();
+        }else if self.editable(){
+            //first prepare for undo 
+            self.undo_save("Clear");
+            {
+                let clip = self.clip();
+                let mut image = self.image.borrow_mut();
+                let background = self.background();
+                match clip {
+                    //only what is selected, by how much it is
+                    Some(clip) => for (color, &c) in image.data_mut().iter_mut().zip(&clip) {
+                        *color = layer::mix(*color, background, c);
+                    },
+                    None => image.set(background),
+                }
+            }
+            self.undo_commit();
+        }
+    }
+    
+    pub fn height(&self) -> u32 {
+        self.image.borrow().height()
+    }
+    
+    pub fn width(&self) -> u32 {
+        self.image.borrow().width()
+    }
+
+    ///apply some transformations to entire canvas or mask
+    pub fn transformation(&self, cod: &str, a: f32, b:i32){
+        if let Some(job) = self.transformation_job(None, cod, a, b) {
+            self.run_job(job);
+        }
+    }
+
+    ///resample document (all layers and mask) to width x height
+    pub fn resize(&self, width: u32, height: u32, filter: Filter) {
+        if let Some(job) = self.resize_job(width, height, filter) {
+            self.run_job(job);
+        }
+    }
+
+    ///resample of document to width x height, to run on a worker thread
+    pub fn resize_job(&self, width: u32, height: u32, filter: Filter) -> Option<Job> {
+        self.reshape_job("resize", width as f32, resize_code(height, filter), self.background())
+    }
+
+    ///rotate document (all layers and mask) 90 degrees clockwise, width and height are swapped
+    pub fn rotate90(&self) {
+        if let Some(job) = self.reshape_job("rotate90", 0.0, 0, self.background()) {
+            self.run_job(job);
+        }
+    }
+
+    ///apply some transformations to canvas selection (in place)
+    pub fn trans_selection(&self, selection: Rect, cod: &str, a: f32, b:i32){
+        if let Some(job) = self.transformation_job(Some(selection), cod, a, b) {
+            self.run_job(job);
+        }
+    }
+
+    ///run job on this thread and keep its result
+    fn run_job(&self, job: Job) {
+        if let Some(done) = job.run(&Progress::new()) {
+            self.finish_job(done);
+        }
+    }
+
+    ///take a copy of what a transformation of selection (everything if None) works on,
+    ///so it can run on another thread; None if there is nothing to do
+    pub fn transformation_job(&self, selection: Option<Rect>, cod: &str, a: f32, b: i32) -> Option<Job> {
+        let fill = if self.mask_flag.get() { selection::mask_color(255) } else { self.background() };
+        self.transform_job(selection, cod, a, b, fill)
+    }
+
+    ///turn selection, or everything when None, by degrees clockwise; fit says what happens to
+    ///the corners, the canvas grows or shrinks only when everything turns; the corners left
+    ///uncovered get fill, transparency if None (upper layers are always left transparent)
+    pub fn rotate_job(&self, selection: Option<Rect>, degrees: f32, interpolation: Interpolation, fit: Fit,
+                      fill: Option<Color>) -> Option<Job> {
+        let mask = self.mask_flag.get();
+        let fit = if selection.is_some() || mask { Fit::Crop } else { fit };
+        let fill = match fill {
+            _ if mask => selection::mask_color(255),
+            Some(color) => color,
+            None => Color::rgba(0, 0, 0, 0),
+        };
+        self.transform_job(selection, "rotate", degrees, rotation_code(interpolation, fit), fill)
+    }
+
+    ///make the canvas width x height without scaling, the old one lands at x,y of the new one
+    ///(negative cuts it); new area of the bottom layer gets fill, transparency if None
+    pub fn canvas_size_job(&self, width: u32, height: u32, x: i32, y: i32, fill: Option<Color>) -> Option<Job> {
+        if width == self.width() && height == self.height() && x == 0 && y == 0 {
+            return None;
+        }
+        self.reshape_to("canvas_size", x as f32, y, width, height, fill.unwrap_or(Color::rgba(0, 0, 0, 0)))
+    }
+
+    ///cut the canvas down to the part of rect inside it
+    pub fn crop_job(&self, rect: Rect) -> Option<Job> {
+        let x0 = cmp::max(rect.x, 0);
+        let y0 = cmp::max(rect.y, 0);
+        let x1 = cmp::min(rect.x + rect.width as i32, self.width() as i32);
+        let y1 = cmp::min(rect.y + rect.height as i32, self.height() as i32);
+        if x1 <= x0 || y1 <= y0 || (x1 - x0, y1 - y0) == (self.width() as i32, self.height() as i32) {
+            return None;
+        }
+        self.reshape_to("crop", -x0 as f32, -y0, (x1 - x0) as u32, (y1 - y0) as u32, Color::rgba(0, 0, 0, 0))
+    }
+
+    ///cut away the border of one color, or transparent, around what is visible; None if there is none
+    pub fn autocrop_job(&self) -> Option<Job> {
+        let flat = self.flattened();
+        let bounds = region::content_bounds(flat.data(), flat.width(), flat.height())?;
+        if (bounds.width, bounds.height) == (self.width(), self.height()) {
+            return None;
+        }
+        self.reshape_to("autocrop", -bounds.x as f32, -bounds.y, bounds.width, bounds.height, Color::rgba(0, 0, 0, 0))
+    }
+
+    ///transformation_job that leaves fill where nothing lands
+    fn transform_job(&self, selection: Option<Rect>, cod: &str, a: f32, b: i32, fill: Color) -> Option<Job> {
+        let mask = self.mask_flag.get();
+        let reshaping = cod == "resize" || (cod == "rotate90" && !mask)
+            || (cod == "rotate" && !mask && rotation(b).1 != Fit::Crop);
+        if selection.is_none() && reshaping {
+            return self.reshape_job(cod, a, b, fill);
+        }
+        if !self.editable() {
+            return None;
+        }
+        //only what is selected changes, unless the outline turns too;
+        //with nothing given the whole image is filtered, still through the selection
+        let clipped = self.mask_enabled.get() && !mask && cod != "rotate90";
+        let selection = match selection {
+            None if clipped => Some(Rect::new(0, 0, self.width(), self.height())),
+            selection => selection,
+        };
+        let image = {
+            let image = if mask { self.mask.borrow() } else { self.image.borrow() };
+            match selection {
+                //mixed back in as is, so white must stay white
+                Some(rect) if clipped => crop(&image, rect),
+                Some(rect) => image.copy_selection(rect.x, rect.y, rect.width, rect.height),
+                None => image.clone(),
+            }
+        };
+        let coverage = match selection {
+            Some(rect) if clipped => Some(self.coverage_in(rect)),
+            _ => None,
+        };
+        Some(Job::new(cod, a, b, Work::Transform { mask, selection, coverage, fill, image }))
+    }
+
+    ///size changing transformation of all layers and mask
+    fn reshape_job(&self, cod: &str, a: f32, b: i32, fill: Color) -> Option<Job> {
+        let (width, height) = transformed_size(cod, a, b, self.width(), self.height());
+        if cod == "resize" && width == self.width() && height == self.height() {
+            return None;
+        }
+        self.reshape_to(cod, a, b, width, height, fill)
+    }
+
+    ///reshape_job into width x height; new area of the bottom layer gets fill, of the others
+    ///transparency, and it is selected only when there is no selection
+    fn reshape_to(&self, cod: &str, a: f32, b: i32, width: u32, height: u32, fill: Color) -> Option<Job> {
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let active = self.active_layer.get();
+        let layer_fill = |n: usize| if n == 0 { fill } else { Color::rgba(0, 0, 0, 0) };
+        let mask_fill = selection::mask_color(if self.selection.borrow().is_some() { 0 } else { 255 });
+        let mut images = vec![self.image.borrow().clone(), self.mask.borrow().clone()];
+        let mut fills = vec![layer_fill(active), mask_fill];
+        for (n, layer) in self.layers.borrow().iter().enumerate() {
+            if n != active {
+                images.push(layer.image.clone());
+                fills.push(layer_fill(n));
+            }
+        }
+        Some(Job::new(cod, a, b, Work::Reshape { width, height, images, fills }))
+    }
+
+    ///flood fill of the mask or active layer starting at x,y (document coordinates)
+    pub fn fill_job(&self, x: i32, y: i32, color: Color, matching: &ColorMatch) -> Option<Job> {
+        if !self.editable() {
+            return None;
+        }
+        if x < 0 || y < 0 || x >= self.width() as i32 || y >= self.height() as i32 {
+            return None;
+        }
+        let mask = self.mask_flag.get();
+        let image = if mask { self.mask.borrow().clone() } else { self.image.borrow().clone() };
+        //the mask itself is filled plainly, the image only where the mask lets paint through
+        let sample = if matching.sample_all && !mask { Some(self.flattened()) } else { None };
+        let clip = self.clip();
+        let matching = *matching;
+        Some(Job::new("fill", 0.0, 0, Work::Fill { mask, x, y, color, matching, sample, clip, image }))
+    }
+
+    ///put the result of a job into the document as one undo step
+    pub fn finish_job(&self, done: Done) {
+        if let Work::Reshape { ref images, .. } = done.work {
+            //the job is stale if layers were added or removed meanwhile
+            if images.len() != self.layers.borrow().len() + 1 {
+                return;
+            }
+            //all layers change, so the step keeps the whole stack
+            if self.layers.borrow().len() > 1 {
+                self.undo_save_layers(&done.label);
+            } else {
+                self.undo_save(&done.label);
+            }
+        } else {
+            self.undo_save(&done.label);
+        }
+        match done.work {
+            Work::Transform { mask, selection, coverage, image, .. } => {
+                let mut target = if mask { self.mask.borrow_mut() } else { self.image.borrow_mut() };
+                match (selection, coverage) {
+                    //same size result: mix it in by how much each pixel is selected
+                    (Some(rect), Some(coverage)) if image.width() == rect.width && image.height() == rect.height => {
+                        let width = target.width() as i32;
+                        let height = target.height() as i32;
+                        let target = target.data_mut();
+                        for (i, (&new, &c)) in image.data().iter().zip(&coverage).enumerate() {
+                            let x = rect.x + i as i32 % rect.width as i32;
+                            let y = rect.y + i as i32 / rect.width as i32;
+                            if c > 0 && x >= 0 && y >= 0 && x < width && y < height {
+                                let old = &mut target[(y * width + x) as usize];
+                                *old = layer::mix(*old, new, c);
+                            }
+                        }
+                    },
+                    (Some(rect), _) => {
+                        //clear only under selection
+                        if mask {
+                            target.rect(rect.x, rect.y, rect.width, rect.height, Color::rgba(255,255,255,25));
+                            target.rect(rect.x, rect.y, rect.width, rect.height, Color::rgba(0,0,0,25));
+                        } else {
+                            //set, not painted: a transparent background must clear upper layers
+                            let background = self.background();
+                            let width = target.width() as i32;
+                            let x0 = cmp::min(cmp::max(rect.x, 0), width);
+                            let x1 = cmp::min(rect.x + rect.width as i32, width);
+                            let y1 = cmp::min(rect.y + rect.height as i32, target.height() as i32);
+                            let data = target.data_mut();
+                            for y in cmp::max(rect.y, 0)..y1 {
+                                for color in data[(y * width + x0) as usize..(y * width + cmp::max(x0, x1)) as usize].iter_mut() {
+                                    *color = background;
+                                }
+                            }
+                        }
+                        target.image(rect.x, rect.y, image.width(), image.height(), image.data());
+                    },
+                    (None, _) => {
+                        target.clear();
+                        target.image(0, 0, image.width(), image.height(), image.data());
+                    },
+                }
+            },
+            Work::Reshape { mut images, .. } => {
+                let active = self.active_layer.get();
+                let selected = self.selection.borrow().is_some();
+                let others = images.split_off(2);
+                *self.mask.borrow_mut() = images.pop().unwrap();
+                *self.image.borrow_mut() = images.pop().unwrap();
+                let mut others = others.into_iter();
+                for (n, layer) in self.layers.borrow_mut().iter_mut().enumerate() {
+                    if n != active {
+                        layer.image = others.next().unwrap();
+                    }
+                }
+                //the selection follows what the mask kept of it
+                let bounds = selection::mask_bounds(self.mask.borrow().data(), self.width());
+                match bounds {
+                    Some(bounds) if selected => self.put_selection(Some(Selection::free(bounds))),
+                    _ => {
+                        if selected {
+                            self.mask.borrow_mut().set(selection::mask_color(255));
+                            self.mask_enabled.set(false);
+                        }
+                        self.put_selection(None);
+                    },
+                }
+            },
+            Work::Fill { mask, image, .. } => {
+                let mut target = if mask { self.mask.borrow_mut() } else { self.image.borrow_mut() };
+                image.mode().set(target.mode().get());
+                *target = image;
+            },
+        }
+        self.undo_commit();
+        self.mask_changed.set(true);
+    }
+
+    ///called after every change of the undo history
+    pub fn on_history_change<T: Fn(&Self) + 'static>(&self, func: T) -> &Self {
+        *self.history_callback.borrow_mut() = Some(Arc::new(func));
+        self
+    }
+
+    pub fn emit_history_change(&self) {
+        if let Some(ref history_callback) = *self.history_callback.borrow() {
+            history_callback(self);
+        }
+    }
+
+    ///all layers, the pixels of the active one are in self.image
+    pub fn layers(&self) -> Ref<'_, Vec<Layer>> {
+        self.layers.borrow()
+    }
+
+    pub fn active_layer(&self) -> usize {
+        self.active_layer.get()
+    }
+
+    ///what clearing leaves: the bottom layer is painted white, upper layers become transparent
+    fn background(&self) -> Color {
+        if self.active_layer.get() == 0 {
+            Color::rgba(255, 255, 255, 255)
+        } else {
+            Color::rgba(0, 0, 0, 0)
+        }
+    }
+
+    ///false if painting would touch a locked layer
+    fn editable(&self) -> bool {
+        self.mask_flag.get() || !self.layers.borrow()[self.active_layer.get()].locked
+    }
+
+    ///move pixels of the active layer back into the stack, so all layers can be handled alike
+    fn park_active(&self) {
+        let mut layers = self.layers.borrow_mut();
+        mem::swap(&mut layers[self.active_layer.get()].image, &mut *self.image.borrow_mut());
+    }
+
+    ///take pixels of the active layer out of the stack, opposite of park_active
+    fn unpark_active(&self) {
+        let mut layers = self.layers.borrow_mut();
+        let image = mem::replace(&mut layers[self.active_layer.get()].image, Image::new(0, 0));
+        image.mode().set(self.mask.borrow().mode().get());
+        *self.image.borrow_mut() = image;
+    }
+
+    ///forget all steps, the document as it is becomes the starting point
+    fn reset_undo(&self) {
+        self.with_history(|history, state| history.reset(state));
+        self.emit_history_change();
+    }
+
+    ///composite visible layers into data for document region x0,y0 - x1,y1
+    pub fn composite_layers(&self, data: &mut [Color], x0: i32, y0: i32, x1: i32, y1: i32) {
+        let layers = self.layers.borrow();
+        let image = self.image.borrow();
+        let stride = self.width() as usize;
+        let active = self.active_layer.get();
+        let sources: Vec<(&[Color], BlendMode, u8)> = layers.iter().enumerate()
+            .filter(|&(_, layer)| layer.visible)
+            .map(|(n, layer)| (if n == active { image.data() } else { layer.image.data() }, layer.blend, layer.opacity))
+            .collect();
+        let (x0, x1) = (x0 as usize, x1 as usize);
+        let rows = &mut data[y0 as usize * stride..y1 as usize * stride];
+        parallel::for_strips(rows, stride, |first, strip| {
+            for (row, line) in strip.chunks_mut(stride).enumerate() {
+                let start = (y0 as usize + first + row) * stride;
+                for x in x0..x1 {
+                    let mut color = Color::rgba(0, 0, 0, 0);
+                    for &(source, blend, opacity) in &sources {
+                        color = layer::blend(blend, color, source[start + x], opacity);
+                    }
+                    line[x] = color;
+                }
+            }
+        });
+    }
+
+    ///image made of all visible layers
+    pub fn flattened(&self) -> Image {
+        let plain = {
+            let layers = self.layers.borrow();
+            layers.len() == 1 && layers[0].is_plain()
+        };
+        if plain {
+            return self.image.borrow().clone();
+        }
+        let width = self.width();
+        let height = self.height();
+        let mut data = vec![Color::rgba(0, 0, 0, 0); (width * height) as usize];
+        self.composite_layers(&mut data, 0, 0, width as i32, height as i32);
+        Image::from_data(width, height, data.into_boxed_slice()).unwrap()
+    }
+
+    ///make layer n the one painted on
+    pub fn select_layer(&self, n: usize) {
+        if n >= self.layers.borrow().len() || n == self.active_layer.get() {
+            return;
+        }
+        self.with_history(|history, state| history.select_layer(n, state));
+        self.emit_history_change();
+    }
+
+    ///start an undo step that adds, removes or reorders layers, undo_commit ends it
+    fn undo_save_layers(&self, label: &str) {
+        self.with_history(|history, state| history.begin_layers(label, state));
+    }
+
+    ///insert layer above the active one and select it
+    fn insert_layer(&self, label: &str, layer: Layer) {
+        self.undo_save_layers(label);
+        self.park_active();
+        let n = self.active_layer.get() + 1;
+        self.layers.borrow_mut().insert(n, layer);
+        self.active_layer.set(n);
+        self.unpark_active();
+        self.undo_commit();
+    }
+
+    ///add a new transparent layer above the active one
+    pub fn new_layer(&self) {
+        let count = self.layer_count.get() + 1;
+        self.layer_count.set(count);
+        let image = Image::from_color(self.width(), self.height(), Color::rgba(0, 0, 0, 0));
+        self.insert_layer("New layer", Layer::new(&format!("Layer {}", count), image));
+    }
+
+    pub fn duplicate_layer(&self) {
+        let n = self.active_layer.get();
+        let mut layer = self.layers.borrow()[n].clone();
+        layer.image = self.image.borrow().clone();
+        layer.name = format!("{} copy", layer.name);
+        self.insert_layer("Duplicate layer", layer);
+    }
+
+    ///merge active layer into the one below, returns false if not possible
+    pub fn merge_down(&self) -> bool {
+        let n = self.active_layer.get();
+        if n == 0 || !self.layers.borrow()[n].visible || self.layers.borrow()[n - 1].locked {
+            return false;
+        }
+        self.undo_save_layers("Merge down");
+        self.park_active();
+        {
+            let mut layers = self.layers.borrow_mut();
+            let top = layers.remove(n);
+            let below = &mut layers[n - 1];
+            for (backdrop, source) in below.image.data_mut().iter_mut().zip(top.image.data().iter()) {
+                *backdrop = layer::blend(top.blend, *backdrop, *source, top.opacity);
+            }
+        }
+        self.active_layer.set(n - 1);
+        self.unpark_active();
+        self.undo_commit();
+        true
+    }
+
+    ///replace all layers with one made of the visible ones
+    pub fn flatten(&self) {
+        self.undo_save_layers("Flatten");
+        let image = self.flattened();
+        *self.layers.borrow_mut() = vec![Layer::new("Background", Image::new(0, 0))];
+        image.mode().set(self.mask.borrow().mode().get());
+        *self.image.borrow_mut() = image;
+        self.active_layer.set(0);
+        self.undo_commit();
+    }
+
+    ///move active layer up (towards the top) or down in the stack
+    pub fn move_layer(&self, up: bool) {
+        let n = self.active_layer.get();
+        let len = self.layers.borrow().len();
+        let m = if up && n + 1 < len {
+            n + 1
+        } else if !up && n > 0 {
+            n - 1
+        } else {
+            return;
+        };
+        self.undo_save_layers("Move layer");
+        self.layers.borrow_mut().swap(n, m);
+        self.active_layer.set(m);
+        self.undo_commit();
+    }
+
+    ///remove active layer, the last one cannot be deleted
+    pub fn delete_layer(&self) -> bool {
+        let n = self.active_layer.get();
+        if self.layers.borrow().len() < 2 {
+            return false;
+        }
+        self.undo_save_layers("Delete layer");
+        self.layers.borrow_mut().remove(n);
+        let n = if n > 0 { n - 1 } else { 0 };
+        self.active_layer.set(n);
+        self.unpark_active();
+        self.undo_commit();
+        true
+    }
+
+    pub fn set_layer_opacity(&self, n: usize, opacity: u8) {
+        let opacity = cmp::min(opacity, 100);
+        if self.layers.borrow()[n].opacity != opacity {
+            //one step for changes one after the other, as from a slider
+            self.with_history(|history, state| history.continue_layers("Layer opacity", state));
+            self.layers.borrow_mut()[n].opacity = opacity;
+            self.undo_commit();
+        }
+    }
+
+    pub fn set_layer_blend(&self, n: usize, blend: BlendMode) {
+        if self.layers.borrow()[n].blend != blend {
+            self.change_layer("Blend mode", n, |layer| layer.blend = blend);
+        }
+    }
+
+    pub fn set_layer_visible(&self, n: usize, visible: bool) {
+        if self.layers.borrow()[n].visible != visible {
+            self.change_layer(if visible { "Show layer" } else { "Hide layer" }, n, |layer| layer.visible = visible);
+        }
+    }
+
+    pub fn set_layer_locked(&self, n: usize, locked: bool) {
+        if self.layers.borrow()[n].locked != locked {
+            self.change_layer(if locked { "Lock layer" } else { "Unlock layer" }, n, |layer| layer.locked = locked);
+        }
+    }
+
+    pub fn rename_layer(&self, n: usize, name: &str) {
+        if self.layers.borrow()[n].name != name {
+            self.change_layer("Rename layer", n, |layer| layer.name = name.to_owned());
+        }
+    }
+
+    ///change a property of layer n as an undo step named label
+    fn change_layer<F: FnOnce(&mut Layer)>(&self, label: &str, n: usize, change: F) {
+        self.undo_save_layers(label);
+        change(&mut self.layers.borrow_mut()[n]);
+        self.undo_commit();
+    }
+
+    ///run f with the history and the state it keeps track of
+    fn with_history<T, F: FnOnce(&mut History, State) -> T>(&self, f: F) -> T {
+        let mut image = self.image.borrow_mut();
+        let mut mask = self.mask.borrow_mut();
+        let mut selection = self.selection.borrow().clone();
+        let mut layers = self.layers.borrow_mut();
+        let mut active = self.active_layer.get();
+        let result = f(&mut self.history.borrow_mut(), State {
+            image: &mut image,
+            mask: &mut mask,
+            selection: &mut selection,
+            layers: &mut layers,
+            active: &mut active,
+        });
+        self.active_layer.set(active);
+        if selection != *self.selection.borrow() {
+            *self.selection.borrow_mut() = selection;
+        }
+        //the mask may have changed under a free selection
+        *self.selection_edges.borrow_mut() = None;
+        result
+    }
+
+    /// call it before starting an operation named label: changes made
+    /// since the last call are recorded as one undo step
+    pub fn undo_save(&self, label: &str) {
+        self.with_history(|history, state| history.begin(label, state));
+        self.emit_history_change();
+    }
+
+    /// record the operation started with undo_save as one undo step
+    pub fn undo_commit(&self) {
+        self.with_history(|history, state| history.checkpoint(state));
+        self.emit_history_change();
+    }
+
+    ///label of the step undo would revert
+    pub fn undo_label(&self) -> Option<String> {
+        self.history.borrow().undo_label().map(|label| label.to_owned())
+    }
+
+    ///label of the step redo would apply
+    pub fn redo_label(&self) -> Option<String> {
+        self.history.borrow().redo_label().map(|label| label.to_owned())
+    }
+
+    ///bounds of the selection in document coordinates
+    pub fn selection(&self) -> Option<Rect> {
+        self.selection.borrow().as_ref().map(|selection| selection.rect)
+    }
+
+    ///selection with its shape
+    pub fn selection_shape(&self) -> Option<Selection> {
+        self.selection.borrow().clone()
+    }
+
+    ///select a rectangle as an undoable step
+    pub fn set_selection(&self, selection: Option<Rect>) {
+        self.select(selection.map(Selection::rectangle));
+    }
+
+    ///replace selection as an undoable step, None deselects
+    pub fn select(&self, selection: Option<Selection>) {
+        match selection {
+            Some(selection) => self.select_with(selection, Combine::Replace),
+            None => self.deselect(),
+        }
+    }
+
+    ///combine a shape with the current selection as an undoable step
+    pub fn select_with(&self, selection: Selection, combine: Combine) {
+        let width = self.width() as i32;
+        let height = self.height() as i32;
+        let rect = selection.rect;
+        let mut coverage = vec![0u8; (width * height) as usize];
+        for (i, c) in selection.coverage().into_iter().enumerate() {
+            let x = rect.x + i as i32 % rect.width as i32;
+            let y = rect.y + i as i32 / rect.width as i32;
+            if x >= 0 && y >= 0 && x < width && y < height {
+                coverage[(y * width + x) as usize] = c;
+            }
+        }
+        let shape = if combine == Combine::Replace { Some(selection) } else { None };
+        self.combine_selection("Select", &coverage, shape, combine);
+    }
+
+    ///put coverage of the whole document into the mask, combined with what is selected;
+    ///shape is what the ants follow, None to follow the mask
+    fn combine_selection(&self, label: &str, coverage: &[u8], shape: Option<Selection>, combine: Combine) {
+        self.undo_save(label);
+        self.put_coverage(coverage, shape, combine);
+        self.undo_commit();
+        self.mask_changed.set(true);
+    }
+
+    ///combine_selection without its own undo step
+    fn put_coverage(&self, coverage: &[u8], shape: Option<Selection>, combine: Combine) {
+        let selected = self.selection.borrow().is_some();
+        let bounds = {
+            let mut mask = self.mask.borrow_mut();
+            for (m, &c) in mask.data_mut().iter_mut().zip(coverage) {
+                let current = if selected { m.r() } else { 0 };
+                *m = selection::mask_color(combine.apply(current, c));
+            }
+            selection::mask_bounds(mask.data(), self.width())
+        };
+        match bounds {
+            Some(bounds) => {
+                self.put_selection(Some(shape.unwrap_or_else(|| Selection::free(bounds))));
+                self.mask_enabled.set(true);
+            },
+            //nothing left selected
+            None => {
+                self.mask.borrow_mut().set(selection::mask_color(255));
+                self.put_selection(None);
+                self.mask_enabled.set(false);
+            },
+        }
+    }
+
+    ///select nothing, so everything can be painted
+    pub fn deselect(&self) {
+        if self.selection.borrow().is_none() {
+            return;
+        }
+        self.undo_save("Deselect");
+        self.mask.borrow_mut().set(selection::mask_color(255));
+        self.put_selection(None);
+        self.undo_commit();
+        self.mask_enabled.set(false);
+        self.mask_changed.set(true);
+    }
+
+    pub fn select_all(&self) {
+        self.select(Some(Selection::rectangle(Rect::new(0, 0, self.width(), self.height()))));
+    }
+
+    ///select what is not selected
+    pub fn invert_selection(&self) {
+        if self.mask_flag.get() {
+            return;
+        }
+        let selected = self.selection.borrow().is_some();
+        let coverage: Vec<u8> = self.mask.borrow().data().iter()
+            .map(|m| if selected { 255 - m.r() } else { 0 })
+            .collect();
+        if selected {
+            self.combine_selection("Invert selection", &coverage, None, Combine::Replace);
+        } else {
+            self.select_all();
+        }
+    }
+
+    ///what was painted on the quick mask becomes the selection
+    pub fn selection_from_mask(&self) {
+        let coverage: Vec<u8> = self.mask.borrow().data().iter().map(|m| m.r()).collect();
+        self.combine_selection("Selection from mask", &coverage, None, Combine::Replace);
+    }
+
+    ///select the opaque part of the active layer
+    pub fn selection_from_alpha(&self) {
+        let coverage: Vec<u8> = self.image.borrow().data().iter().map(|c| c.a()).collect();
+        self.combine_selection("Selection from alpha", &coverage, None, Combine::Replace);
+    }
+
+    ///change the edge of the selection, whose outline then follows the mask
+    pub fn refine_selection(&self, refine: Refine) {
+        if self.mask_flag.get() || self.selection.borrow().is_none() {
+            return;
+        }
+        let coverage: Vec<u8> = self.mask.borrow().data().iter().map(|m| m.r()).collect();
+        let coverage = refine.apply(&coverage, self.width(), self.height());
+        self.combine_selection(refine.label(), &coverage, None, Combine::Replace);
+    }
+
+    ///lift the selected pixels of the active layer so they can be moved, leaving
+    ///transparency or the background behind; true if something is floating
+    pub fn float_selection(&self, transparent: bool) -> bool {
+        if self.floating.borrow().is_some() {
+            return true;
+        }
+        let rect = match self.selection() {
+            Some(rect) if !self.mask_flag.get() && self.editable() => rect,
+            _ => return false,
+        };
+        let coverage: Vec<u8> = self.mask.borrow().data().iter().map(|m| m.r()).collect();
+        let background = if transparent { Color::rgba(0, 0, 0, 0) } else { self.background() };
+        //one undo step from lifting to putting down
+        self.undo_save("Move selection");
+        let mut floating = Floating::lift(&self.image.borrow(), &coverage, rect, background);
+        floating.render(&mut self.image.borrow_mut(), Interpolation::Nearest);
+        *self.floating.borrow_mut() = Some(floating);
+        true
+    }
+
+    ///where the floating pixels are
+    pub fn floating_quad(&self) -> Option<Quad> {
+        self.floating.borrow().as_ref().map(|floating| floating.quad())
+    }
+
+    ///size of the floating pixels before scaling
+    pub fn floating_size(&self) -> Option<(u32, u32)> {
+        self.floating.borrow().as_ref().map(|floating| (floating.width(), floating.height()))
+    }
+
+    ///move, scale, turn or distort the floating pixels with a quick preview,
+    ///false if quad is folded and nothing changed
+    pub fn place_floating(&self, quad: Quad) -> bool {
+        let mut floating = self.floating.borrow_mut();
+        let floating = match *floating {
+            Some(ref mut floating) => floating,
+            None => return false,
+        };
+        if !floating.place(quad) {
+            return false;
+        }
+        floating.render(&mut self.image.borrow_mut(), Interpolation::Nearest);
+        true
+    }
+
+    ///put the floating pixels down where they are, resampled with interpolation;
+    ///the selection follows them
+    pub fn commit_floating(&self, interpolation: Interpolation) {
+        let mut floating = match self.floating.borrow_mut().take() {
+            Some(floating) => floating,
+            None => return,
+        };
+        floating.render(&mut self.image.borrow_mut(), interpolation);
+        let coverage = floating.placed_coverage(self.width(), self.height());
+        //a shape only moved keeps its outline, otherwise the ants follow the mask
+        let shape = match (self.selection_shape(), floating.offset()) {
+            (Some(selection), Some((dx, dy))) => Some(selection.translated(dx, dy)),
+            _ => None,
+        };
+        self.put_coverage(&coverage, shape, Combine::Replace);
+        self.undo_commit();
+        self.mask_changed.set(true);
+    }
+
+    ///put the pixels back where they were taken from
+    pub fn cancel_floating(&self) {
+        if let Some(floating) = self.floating.borrow_mut().take() {
+            *self.image.borrow_mut() = floating.original;
+            self.undo_commit();
+        }
+    }
+
+    fn put_selection(&self, selection: Option<Selection>) {
+        *self.selection.borrow_mut() = selection;
+        *self.selection_edges.borrow_mut() = None;
+    }
+
+    ///lines the marching ants follow, in document coordinates
+    pub fn selection_edges(&self) -> Vec<((i32, i32), (i32, i32))> {
+        let selection = self.selection.borrow();
+        let selection = match *selection {
+            Some(ref selection) => selection,
+            None => return Vec::new(),
+        };
+        if selection.shape != Shape::Free {
+            let outline = selection.outline();
+            return outline.iter().enumerate().map(|(n, &p)| (p, outline[(n + 1) % outline.len()])).collect();
+        }
+        self.selection_edges.borrow_mut()
+            .get_or_insert_with(|| selection::mask_edges(self.mask.borrow().data(), self.width(), self.height()))
+            .clone()
+    }
+
+    ///how much pixel x,y is selected, 0..255; everything is when there is no selection
+    pub fn selected(&self, x: i32, y: i32) -> u8 {
+        if !self.mask_enabled.get() || self.mask_flag.get() {
+            return 255;
+        }
+        self.mask.borrow().pixcol(x, y).r()
+    }
+
+    ///how much each pixel of rect is selected according to the mask, row by row
+    fn coverage_in(&self, rect: Rect) -> Vec<u8> {
+        let mask = self.mask.borrow();
+        let (width, height) = (mask.width() as i32, mask.height() as i32);
+        let mut coverage = Vec::with_capacity((rect.width * rect.height) as usize);
+        for y in rect.y..rect.y + rect.height as i32 {
+            for x in rect.x..rect.x + rect.width as i32 {
+                let inside = x >= 0 && y >= 0 && x < width && y < height;
+                coverage.push(if inside { mask.data()[(y * width + x) as usize].r() } else { 0 });
+            }
+        }
+        coverage
+    }
+
+    ///how much of each pixel painting may change, None if all of it
+    fn clip(&self) -> Option<Vec<u8>> {
+        if !self.mask_enabled.get() || self.mask_flag.get() {
+            return None;
+        }
+        Some(self.mask.borrow().data().iter().map(|m| m.r()).collect())
+    }
+
+    pub fn mode_set(&self, mode: Mode) {
+        self.image.borrow().mode().set(mode);
+        self.mask.borrow().mode().set(mode);
+    }
+
+    /// retrieve image from undo stack, returns false if there was nothing to undo
+    pub fn undo (&self) -> bool {
+        let done = self.with_history(|history, state| history.undo(state));
+        if done {
+            //size, mask and selection may have changed too
+            self.mask_changed.set(true);
+            self.emit_history_change();
+        }
+        done
+    }
+    
+    pub fn redo (&self) -> bool {
+        let done = self.with_history(|history, state| history.redo(state));
+        if done {
+            self.mask_changed.set(true);
+            self.emit_history_change();
+        }
+        done
+    }
+    
+    ///select the region of color similar to the one at (x,y), combined with the current selection
+    pub fn magicwand (& self, x: i32 , y: i32, matching: &ColorMatch, combine: Combine){
+        if x < 0 || y < 0 || x >= self.width() as i32 || y >= self.height() as i32 {
+            return;
+        }
+        let width = self.width();
+        let height = self.height();
+        let coverage = if matching.sample_all {
+            region::similar(self.flattened().data(), width, height, x, y, matching)
+        } else {
+            region::similar(self.image.borrow().data(), width, height, x, y, matching)
+        };
+        self.combine_selection("Magic wand", &coverage, None, combine);
+    }
+
+   ///wrapper for filling an image within a canvas (document coordinates)
+    pub fn fill (&self, x: i32 , y: i32, color: Color, matching: &ColorMatch){
+        if let Some(job) = self.fill_job(x, y, color, matching) {
+            self.run_job(job);
+        }
+    }
+
+    ///paste an image into current canvas starting at x,y with transparency , mask and view support
+    #[inline]
+    pub fn paste_image (&self, x: i32, y:i32, opacity: u8, buffer: Image, ){
+        let w = buffer.width() as i32;
+        let h = buffer.height() as i32;
+        let xc=x-w/2; //center buffer at cursor 
+        let yc=y-h/2;
+        let data = buffer.into_data();
+        let mut i:usize = 0;
+        let mut r;
+        let mut g;
+        let mut b;
+        let mut a;
+        let op = opacity as f32;
+        for y1 in yc..yc+h {
+            for x1 in xc..xc+w {
+                if i < data.len(){
+                    r = data[i].r();
+                    g = data[i].g();
+                    b = data[i].b();
+                    a = data[i].a();
+                    if a > 0 {a = (a as f32 /100.0 * op) as u8}
+                    self.pixel(x1,y1,Color::rgba(r,g,b,a));
+                }
+                i += 1;
+            }
+        }
+    }
+    
+    ///paste internal copy buffer into current canvas starting at x,y with transparency , mask and view support
+    #[inline]
+    pub fn paste_buffer (&self, x: i32, y:i32, opacity: u8 ){
+        let buffer = self.copy_buffer.borrow().clone();
+        let w = buffer.width() as i32;
+        let h = buffer.height() as i32;
+        let xc=x-w/2; //center buffer at cursor 
+        let yc=y-h/2;
+        let data = buffer.into_data();
+        let mut i:usize = 0;
+        let mut r;
+        let mut g;
+        let mut b;
+        let mut a;
+
+        for y1 in yc..yc+h {
+            for x1 in xc..xc+w {
+                if i < data.len(){
+                    r = data[i].r();
+                    g = data[i].g();
+                    b = data[i].b();
+                    a = data[i].a();
+                    if a > 0 {a = opacity}
+                    self.pixel(x1,y1,Color::rgba(r,g,b,a));
+                }
+                i += 1;
+            }
+        }
+    }
+
+    pub fn paint_on_mask(&self) {
+        self.mask_changed.set(true);
+        if self.mask_flag.get(){
+            self.mask_flag.set(false);
+            //what was painted is the selection now, unless the mask stayed clear
+            let clear = self.mask.borrow().data().iter().all(|m| m.r() == 255);
+            if !clear || self.selection.borrow().is_some() {
+                self.selection_from_mask();
+            }
+        }else{
+            self.mask_flag.set(true);
+            self.enable_mask(true);
+        }
+    }
+/*
+    pub fn switch_mask(&self) {
+        let mut image = self.image.borrow_mut();
+        let image2 = image.clone();
+        let mut mask = self.mask.borrow_mut();
+        *image = mask.clone();
+        *mask = image2;
+        self.mask_changed.set(true);
+        if self.mask_flag.get(){
+            self.mask_flag.set(false); 
+            self.enable_mask(true);
+        }else{
+            self.mask_flag.set(true);
+            self.enable_mask(false);
+        }
+    }
+*/
+    pub fn clear_mask(& self) {
+        self.undo_save("Clear mask");
+        self.mask.borrow_mut().set(Color::rgba(255, 0, 0,25));
+        //the mask is the selection
+        self.put_selection(None);
+        self.undo_commit();
+        self.mask_changed.set(true);
+    }
+
+    pub fn enable_mask(& self, status: bool){
+        self.mask_enabled.set(status);
+    }
+
+    pub fn mask_flag(& self) -> bool {
+        self.mask_flag.get()
+    }
+
+    pub fn mask_enabled(& self) -> bool {
+        self.mask_enabled.get()
+    }
+
+    ///true once after the mask changed, so it can be redrawn
+    pub fn take_mask_changed(& self) -> bool {
+        self.mask_changed.replace(false)
+    }
+
+    pub fn set_mask_changed(& self) {
+        self.mask_changed.set(true);
+    }
+
+    ///the mask is the selection, so this inverts it
+    pub fn invert_mask(&self) {
+        self.invert_selection();
+    }
+
+    ///Draw some text on canvas
+    pub fn text(&self, text: &str, font_path: &str, x0: i32, y0: i32, color: Color, size: i32){
+        //self.undo_save();  //save state for undo
+        let text = text;
+        let size = size as f32;
+        //using rusttype to render text
+
+/*        // Load the font at compile time !
+        #[cfg(target_os = "linux")]
+        let font_data = include_bytes!("/usr/share/fonts/gnu-free/FreeMonoBold.ttf");
+        #[cfg(target_os = "redox")]
+        let font_data = include_bytes!("/ui/fonts/Mono/Fira/Bold.ttf");
+        let collection = FontCollection::from_bytes(font_data as &[u8]);
+*/        
+        //Load font at runtime
+        let mut f = match File::open(font_path.to_owned()) {
+            Err(e) => return,
+            Ok(f) =>f,
+        };
+        let mut buffer = Vec::new();
+        
+        f.read_to_end(&mut buffer).unwrap();
+        
+        let collection = FontCollection::from_bytes(buffer);
+
+        let font = collection.into_font().unwrap();
+        let scale = Scale {x: size, y: size};
+        let start = point(x0 as f32, y0 as f32);
+        let opacity = color.a() as f32;
+        for glyph in font.layout(text, scale, start) {
+            if let Some(bounding_box) = glyph.pixel_bounding_box() {
+                glyph.draw(|x, y, v| self.pixel(
+                    x as i32 + bounding_box.min.x ,
+                    y as i32 + bounding_box.min.y ,
+                    Color::rgba(color.r(), color.g(), color.b(), (v * opacity) as u8)
+                ));
+            }
+        }
+    }
+
+/* Here unfortunately I have to reimplement not only the pixel function to
+   take care of mask but also the other graphics functions
+   because in rust I cannot override the pixel function !! 
+*/ 
+    ///pixel function with mask support, x and y are document coordinates
+    #[inline]
+    pub fn pixel(&self , x: i32, y: i32, color: Color) {
+        let mut color = color;
+        if self.mask_flag.get(){
+            self.mask.borrow_mut().pixel(x, y, color);
+        }else if self.editable(){
+            //if we are not painting on the mask, paint only as much as the pixel is selected
+            if self.mask_enabled.get(){
+                let selected = self.selected(x, y) as u32;
+                if selected == 0 {
+                    return;
+                }
+                color = Color::rgba(color.r(),color.g(),color.b(),(color.a() as u32 * selected / 255) as u8);
+            }
+            self.image.borrow_mut().pixel(x, y, color);
+        }
+    }
+
+    ///circle with mask support
+    pub fn circle(&self , x0: i32, y0: i32, radius: i32, color: Color) {
+        //self.image.borrow_mut().circle(x0, y0, radius, color);
+        let mut x = radius.abs();
+        let mut y = 0;
+        let mut err = -radius.abs();
+        
+        match radius {
+            radius if radius > 0 => {
+                err = 0;
+                while x >= y {
+                    self.pixel(x0 - x, y0 + y, color);
+                    self.pixel(x0 + x, y0 + y, color);
+                    self.pixel(x0 - y, y0 + x, color);
+                    self.pixel(x0 + y, y0 + x, color);
+                    self.pixel(x0 - x, y0 - y, color);
+                    self.pixel(x0 + x, y0 - y, color);
+                    self.pixel(x0 - y, y0 - x, color);
+                    self.pixel(x0 + y, y0 - x, color);
+                
+                    y += 1;
+                    err += 1 + 2*y;
+                    if 2*(err-x) + 1 > 0 {
+                        x -= 1;
+                        err += 1 - 2*x;
+                    }
+                }      
+            },
+            
+            radius if radius < 0 => {
+                while x >= y {
+                    let lasty = y;
+                    err +=y;
+                    y +=1;
+                    err += y;
+                    self.line4points(x0,y0,x,lasty,color);
+                    if err >=0 {
+                        if x != lasty{
+                           self.line4points(x0,y0,lasty,x,color);
+                        }
+                        err -= x;
+                        x -= 1;
+                        err -= x;
+                    }
+                }
+
+                },
+                     _ => {
+                            self.pixel(x0, y0, color);
+                            
+                        },
+        }
+    }
+
+    fn line4points(&self, x0: i32, y0: i32, x: i32, y: i32, color: Color){
+        self.line(x0 - x, y0 + y, x + x0, y0 + y, color);
+        //self.rect(x0 - x, y0 + y, x as u32 * 2 + 1, 1, color);
+        if y != 0 {
+            self.line(x0 - x, y0 - y, x + x0, y0 - y , color);
+            //self.rect(x0 - x, y0 - y, x as u32 * 2 + 1, 1, color);
+        }
+    }
+
+    ///Draws antialiased circle with mask support
+    pub fn wu_circle (&self, x0: i32, y0: i32, radius: i32, color: Color){
+        let r = color.r();
+        let g = color.g();
+        let b = color.b();
+        let a = color.a();
+        let mut y =0;
+        let mut x = radius;
+        let mut d =0_f64;
+        
+        self.pixel (x0+x,y0+y,color);
+        self.pixel (x0-x,y0-y,color);
+        self.pixel (x0+y,y0-x,color);
+        self.pixel (x0-y,y0+x,color);
+        
+        while x > y {
+            let di = dist(radius,y);
+            if di < d { x -= 1;}
+            let col = Color::rgba(r,g,b,(a as f64*(1.0-di)) as u8);
+            let col2 = Color::rgba(r,g,b,(a as f64*di) as u8);
+            
+            self.pixel(x0+x, y0+y, col);
+            self.pixel(x0+x-1, y0+y, col2);//-
+            self.pixel(x0-x, y0+y, col);
+            self.pixel(x0-x+1, y0+y, col2);//+
+            self.pixel(x0+x, y0-y, col);
+            self.pixel(x0+x-1, y0-y, col2);//-
+            self.pixel(x0-x, y0-y, col);
+            self.pixel(x0-x+1, y0-y, col2);//+
+            
+            self.pixel(x0+y, y0+x, col);
+            self.pixel(x0+y, y0+x-1, col2);
+            self.pixel(x0-y, y0+x, col);
+            self.pixel(x0-y, y0+x-1, col2);
+            self.pixel(x0+y, y0-x, col);
+            self.pixel(x0+y, y0-x+1, col2);
+            self.pixel(x0-y, y0-x, col);
+            self.pixel(x0-y, y0-x+1, col2);
+            d = di;
+            y += 1;
+        }
+        
+        fn dist(r: i32, y: i32) -> f64{
+            let x :f64 = ((r*r-y*y)as f64).sqrt();
+            x.ceil()-x
+        }
+    }
+    
+    
+    ///start a brush stroke at x,y (document coordinates), painting color up to its alpha
+    ///however often the stroke crosses itself
+    pub fn begin_stroke(&self, brush: Brush, color: Color, x: f32, y: f32) {
+        if !self.editable() {
+            return;
+        }
+        let (stroke, dab) = {
+            let target = if self.mask_flag.get() { self.mask.borrow() } else { self.image.borrow() };
+            Stroke::new(brush, color, &target, self.clip(), x, y)
+        };
+        *self.stroke.borrow_mut() = Some(stroke);
+        self.dab(&dab);
+    }
+
+    ///go on with the stroke to x,y, with dabs all along the way
+    pub fn stroke_to(&self, x: f32, y: f32) {
+        let dabs = match *self.stroke.borrow_mut() {
+            Some(ref mut stroke) => stroke.to(x, y),
+            None => return,
+        };
+        for dab in &dabs {
+            self.dab(dab);
+        }
+    }
+
+    ///start erasing at x,y (document coordinates) up to opacity, the brush gives the shape
+    pub fn begin_erase(&self, brush: Brush, opacity: u8, erase: Erase, x: f32, y: f32) {
+        if !self.editable() {
+            return;
+        }
+        let mask = self.mask_flag.get();
+        let (width, height) = (self.width(), self.height());
+        let to = match erase {
+            //back to whatever the history still has, the oldest state if there are fewer steps
+            Erase::History(steps) => match self.history.borrow().snapshot(mask, steps) {
+                Some(image) => Some(image),
+                None => return,
+            },
+            //the mask has no transparency, erasing it selects everything again
+            _ if mask => Some(Image::from_color(width, height, selection::mask_color(255))),
+            Erase::Transparent => None,
+            Erase::Background => Some(Image::from_color(width, height, self.background())),
+            Erase::Auto => {
+                let opaque = self.active_layer.get() == 0 && self.image.borrow().data().iter().all(|c| c.a() == 255);
+                if opaque { Some(Image::from_color(width, height, self.background())) } else { None }
+            },
+        };
+        let (stroke, dab) = {
+            let target = if mask { self.mask.borrow() } else { self.image.borrow() };
+            Stroke::eraser(brush, opacity, &target, to, self.clip(), x, y)
+        };
+        *self.stroke.borrow_mut() = Some(stroke);
+        self.dab(&dab);
+    }
+
+    pub fn end_stroke(&self) {
+        self.stroke.borrow_mut().take();
+    }
+
+    fn dab(&self, dab: &Dab) {
+        let mut stroke = self.stroke.borrow_mut();
+        let stroke = match *stroke {
+            Some(ref mut stroke) => stroke,
+            None => return,
+        };
+        if stroke.tip() == Tip::Buffer && !stroke.erasing() {
+            self.paste_buffer(dab.x as i32, dab.y as i32, (dab.flow * 255.0) as u8);
+        } else {
+            let mut target = if self.mask_flag.get() { self.mask.borrow_mut() } else { self.image.borrow_mut() };
+            stroke.paint(&mut target, dab);
+        }
+    }
+
+
+    ///paint gradient over the layer for a drag from start to end, through pixel so it
+    ///blends and stays in the selection
+    pub fn gradient(&self, gradient: &Gradient, start: (f32, f32), end: (f32, f32)) {
+        for y in 0..self.height() as i32 {
+            for x in 0..self.width() as i32 {
+                let color = gradient.color(start, end, x, y);
+                if color.a() > 0 {
+                    self.pixel(x, y, color);
+                }
+            }
+        }
+    }
+
+    //rectangle with mask support
+    pub fn rect(&self, x: i32, y: i32 ,lenght: u32, width: u32, color: Color){
+        //self.image.borrow_mut().rect(x ,y, lenght, width, color);
+        let lenght = lenght as i32;
+        let width = width as i32;
+        self.line(x, y, x+lenght, y, color);
+        self.line(x, y+1, x, y+width, color);
+        self.line(x+1 ,y+width, x+lenght-1, y+width, color);
+        self.line(x+lenght,y+width,x+lenght, y+1, color);
+    }
+    
+    ///line with mask support
+    pub fn line(&self, argx1: i32, argy1: i32, argx2: i32, argy2: i32, color: Color) {
+        let mut x = argx1;
+        let mut y = argy1;
+
+        let dx = if argx1 > argx2 { argx1 - argx2 } else { argx2 - argx1 };
+        let dy = if argy1 > argy2 { argy1 - argy2 } else { argy2 - argy1 };
+
+        let sx = if argx1 < argx2 { 1 } else { -1 };
+        let sy = if argy1 < argy2 { 1 } else { -1 };
+
+        let mut err = if dx > dy { dx } else {-dy} / 2;
+        let mut err_tolerance;
+
+        loop {
+            self.pixel(x, y, color);
+
+            if x == argx2 && y == argy2 { break };
+
+            err_tolerance = 2 * err;
+
+            if err_tolerance > -dx { err -= dy; x += sx; }
+            if err_tolerance < dy { err += dx; y += sy; }
+        }
+    }
+    
+    /// wu_line with mask support
+    pub fn wu_line (&self, x0: i32, y0: i32, x1: i32, y1: i32, color: Color) {
+        
+        let mut x0 = x0 as f64;
+        let mut y0 = y0 as f64;
+        let mut x1 = x1 as f64;
+        let mut y1 = y1 as f64;
+        let r = color.r();
+        let g = color.g();
+        let b = color.b();
+        let a = color.a() as f64;
+        
+        fn ipart (x: f64) -> i32 {
+            x.trunc() as i32
+        }
+        fn round (x: f64) -> i32 {
+            ipart(x+0.5) as i32
+        }
+        fn fpart (x: f64) -> f64 {
+            if x <0.0 { return 1.0-(x-x.floor());}
+            x-x.floor() 
+        }
+        fn rfpart(x: f64) -> f64 {
+            1.0-fpart(x)
+        }
+        fn chkalpha (mut alpha :f64) -> u8 {
+             if alpha > 255.0 { alpha = 255.0};
+             if alpha < 0.0 {alpha = 0.0};
+             alpha as u8
+        }
+        
+        let steep :bool = (y1-y0).abs() > (x1-x0).abs();
+        let mut temp;
+        if steep {
+            temp = x0; x0 = y0; y0 = temp;
+            temp = x1; x1 = y1; y1 = temp;
+        }
+        if x0 > x1 {
+            temp = x0; x0 = x1; x1 = temp;
+            temp = y0; y0 = y1; y1 = temp;
+        }
+        let dx = x1 -x0;
+        let dy = y1- y0;
+        let gradient = dy/dx;
+        
+        let mut xend: f64 = (x0 as f64).round() ;
+        let mut yend: f64 = y0 + gradient * (xend - x0);
+        let mut xgap: f64 = rfpart(x0+0.5);
+        let xpixel1 = xend as i32;
+        let ypixel1 = (ipart (yend)) as i32;
+        
+        if steep {
+            self.pixel(ypixel1, xpixel1, Color::rgba(r,g,b,chkalpha(rfpart(yend)*xgap*a)));
+            self.pixel(ypixel1+1, xpixel1, Color::rgba(r,g,b,chkalpha(fpart(yend)*xgap*a)));
+        }else{
+            self.pixel(xpixel1, ypixel1, Color::rgba(r,g,b,chkalpha(rfpart(yend)*xgap*a)));
+            self.pixel(xpixel1+1, ypixel1, Color::rgba(r,g,b,chkalpha(fpart(yend)*xgap*a)));
+        }
+        let mut intery :f64 = yend + gradient;
+        xend = x1.round();
+        yend = y1 + gradient * (xend-x1);
+        xgap = fpart(x1 + 0.5);
+        let xpixel2 = xend as i32;
+        let ypixel2 = ipart(yend) as i32;
+        if steep {
+            self.pixel(ypixel2, xpixel2, Color::rgba(r,g,b,chkalpha(rfpart(yend)*xgap*a)));
+            self.pixel(ypixel2+1, xpixel2, Color::rgba(r,g,b,chkalpha(fpart(yend)*xgap*a)));
+        }else{
+            self.pixel(xpixel2, ypixel2, Color::rgba(r,g,b,chkalpha(rfpart(yend)*xgap*a)));
+            self.pixel(xpixel2+1, ypixel2, Color::rgba(r,g,b,chkalpha(fpart(yend)*xgap*a)));
+        }
+        if steep {
+            for x in (xpixel1+1)..(xpixel2) {
+                self.pixel(ipart(intery) as i32 , x, Color::rgba(r,g,b,chkalpha(a*rfpart(intery))));
+                self.pixel(ipart(intery) as i32 + 1, x, Color::rgba(r,g,b,chkalpha(a*fpart(intery))));
+                intery += gradient;
+            }
+        }else{
+            for x in (xpixel1+1)..(xpixel2) {
+                self.pixel(x, ipart(intery) as i32, Color::rgba(r,g,b,chkalpha(a*rfpart(intery))));
+                self.pixel(x, ipart(intery) as i32 + 1, Color::rgba(r,g,b,chkalpha(a*fpart(intery))));
+                intery += gradient;
+            } 
+        }           
+    }
+
+    ///continuus brush circular shape with mask support
+    pub fn brush_line(&self, argx1: i32, argy1: i32, argx2: i32, argy2: i32, radius: i32, color: Color) {
+        let mut x = argx1;
+        let mut y = argy1;
+
+        let dx = if argx1 > argx2 { argx1 - argx2 } else { argx2 - argx1 };
+        let dy = if argy1 > argy2 { argy1 - argy2 } else { argy2 - argy1 };
+
+        let sx = if argx1 < argx2 { 1 } else { -1 };
+        let sy = if argy1 < argy2 { 1 } else { -1 };
+
+        let mut err = if dx > dy { dx } else {-dy} / 2;
+        let mut err_tolerance;
+
+        loop {
+            self.circle(x, y, radius, color);
+
+            if x == argx2 && y == argy2 { break };
+
+            err_tolerance = 2 * err;
+
+            if err_tolerance > -dx { err -= dy; x += sx; }
+            if err_tolerance < dy { err += dx; y += sy; }
+        }
+    }
+    
+    ///continuus brush rectangular shape not yet with mask support
+    pub fn rect_line(&self, argx1: i32, argy1: i32, argx2: i32, argy2: i32,lenght: u32, width: u32, color: Color) {
+        let mut x = argx1;
+        let mut y = argy1;
+
+        let dx = if argx1 > argx2 { argx1 - argx2 } else { argx2 - argx1 };
+        let dy = if argy1 > argy2 { argy1 - argy2 } else { argy2 - argy1 };
+
+        let sx = if argx1 < argx2 { 1 } else { -1 };
+        let sy = if argy1 < argy2 { 1 } else { -1 };
+
+        let mut err = if dx > dy { dx } else {-dy} / 2;
+        let mut err_tolerance;
+
+        loop {
+            self.rect(x, y, lenght, width, color);
+
+            if x == argx2 && y == argy2 { break };
+
+            err_tolerance = 2 * err;
+
+            if err_tolerance > -dx { err -= dy; x += sx; }
+            if err_tolerance < dy { err += dx; y += sy; }
+        }
+    }
+
+     ///Draws a regular polygon with mask support
+    pub fn polygon(&self, x0: i32, y0: i32, r: i32, sides: u32, angle: f32, color: Color, antialias: bool ) {
+        let mut x:Vec<i32> = Vec::new();
+        let mut y:Vec<i32> = Vec::new();
+        let i :usize = 0;
+        let sides = sides as usize;
+        //find vertices
+        for i in 0..=sides {
+            let t :f32 =angle + 2.0*PI* i as f32 /sides as f32;
+            x.push((r as f32 * t.cos()) as i32 + x0);
+            y.push((r as f32 * t.sin()) as i32 + y0);
+        }
+        
+        if antialias {
+        for i in 0..sides {
+            self.wu_line(x[i],y[i],x[i+1],y[i+1],color);
+        }
+        self.wu_line(x[sides],y[sides],x[0],y[0],color);    
+        }else{
+        for i in 0..sides {
+            self.line(x[i],y[i],x[i+1],y[i+1],color);
+        }
+        self.line(x[sides],y[sides],x[0],y[0],color);
+        }
+    }
+    
+    ///crop new image from current image (copy) tranforming pure white into transparent with mask support
+    pub fn copy_selection(&self, x: i32,y: i32,w: u32, h: u32) {
+        let rect = Rect::new(x, y, w, h);
+        //outside the document counts as not selected
+        let mut image = crop(&self.image.borrow(), rect);
+        for (col, c) in image.data_mut().iter_mut().zip(self.coverage_in(rect)) {
+            //unselected and white pixels are left out
+            if c == 0 || (col.r()==255 && col.g()==255 && col.b()==255) {
+                *col = Color::rgba(0,0,0,0);
+            }
+        }
+        *self.copy_buffer.borrow_mut() = image;
+    }
+
+    ///pixels of the active layer under the selection, faded by its shape, None without one
+    pub fn selected_image(&self) -> Option<Image> {
+        let rect = self.selection_shape()?.rect;
+        let mut image = crop(&self.image.borrow(), rect);
+        if self.mask_enabled.get() {
+            for (color, c) in image.data_mut().iter_mut().zip(self.coverage_in(rect)) {
+                *color = Color::rgba(color.r(), color.g(), color.b(), (color.a() as u32 * c as u32 / 255) as u8);
+            }
+        }
+        Some(image)
+    }
+
+    ///copy the selection, what is outside its shape becomes transparent
+    pub fn copy_selected(&self) {
+        let selection = match self.selection_shape() {
+            Some(selection) => selection,
+            None => return,
+        };
+        let rect = selection.rect;
+        self.copy_selection(rect.x, rect.y, rect.width, rect.height);
+        if self.mask_enabled.get() {
+            let coverage = self.coverage_in(rect);
+            let mut buffer = self.copy_buffer.borrow_mut();
+            for (color, c) in buffer.data_mut().iter_mut().zip(coverage) {
+                *color = Color::rgba(color.r(), color.g(), color.b(), (color.a() as u32 * c as u32 / 255) as u8);
+            }
+        }
+    }
+
+}
+///copy of rect out of image, transparent outside it
+fn crop(image: &Image, rect: Rect) -> Image {
+    let mut data = Vec::with_capacity((rect.width * rect.height) as usize);
+    for y in rect.y..rect.y + rect.height as i32 {
+        for x in rect.x..rect.x + rect.width as i32 {
+            let inside = x >= 0 && y >= 0 && x < image.width() as i32 && y < image.height() as i32;
+            data.push(if inside { image.data()[(y * image.width() as i32 + x) as usize] } else { Color::rgba(0, 0, 0, 0) });
+        }
+    }
+    Image::from_data(rect.width, rect.height, data.into_boxed_slice()).unwrap()
+}
+
+
+///image on a width x height canvas of fill, placed at x,y
+fn recanvas(image: &Image, width: u32, height: u32, x: i32, y: i32, fill: Color) -> Image {
+    let mut new_image = Image::from_color(width, height, fill);
+    new_image.mode().set(image.mode().get());
+    //columns of the old image that land on the new one
+    let x0 = cmp::max(0, -x);
+    let x1 = cmp::min(image.width() as i32, width as i32 - x);
+    if x1 > x0 {
+        for row in cmp::max(0, -y)..cmp::min(image.height() as i32, height as i32 - y) {
+            let from = (row * image.width() as i32 + x0) as usize;
+            let to = ((row + y) * width as i32 + x + x0) as usize;
+            let count = (x1 - x0) as usize;
+            new_image.data_mut()[to..to + count].copy_from_slice(&image.data()[from..from + count]);
+        }
+    }
+    new_image
+}
+
+///readable name of a transformation code, "flip_vertical" becomes "Flip vertical"
+pub fn label(cod: &str) -> String {
+    let text = cod.replace('_', " ");
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => text,
+    }
+}
+
+
+///rows of context a blur of sigma needs above and below a strip
+fn blur_margin(sigma: f32) -> u32 {
+    (2.0 * sigma.abs().max(1.0)).ceil() as u32 + 2
+}
+
+///apply op to horizontal strips of imgbuf, each one extended by margin rows so
+///the result is the same as op on the whole image
+fn filter_strips<F>(imgbuf: &image::RgbaImage, margin: u32, progress: &Progress, op: F) -> Option<image::RgbaImage>
+where F: Fn(&image::RgbaImage) -> image::RgbaImage + Sync {
+    let (width, height) = imgbuf.dimensions();
+    let stride = 4 * width as usize;
+    let raw: &[u8] = imgbuf;
+    let mut out = vec![0; raw.len()];
+    let done = parallel::for_strips_with(&mut out, stride, progress, |first, strip| {
+        let rows = strip.len() / stride;
+        let top = (first as u32).saturating_sub(margin);
+        let bottom = cmp::min(first as u32 + rows as u32 + margin, height);
+        let part = if top == 0 && bottom == height {
+            op(imgbuf)
+        } else {
+            let data = raw[top as usize * stride..bottom as usize * stride].to_vec();
+            op(&ImageBuffer::from_raw(width, bottom - top, data).unwrap())
+        }.into_raw();
+        let skip = (first - top as usize) * stride;
+        strip.copy_from_slice(&part[skip..skip + strip.len()]);
+    });
+    if done { ImageBuffer::from_raw(width, height, out) } else { None }
+}
+
+///nearest neighbour resize, same pixels as imageops::resize with FilterType::Nearest
+fn resize_nearest(imgbuf: &image::RgbaImage, new_width: u32, new_height: u32, progress: &Progress) -> Option<image::RgbaImage> {
+    let (width, height) = imgbuf.dimensions();
+    let ratio_x = width as f32 / new_width as f32;
+    let ratio_y = height as f32 / new_height as f32;
+    //source of output pixel n is at the floor of the scaled pixel centre
+    let source = |n: u32, ratio: f32, max: u32| cmp::min(((n as f32 + 0.5) * ratio).floor() as u32, max - 1) as usize;
+    let columns: Vec<usize> = (0..new_width).map(|x| source(x, ratio_x, width)).collect();
+    let raw: &[u8] = imgbuf;
+    let stride = 4 * width as usize;
+    let mut out = vec![0; 4 * new_width as usize * new_height as usize];
+    let done = parallel::for_strips_with(&mut out, 4 * new_width as usize, progress, |first, strip| {
+        for (row, line) in strip.chunks_mut(4 * new_width as usize).enumerate() {
+            let start = source((first + row) as u32, ratio_y, height) * stride;
+            for (pixel, &x) in line.chunks_mut(4).zip(&columns) {
+                pixel.copy_from_slice(&raw[start + 4 * x..start + 4 * x + 4]);
+            }
+        }
+    });
+    if done { ImageBuffer::from_raw(new_width, new_height, out) } else { None }
+}
+
+///resize through filter on premultiplied alpha, so transparent pixels do not leave dark fringes
+fn resize_filtered(imgbuf: &image::RgbaImage, new_width: u32, new_height: u32, filter: Filter, progress: &Progress) -> Option<image::RgbaImage> {
+    let (width, height) = imgbuf.dimensions();
+    //16 bits keep the color of nearly transparent pixels
+    let mut premultiplied = Vec::with_capacity(4 * width as usize * height as usize);
+    for p in imgbuf.pixels() {
+        let a = p[3] as u32;
+        for &c in &p.data[..3] {
+            premultiplied.push(((c as u32 * a * 257 + 127) / 255) as u16);
+        }
+        premultiplied.push((a * 257) as u16);
+    }
+    //each output row is the columns filtered down to it, then that row filtered across
+    let rows = filter.weights(height, new_height);
+    let columns = filter.weights(width, new_width);
+    let stride = 4 * width as usize;
+    let mut out = vec![0; 4 * new_width as usize * new_height as usize];
+    let done = parallel::for_strips_with(&mut out, 4 * new_width as usize, progress, |first, strip| {
+        let mut line = vec![0.0f32; stride];
+        for (row, pixels) in strip.chunks_mut(4 * new_width as usize).enumerate() {
+            let (top, ref weights) = rows[first + row];
+            for v in line.iter_mut() {
+                *v = 0.0;
+            }
+            for (j, &w) in weights.iter().enumerate() {
+                let start = (top + j) * stride;
+                for (v, &c) in line.iter_mut().zip(&premultiplied[start..start + stride]) {
+                    *v += c as f32 * w;
+                }
+            }
+            //kept in range between the passes, as imageops::resize does
+            for v in line.iter_mut() {
+                *v = v.max(0.0).min(65535.0);
+            }
+            for (pixel, &(left, ref weights)) in pixels.chunks_mut(4).zip(&columns) {
+                let mut p = [0.0f32; 4];
+                for (i, &w) in weights.iter().enumerate() {
+                    let at = 4 * (left + i);
+                    for (c, &v) in p.iter_mut().zip(&line[at..at + 4]) {
+                        *c += v * w;
+                    }
+                }
+                let a = p[3].max(0.0).min(65535.0) as u32;
+                if a < 129 {
+                    continue;
+                }
+                //sharp filters can ring a little past the alpha
+                for (value, &c) in pixel.iter_mut().zip(&p[..3]) {
+                    *value = cmp::min(255, (c.max(0.0) as u32 * 255 + a / 2) / a) as u8;
+                }
+                pixel[3] = ((a + 128) / 257) as u8;
+            }
+        }
+    });
+    if done { ImageBuffer::from_raw(new_width, new_height, out) } else { None }
+}
+
+///b of a "resize" transformation, plain height is nearest neighbour
+fn resize_code(height: u32, filter: Filter) -> i32 {
+    (height & 0xff_ffff) as i32 | (filter as i32) << 24
+}
+
+///height and filter of a "resize" transformation with b
+fn resize_size(b: i32) -> (u32, Filter) {
+    ((b & 0xff_ffff) as u32, Filter::from_i32(b >> 24))
+}
+
+///b of a "rotate" transformation
+fn rotation_code(interpolation: Interpolation, fit: Fit) -> i32 {
+    interpolation as i32 + 3 * fit as i32
+}
+
+///how a "rotate" transformation with b samples and fits
+fn rotation(b: i32) -> (Interpolation, Fit) {
+    (Interpolation::from_i32(b % 3), Fit::from_i32(b / 3))
+}
+
+///turn image by theta radians clockwise about its center into a new width x height image
+///around the same center; fill shows where the image does not reach
+fn rotate(image: &image::RgbaImage, width: u32, height: u32, theta: f32, interpolation: Interpolation,
+          fill: Color, progress: &Progress) -> Option<image::RgbaImage> {
+    let (w, h) = image.dimensions();
+    let raw: &[u8] = image;
+    let (sin, cos) = theta.sin_cos();
+    //premultiplied and in the byte order of the buffer
+    let alpha = fill.a() as f32 / 255.0;
+    let back = [fill.b() as f32 * alpha, fill.g() as f32 * alpha, fill.r() as f32 * alpha, fill.a() as f32];
+    //how much of a pixel centered at t lies on n pixels, smoothed over one pixel
+    let edge = |t: f32, n: u32| (t + 1.0).min(n as f32 - t).max(0.0).min(1.0);
+    let mut out = vec![0; 4 * width as usize * height as usize];
+    let done = parallel::for_strips_with(&mut out, 4 * width as usize, progress, |first, strip| {
+        for (row, line) in strip.chunks_mut(4 * width as usize).enumerate() {
+            let dy = (first + row) as f32 + 0.5 - height as f32 / 2.0;
+            for (x, pixel) in line.chunks_mut(4).enumerate() {
+                let dx = x as f32 + 0.5 - width as f32 / 2.0;
+                let u = cos * dx + sin * dy + w as f32 / 2.0 - 0.5;
+                let v = cos * dy - sin * dx + h as f32 / 2.0 - 0.5;
+                let inside = match interpolation {
+                    Interpolation::Nearest => edge(u.round(), w).min(edge(v.round(), h)),
+                    _ => edge(u, w) * edge(v, h),
+                };
+                let c = if inside > 0.0 {
+                    resample::sample_clamped(w as usize, h as usize, u, v, interpolation, |i| {
+                        let p = &raw[4 * i..4 * i + 4];
+                        let a = p[3] as f32 / 255.0;
+                        [p[0] as f32 * a, p[1] as f32 * a, p[2] as f32 * a, p[3] as f32]
+                    })
+                } else {
+                    [0.0; 4]
+                };
+                //bicubic overshoots a little around sharp edges
+                let a = (c[3] * inside + back[3] * (1.0 - inside)).max(0.0).min(255.0);
+                if a < 0.5 {
+                    pixel.copy_from_slice(&[0; 4]);
+                    continue;
+                }
+                for n in 0..3 {
+                    let value = (c[n] * inside + back[n] * (1.0 - inside)) * 255.0 / a;
+                    pixel[n] = (value + 0.5).max(0.0).min(255.0) as u8;
+                }
+                pixel[3] = (a + 0.5) as u8;
+            }
+        }
+    });
+    if done { ImageBuffer::from_raw(width, height, out) } else { None }
+}
+
+///what a job works on, replaced by the result when it is done
+enum Work {
+    //the active layer or the mask, whole or the selection of it
+    //fill is left where a rotation uncovers the corners
+    Transform { mask: bool, selection: Option<Rect>, coverage: Option<Vec<u8>>, fill: Color, image: Image },
+    //active layer, mask and the other layers bottom to top, for size changes, each with its fill
+    Reshape { width: u32, height: u32, images: Vec<Image>, fills: Vec<Color> },
+    //sample is what colors are compared on when not image, clip the mask to honour
+    Fill { mask: bool, x: i32, y: i32, color: Color, matching: ColorMatch, sample: Option<Image>, clip: Option<Vec<u8>>, image: Image },
+}
+
+///operation on a copy of document data, it can run on a worker thread;
+///the result goes back with Document::finish_job
+pub struct Job {
+    label: String,
+    cod: String,
+    a: f32,
+    b: i32,
+    work: Work,
+}
+
+///result of a job
+pub struct Done {
+    label: String,
+    work: Work,
+}
+
+impl Job {
+    fn new(cod: &str, a: f32, b: i32, work: Work) -> Self {
+        Job { label: label(cod), cod: cod.to_string(), a, b, work }
+    }
+
+    ///name of the undo step it makes
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    ///do the work, None when progress is cancelled
+    pub fn run(self, progress: &Progress) -> Option<Done> {
+        let Job { label, cod, a, b, work } = self;
+        let work = match work {
+            Work::Transform { mask, selection, coverage, fill, image } => {
+                let (width, height) = transformed_size(&cod, a, b, image.width(), image.height());
+                progress.start(height as usize);
+                let image = transform_image(&image, width, height, &cod, a, b, fill, progress)?;
+                Work::Transform { mask, selection, coverage, fill, image }
+            },
+            Work::Reshape { width, height, images, fills } => {
+                progress.start(images.len() * height as usize);
+                let mut reshaped = Vec::with_capacity(images.len());
+                for (image, &fill) in images.iter().zip(&fills) {
+                    reshaped.push(transform_image(image, width, height, &cod, a, b, fill, progress)?);
+                }
+                Work::Reshape { width, height, images: reshaped, fills }
+            },
+            Work::Fill { mask, x, y, color, matching, sample, clip, mut image } => {
+                //finding the region, then painting it
+                progress.start(3 * image.height() as usize);
+                let coverage = {
+                    let source = sample.as_ref().unwrap_or(&image);
+                    region::similar_with(source.data(), source.width(), source.height(), x, y, &matching, progress)?
+                };
+                if !region::paint_with(&mut image, &coverage, clip.as_ref().map(|clip| &clip[..]), color, progress) {
+                    return None;
+                }
+                Work::Fill { mask, x, y, color, matching, sample: None, clip: None, image }
+            },
+        };
+        if progress.is_cancelled() {
+            return None;
+        }
+        Some(Done { label, work })
+    }
+}
+
+///size of the result of transformation cod on a width x height image
+fn transformed_size(cod: &str, a: f32, b: i32, width: u32, height: u32) -> (u32, u32) {
+    match cod {
+        "resize" => (a as u32, resize_size(b).0),
+        "rotate90" => (height, width),
+        "rotate" => rotation(b).1.size(width, height, a * PI / 180.0),
+        _ => (width, height),
+    }
+}
+
+///transformation of image into a new width x height image, with the same mode
+fn transform_image(image: &Image, width: u32, height: u32, cod: &str, a: f32, b: i32, fill: Color, progress: &Progress) -> Option<Image> {
+    if cod == "canvas_size" || cod == "crop" || cod == "autocrop" {
+        let new_image = recanvas(image, width, height, a as i32, b, fill);
+        progress.advance(height as usize);
+        return Some(new_image);
+    }
+    let data = trans_from_slice(image.data(), image.width(), image.height(), cod, a, b, fill, progress)?;
+    let new_image = Image::from_data(width, height, data.into_boxed_slice()).unwrap();
+    new_image.mode().set(image.mode().get());
+    Some(new_image)
+}
+
+/// apply some transformation to an image slice, rows of the result are added to progress
+fn trans_from_slice (image_data: &[Color], width: u32, height: u32, cod: &str, a: f32, b:i32, fill: Color, progress: &Progress) -> Option<Vec<Color>> {
+    let image_buffer = unsafe {
+        slice::from_raw_parts(image_data.as_ptr() as *const u8, 4 * image_data.len())
+    };
+    let mut imgbuf : image::ImageBuffer<image::Rgba<u8>, _> = image::ImageBuffer::from_raw(width as u32, height as u32, image_buffer.to_vec()).unwrap();
+    let vec_image_buffer:Vec<u8> = image::ImageBuffer::into_raw ( 
+        match cod {
+        
+         "blur"            => filter_strips(&imgbuf, blur_margin(a), progress, |part| image::imageops::blur(part,a))?,
+         "unsharpen"       => filter_strips(&imgbuf, blur_margin(a), progress, |part| image::imageops::unsharpen(part,a,10))?,
+         "rotate"          => {let (new_width, new_height) = transformed_size(cod, a, b, width, height);
+                               rotate(&imgbuf, new_width, new_height, a * PI/180.0, rotation(b).0, fill, progress)?},
+         "contrast"        => filter_strips(&imgbuf, 0, progress, |part| image::imageops::colorops::contrast(part, a))?,
+         "resize"          => {let (new_height, filter) = resize_size(b);
+                               match filter {
+                                   Filter::Nearest => resize_nearest(&imgbuf, a as u32, new_height, progress)?,
+                                   filter => resize_filtered(&imgbuf, a as u32, new_height, filter, progress)?,
+                               }},
+         //quick ones, done in one go
+         _ => {
+            let result = match cod {
+             "flip_vertical"   => image::imageops::flip_vertical(&imgbuf),
+             "flip_horizontal" => image::imageops::flip_horizontal(&imgbuf),
+             "rotate90"        => image::imageops::rotate90(&imgbuf),
+             "brighten"        => image::imageops::colorops::brighten(&imgbuf, 10),
+             "darken"          => image::imageops::colorops::brighten(&imgbuf, -10),
+             "invert"          => {image::imageops::colorops::invert(&mut imgbuf);
+                                    imgbuf},
+             "grayscale"       => gray2rgba(image::imageops::colorops::grayscale(&imgbuf),
+                                            1.2,1.2,1.2),
+             "edge"            => {let kernel = [-1.0f32, -1.0, -1.0,
+                                                 -1.0, 8.0, -1.0,
+                                                 -1.0, -1.0, -1.0];
+                                image::imageops::filter3x3(&imgbuf, &kernel)
+                                },
+                             _ => imgbuf,
+            };
+            progress.advance(result.height() as usize);
+            result
+         },
+     });
+    
+    //convert rgba u8 image buffer back into Color slice
+    let mut new_slice = vec![Color::rgba(0, 0, 0, 0); vec_image_buffer.len() / 4];
+    let (new_width, _) = transformed_size(cod, a, b, width, height);
+    parallel::for_strips(&mut new_slice, new_width as usize, |first, strip| {
+        let start = first * new_width as usize * 4;
+        for (color, p) in strip.iter_mut().zip(vec_image_buffer[start..].chunks(4)) {
+            *color = Color::rgba(p[2], p[1], p[0], p[3]); //taking care of byte order
+        }
+    });
+    Some(new_slice)
+}
+
+/// convert grayscale format image to rgba format
+fn gray2rgba (grayimage: image::ImageBuffer<image::Luma<u8>, Vec<u8>>,
+                r_factor : f32,
+                g_factor : f32,
+                b_factor : f32
+                )
+                -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+    let mut r: u8 ;
+    let mut g: u8 ;
+    let mut b: u8 ;
+    let mut a: u8 ;
+    let mut new_buffer = Vec::new();
+    let width = grayimage.width();
+    let height = grayimage.height();
+
+    for luma in image::ImageBuffer::into_raw (grayimage) {
+        if luma == 255 {
+            r=255;
+            g=255;
+            b=255;
+        }else{
+        r = (luma as f32 / r_factor) as u8;
+        g = (luma as f32 / g_factor) as u8;
+        b = (luma as f32 / b_factor) as u8;
+        }
+        a = 255;
+        new_buffer.push(b);
+        new_buffer.push(g);
+        new_buffer.push(r);
+        new_buffer.push(a);
+    }
+    let imgbuf : image::ImageBuffer<image::Rgba<u8>, _> = image::ImageBuffer::from_raw(width as u32, height as u32, new_buffer).unwrap();
+        imgbuf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //three layers of 8x6, each with its own pixels
+    fn layered() -> Document {
+        let document = Document::from_color(8, 6, Color::rgb(255, 0, 0));
+        document.new_layer();
+        document.image.borrow_mut().rect(2, 1, 3, 3, Color::rgb(0, 255, 0));
+        document.new_layer();
+        document.image.borrow_mut().rect(1, 2, 2, 2, Color::rgb(0, 0, 255));
+        document.undo_commit();
+        document
+    }
+
+    fn pixels(document: &Document) -> Vec<(u32, u32, Vec<u32>)> {
+        document.export_layers().iter()
+            .map(|layer| (layer.image.width(), layer.image.height(), layer.image.data().iter().map(|c| c.data).collect()))
+            .collect()
+    }
+
+    fn rgb(r: u8, g: u8, b: u8) -> u32 {
+        Color::rgb(r, g, b).data
+    }
+
+    //color at x,y of layer n
+    fn at(pixels: &[(u32, u32, Vec<u32>)], n: usize, x: u32, y: u32) -> u32 {
+        let (width, _, ref data) = pixels[n];
+        data[(y * width + x) as usize]
+    }
+
+    //run job on the document it was made from, check the new size and that undo and redo
+    //go back and forth; the pixels after the job are returned
+    fn undo_restores(document: &Document, job: Option<Job>, width: u32, height: u32) -> Vec<(u32, u32, Vec<u32>)> {
+        let before = pixels(document);
+        let (old_width, old_height) = (document.width(), document.height());
+        document.run_job(job.unwrap_or_else(|| panic!("no job")));
+        let after = pixels(document);
+        assert!(after.iter().all(|&(w, h, _)| (w, h) == (width, height)));
+        assert_eq!((document.width(), document.height()), (width, height));
+
+        assert!(document.undo());
+        assert_eq!(pixels(document), before);
+        assert_eq!((document.mask().width(), document.mask().height()), (old_width, old_height));
+        assert!(document.redo());
+        assert_eq!(pixels(document), after);
+        after
+    }
+
+    #[test]
+    fn copy_of_selection_past_the_edge() {
+        let document = Document::from_color(8, 6, Color::rgb(255, 0, 0));
+        document.select(Some(Selection::ellipse(Rect::new(4, 3, 10, 8))));
+        document.copy_selected();
+        let buffer = document.copy_buffer.borrow();
+        assert_eq!((buffer.width(), buffer.height()), (10, 8));
+        //inside the document and the ellipse, then outside the document
+        assert!(buffer.data()[2 * 10 + 2].data == Color::rgb(255, 0, 0).data);
+        assert_eq!(buffer.data()[7 * 10 + 5].a(), 0);
+    }
+
+    #[test]
+    fn layer_properties_undo() {
+        let document = layered();
+        document.set_layer_opacity(1, 70);
+        document.set_layer_opacity(1, 40);
+        document.set_layer_visible(2, false);
+        //unchanged, no step
+        document.set_layer_visible(2, false);
+        assert_eq!(document.undo_label().as_deref(), Some("Hide layer"));
+        document.new_layer();
+        assert!(document.undo());
+        assert_eq!((document.layers()[1].opacity, document.layers()[2].visible), (40, false));
+        assert!(document.undo());
+        assert!(document.layers()[2].visible);
+        assert!(document.undo());
+        assert_eq!(document.layers()[1].opacity, 100);
+        assert!(document.redo());
+        assert_eq!(document.layers()[1].opacity, 40);
+    }
+
+    #[test]
+    fn fill_reports_progress_and_cancels() {
+        let document = Document::from_color(40, 30, Color::rgb(255, 255, 255));
+        let matching = ColorMatch::default();
+        let progress = Progress::new();
+        assert!(document.fill_job(3, 3, Color::rgb(0, 0, 255), &matching).unwrap().run(&progress).is_some());
+        assert_eq!(progress.percent(), 100);
+
+        let progress = Progress::new();
+        progress.cancel();
+        assert!(document.fill_job(3, 3, Color::rgb(0, 0, 255), &matching).unwrap().run(&progress).is_none());
+    }
+
+    #[test]
+    fn edge_changes_flat_image() {
+        let document = Document::from_color(8, 8, Color::rgb(128, 128, 128));
+        let before: Vec<u32> = document.image.borrow().data().iter().map(|c| c.data).collect();
+        document.transformation("edge", 0.0, 0);
+        let after: Vec<u32> = document.image.borrow().data().iter().map(|c| c.data).collect();
+        assert_ne!(before, after);
+    }
+
+    #[test]
+    fn crop_of_layers_undoes() {
+        let document = layered();
+        let before = pixels(&document);
+        let after = undo_restores(&document, document.crop_job(Rect::new(1, 1, 5, 4)), 5, 4);
+        for n in 0..3 {
+            assert_eq!(at(&after, n, 0, 0), at(&before, n, 1, 1));
+        }
+        //the corners of the green and blue squares
+        assert_eq!(at(&after, 1, 1, 0), rgb(0, 255, 0));
+        assert_eq!(at(&after, 2, 0, 1), rgb(0, 0, 255));
+    }
+
+    #[test]
+    fn canvas_size_of_layers_undoes() {
+        let document = layered();
+        let after = undo_restores(&document, document.canvas_size_job(12, 4, 2, -1, Some(Color::rgb(0, 0, 0))), 12, 4);
+        //new border: the fill on the bottom layer, transparency above
+        assert_eq!(at(&after, 0, 0, 0), rgb(0, 0, 0));
+        assert_eq!(at(&after, 0, 11, 3), rgb(0, 0, 0));
+        assert_eq!(at(&after, 1, 0, 0), 0);
+        //old 0,1 is at 2,0
+        assert_eq!(at(&after, 0, 2, 0), rgb(255, 0, 0));
+        assert_eq!(at(&after, 1, 4, 0), rgb(0, 255, 0));
+        assert_eq!(at(&after, 2, 3, 1), rgb(0, 0, 255));
+    }
+
+    #[test]
+    fn autocrop_of_layers_undoes() {
+        let document = layered();
+        document.select_layer(0);
+        document.undo_save("Clear");
+        document.image.borrow_mut().set(Color::rgba(0, 0, 0, 0));
+        document.undo_commit();
+        let after = undo_restores(&document, document.autocrop_job(), 4, 3);
+        //the content started at 1,1
+        assert_eq!(at(&after, 1, 1, 0), rgb(0, 255, 0));
+        assert_eq!(at(&after, 2, 0, 1), rgb(0, 0, 255));
+        assert_eq!(at(&after, 2, 0, 0), 0);
+    }
+}
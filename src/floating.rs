@@ -0,0 +1,316 @@
+//floating selection: pixels lifted off the active layer, they can be moved, scaled
+//and turned over what is left of it until they are put down again
+
+use orbclient::Color;
+use orbimage::Image;
+
+use crate::layer::{self, BlendMode};
+use crate::resample::{self, Interpolation};
+use crate::Rect;
+
+///where the floating pixels go: the document positions of their corners, top left first
+///and clockwise, and the point they turn around
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quad {
+    pub corners: [(f32, f32); 4],
+    pub pivot: (f32, f32),
+}
+
+impl Quad {
+    ///a width x height rectangle at x,y turning around its center
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Quad {
+            corners: [(x, y), (x + width, y), (x + width, y + height), (x, y + height)],
+            pivot: (x + width / 2.0, y + height / 2.0),
+        }
+    }
+
+    pub fn translated(&self, dx: f32, dy: f32) -> Self {
+        let mut quad = *self;
+        for c in quad.corners.iter_mut().chain(Some(&mut quad.pivot)) {
+            *c = (c.0 + dx, c.1 + dy);
+        }
+        quad
+    }
+
+    ///turned around the pivot by angle radians, clockwise
+    pub fn rotated(&self, angle: f32) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        let (px, py) = self.pivot;
+        let mut quad = *self;
+        for c in quad.corners.iter_mut() {
+            let (dx, dy) = (c.0 - px, c.1 - py);
+            *c = (px + dx * cos - dy * sin, py + dx * sin + dy * cos);
+        }
+        quad
+    }
+
+    ///direction of the top side in radians, clockwise from the x axis
+    pub fn angle(&self) -> f32 {
+        let [a, b, _, _] = self.corners;
+        (b.1 - a.1).atan2(b.0 - a.0)
+    }
+
+    ///middle of side n, side 0 is the top one and they go clockwise
+    pub fn side(&self, n: usize) -> (f32, f32) {
+        let (a, b) = (self.corners[n % 4], self.corners[(n + 1) % 4]);
+        ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0)
+    }
+
+    ///true if x,y is inside the quad, which must be convex
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        let c = &self.corners;
+        let sides: Vec<f32> = (0..4).map(|n| {
+            let (a, b) = (c[n], c[(n + 1) % 4]);
+            (b.0 - a.0) * (y - a.1) - (b.1 - a.1) * (x - a.0)
+        }).collect();
+        sides.iter().all(|&s| s >= 0.0) || sides.iter().all(|&s| s <= 0.0)
+    }
+
+    ///no corner pokes inward and the sides do not cross
+    pub fn is_convex(&self) -> bool {
+        let c = &self.corners;
+        let turns: Vec<f32> = (0..4).map(|n| {
+            let (a, b, d) = (c[n], c[(n + 1) % 4], c[(n + 2) % 4]);
+            (b.0 - a.0) * (d.1 - b.1) - (b.1 - a.1) * (d.0 - b.0)
+        }).collect();
+        turns.iter().all(|&t| t > 0.0) || turns.iter().all(|&t| t < 0.0)
+    }
+
+    ///from where in a width x height image each document point comes, None for a flat quad
+    fn inverse(&self, width: u32, height: u32) -> Option<Projection> {
+        let m = Projection::unit_to(&self.corners)?.invert()?;
+        //unit square to pixels
+        let (w, h) = (width as f32, height as f32);
+        Some(Projection([
+            [m.0[0][0] * w, m.0[0][1] * w, m.0[0][2] * w],
+            [m.0[1][0] * h, m.0[1][1] * h, m.0[1][2] * h],
+            m.0[2],
+        ]))
+    }
+}
+
+///projective mapping of the plane, 3x3 matrix on homogeneous points
+#[derive(Clone, Copy, Debug)]
+struct Projection([[f32; 3]; 3]);
+
+impl Projection {
+    ///mapping of the unit square on a quad, corner 0 at 0,0 and clockwise
+    fn unit_to(corners: &[(f32, f32); 4]) -> Option<Self> {
+        let [(x0, y0), (x1, y1), (x2, y2), (x3, y3)] = *corners;
+        let (dx1, dx2, dx3) = (x1 - x2, x3 - x2, x0 - x1 + x2 - x3);
+        let (dy1, dy2, dy3) = (y1 - y2, y3 - y2, y0 - y1 + y2 - y3);
+        let den = dx1 * dy2 - dx2 * dy1;
+        if den.abs() < 1e-6 {
+            return None;
+        }
+        //a parallelogram gives g = h = 0, a plain affine mapping
+        let g = (dx3 * dy2 - dx2 * dy3) / den;
+        let h = (dx1 * dy3 - dx3 * dy1) / den;
+        Some(Projection([
+            [x1 - x0 + g * x1, x3 - x0 + h * x3, x0],
+            [y1 - y0 + g * y1, y3 - y0 + h * y3, y0],
+            [g, h, 1.0],
+        ]))
+    }
+
+    fn invert(&self) -> Option<Self> {
+        let m = &self.0;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+        let adjugate = [
+            [cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2)],
+            [-cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2)],
+            [cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1)],
+        ];
+        let det = m[0][0] * adjugate[0][0] + m[0][1] * adjugate[1][0] + m[0][2] * adjugate[2][0];
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let mut inverse = adjugate;
+        for v in inverse.iter_mut().flat_map(|row| row.iter_mut()) {
+            *v /= det;
+        }
+        Some(Projection(inverse))
+    }
+
+    fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        let m = &self.0;
+        let w = m[2][0] * x + m[2][1] * y + m[2][2];
+        ((m[0][0] * x + m[0][1] * y + m[0][2]) / w, (m[1][0] * x + m[1][1] * y + m[1][2]) / w)
+    }
+}
+
+pub struct Floating {
+    ///lifted pixels, their alpha already scaled by how much they were selected
+    image: Image,
+    ///how much each lifted pixel was selected
+    coverage: Vec<u8>,
+    ///layer with the pixels taken away
+    base: Image,
+    ///layer before lifting, to cancel
+    pub original: Image,
+    ///where the pixels were lifted from
+    rect: Rect,
+    ///part of the layer drawn over last time
+    drawn: Option<Rect>,
+    quad: Quad,
+    //document to lifted pixels
+    inverse: Projection,
+}
+
+impl Floating {
+    ///lift the pixels of rect selected by coverage (one value per layer pixel) off layer,
+    ///leaving background behind; the layer is not changed until render
+    pub fn lift(layer: &Image, coverage: &[u8], rect: Rect, background: Color) -> Self {
+        let stride = layer.width() as i32;
+        let mut pixels = Vec::with_capacity((rect.width * rect.height) as usize);
+        let mut lifted = Vec::with_capacity(pixels.capacity());
+        let mut base = layer.clone();
+        for y in rect.y..rect.y + rect.height as i32 {
+            for x in rect.x..rect.x + rect.width as i32 {
+                let inside = x >= 0 && y >= 0 && x < stride && y < layer.height() as i32;
+                let (color, c) = if inside {
+                    let i = (y * stride + x) as usize;
+                    (layer.data()[i], coverage[i])
+                } else {
+                    (Color::rgba(0, 0, 0, 0), 0)
+                };
+                pixels.push(Color::rgba(color.r(), color.g(), color.b(), (color.a() as u32 * c as u32 / 255) as u8));
+                lifted.push(c);
+                if inside && c > 0 {
+                    let old = &mut base.data_mut()[(y * stride + x) as usize];
+                    *old = layer::mix(*old, background, c);
+                }
+            }
+        }
+        let quad = Quad::new(rect.x as f32, rect.y as f32, rect.width as f32, rect.height as f32);
+        Floating {
+            inverse: quad.inverse(rect.width, rect.height).unwrap(),
+            image: Image::from_data(rect.width, rect.height, pixels.into_boxed_slice()).unwrap(),
+            coverage: lifted,
+            base,
+            original: layer.clone(),
+            rect,
+            drawn: None,
+            quad,
+        }
+    }
+
+    ///how far the pixels went if they were only moved, by whole pixels
+    pub fn offset(&self) -> Option<(i32, i32)> {
+        let (x, y) = self.quad.corners[0];
+        let (dx, dy) = (x - self.rect.x as f32, y - self.rect.y as f32);
+        let moved = Quad::new(self.rect.x as f32, self.rect.y as f32, self.rect.width as f32, self.rect.height as f32)
+            .translated(dx, dy);
+        if moved.corners == self.quad.corners && dx.fract() == 0.0 && dy.fract() == 0.0 {
+            Some((dx as i32, dy as i32))
+        } else {
+            None
+        }
+    }
+
+    pub fn quad(&self) -> Quad {
+        self.quad
+    }
+
+    ///put the pixels on quad, false if it is folded over itself and they stay where they are
+    pub fn place(&mut self, quad: Quad) -> bool {
+        let inverse = match quad.inverse(self.width(), self.height()) {
+            Some(inverse) if quad.is_convex() => inverse,
+            _ => return false,
+        };
+        self.quad = quad;
+        self.inverse = inverse;
+        true
+    }
+
+    pub fn width(&self) -> u32 {
+        self.image.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.image.height()
+    }
+
+    ///pixels of a width x height document touched by the placed pixels
+    pub fn bounds(&self, width: u32, height: u32) -> Option<Rect> {
+        let corners = self.quad.corners;
+        let x0 = corners.iter().map(|c| c.0).fold(f32::MAX, f32::min).floor().max(0.0) as i32;
+        let y0 = corners.iter().map(|c| c.1).fold(f32::MAX, f32::min).floor().max(0.0) as i32;
+        let x1 = corners.iter().map(|c| c.0).fold(f32::MIN, f32::max).ceil().min(width as f32) as i32;
+        let y1 = corners.iter().map(|c| c.1).fold(f32::MIN, f32::max).ceil().min(height as f32) as i32;
+        if x1 <= x0 || y1 <= y0 {
+            return None;
+        }
+        Some(Rect::new(x0, y0, (x1 - x0) as u32, (y1 - y0) as u32))
+    }
+
+    ///draw the placed pixels over what was left of the layer
+    pub fn render(&mut self, layer: &mut Image, interpolation: Interpolation) {
+        let stride = layer.width() as usize;
+        //put back what was under the pixels last time
+        if let Some(rect) = self.drawn.take() {
+            for y in rect.y as usize..rect.y as usize + rect.height as usize {
+                let row = y * stride + rect.x as usize..y * stride + rect.x as usize + rect.width as usize;
+                layer.data_mut()[row.clone()].copy_from_slice(&self.base.data()[row]);
+            }
+        }
+        let rect = match self.bounds(layer.width(), layer.height()) {
+            Some(rect) => rect,
+            None => return,
+        };
+        let data = layer.data_mut();
+        for y in rect.y..rect.y + rect.height as i32 {
+            for x in rect.x..rect.x + rect.width as i32 {
+                let color = self.sample(x, y, interpolation);
+                if color.a() > 0 {
+                    let i = y as usize * stride + x as usize;
+                    data[i] = layer::blend(BlendMode::Normal, data[i], color, 100);
+                }
+            }
+        }
+        self.drawn = Some(rect);
+    }
+
+    ///how much each pixel of a width x height document is selected once the pixels are placed
+    pub fn placed_coverage(&self, width: u32, height: u32) -> Vec<u8> {
+        let mut coverage = vec![0u8; (width * height) as usize];
+        if let Some(rect) = self.bounds(width, height) {
+            let (w, h) = (self.width() as usize, self.height() as usize);
+            for y in rect.y..rect.y + rect.height as i32 {
+                for x in rect.x..rect.x + rect.width as i32 {
+                    let (u, v) = self.source(x, y);
+                    let c = resample::sample(w, h, u, v, Interpolation::Bilinear, |i| [self.coverage[i] as f32, 0.0, 0.0, 0.0]);
+                    coverage[(y * width as i32 + x) as usize] = (c[0] + 0.5) as u8;
+                }
+            }
+        }
+        coverage
+    }
+
+    ///where in the lifted pixels the center of document pixel x,y comes from
+    fn source(&self, x: i32, y: i32) -> (f32, f32) {
+        let (u, v) = self.inverse.apply(x as f32 + 0.5, y as f32 + 0.5);
+        (u - 0.5, v - 0.5)
+    }
+
+    ///color the placed pixels give to document pixel x,y
+    fn sample(&self, x: i32, y: i32, interpolation: Interpolation) -> Color {
+        let (u, v) = self.source(x, y);
+        let data = self.image.data();
+        //premultiplied, so transparent pixels do not darken the edges
+        let c = resample::sample(self.width() as usize, self.height() as usize, u, v, interpolation, |i| {
+            let c = data[i];
+            let a = c.a() as f32 / 255.0;
+            [c.r() as f32 * a, c.g() as f32 * a, c.b() as f32 * a, c.a() as f32]
+        });
+        //bicubic overshoots a little around sharp edges
+        let alpha = c[3].min(255.0);
+        if alpha < 0.5 {
+            return Color::rgba(0, 0, 0, 0);
+        }
+        let a = alpha / 255.0;
+        let channel = |v: f32| (v / a + 0.5).max(0.0).min(255.0) as u8;
+        Color::rgba(channel(c[0]), channel(c[1]), channel(c[2]), (alpha + 0.5) as u8)
+    }
+}
@@ -0,0 +1,195 @@
+//gradients painted by dragging from a start to an end point: colors along the drag,
+//around the start point or in between
+
+use orbclient::Color;
+
+///how the drag sets the position along the gradient
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shape {
+    ///bands across the drag
+    Linear,
+    ///circles around the start point
+    Radial,
+    ///turning around the start point, starting in the drag direction
+    Conical,
+    ///squares turned like the drag, around the start point
+    Diamond,
+}
+
+impl Shape {
+    ///0 linear, 1 radial, 2 conical, 3 diamond
+    pub fn from_i32(n: i32) -> Self {
+        match n {
+            1 => Shape::Radial,
+            2 => Shape::Conical,
+            3 => Shape::Diamond,
+            _ => Shape::Linear,
+        }
+    }
+}
+
+///what lies past the end of the drag
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Spread {
+    ///the last color goes on
+    Pad,
+    ///the gradient starts over
+    Repeat,
+    ///the gradient goes back and forth
+    Reflect,
+}
+
+impl Spread {
+    ///0 pad, 1 repeat, 2 reflect
+    pub fn from_i32(n: i32) -> Self {
+        match n {
+            1 => Spread::Repeat,
+            2 => Spread::Reflect,
+            _ => Spread::Pad,
+        }
+    }
+}
+
+///color at a position of the gradient, 0.0 start and 1.0 end
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stop {
+    pub position: f32,
+    pub color: Color,
+}
+
+#[derive(Clone, Debug)]
+pub struct Gradient {
+    pub shape: Shape,
+    pub spread: Spread,
+    ///sorted by position, at least one
+    stops: Vec<Stop>,
+    ///ordered noise of less than a level, so smooth gradients show no bands
+    pub dither: bool,
+}
+
+//4x4 Bayer matrix, thresholds for dithering
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+impl Gradient {
+    ///gradient through stops, transparent if there are none
+    pub fn new(shape: Shape, spread: Spread, stops: &[Stop], dither: bool) -> Self {
+        let mut stops = stops.to_vec();
+        if stops.is_empty() {
+            stops.push(Stop { position: 0.0, color: Color::rgba(0, 0, 0, 0) });
+        }
+        stops.sort_by(|a, b| a.position.partial_cmp(&b.position).unwrap_or(std::cmp::Ordering::Equal));
+        Gradient { shape, spread, stops, dither }
+    }
+
+    pub fn stops(&self) -> &[Stop] {
+        &self.stops
+    }
+
+    ///color of pixel x,y for a drag from start to end, in the same coordinates
+    pub fn color(&self, start: (f32, f32), end: (f32, f32), x: i32, y: i32) -> Color {
+        let t = self.spread(self.offset(start, end, x as f32 + 0.5, y as f32 + 0.5));
+        let c = self.at(t);
+        let noise = if self.dither {
+            BAYER[(y & 3) as usize][(x & 3) as usize] as f32 / 16.0 - 15.0 / 32.0
+        } else {
+            0.0
+        };
+        let level = |v: f32| (v + 0.5 + noise).max(0.0).min(255.0) as u8;
+        Color::rgba(level(c[0]), level(c[1]), level(c[2]), level(c[3]))
+    }
+
+    ///position of point x,y along the drag from start to end, before spreading
+    fn offset(&self, start: (f32, f32), end: (f32, f32), x: f32, y: f32) -> f32 {
+        let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+        let length = (dx * dx + dy * dy).sqrt();
+        if length < 1e-3 {
+            return 1.0;
+        }
+        let (ux, uy) = (dx / length, dy / length);
+        let (px, py) = (x - start.0, y - start.1);
+        //along the drag and across it
+        let along = (px * ux + py * uy) / length;
+        let across = (py * ux - px * uy) / length;
+        match self.shape {
+            Shape::Linear => along,
+            Shape::Radial => (along * along + across * across).sqrt(),
+            Shape::Conical => {
+                let turn = across.atan2(along) / (2.0 * std::f32::consts::PI);
+                if turn < 0.0 { turn + 1.0 } else { turn }
+            },
+            Shape::Diamond => along.abs() + across.abs(),
+        }
+    }
+
+    ///offset t brought into 0..1
+    fn spread(&self, t: f32) -> f32 {
+        match self.spread {
+            Spread::Pad => t.max(0.0).min(1.0),
+            Spread::Repeat => t - t.floor(),
+            Spread::Reflect => {
+                let t = t.abs() % 2.0;
+                if t > 1.0 { 2.0 - t } else { t }
+            },
+        }
+    }
+
+    ///straight color at t as 0..255 floats, mixed premultiplied so fading out does not darken
+    fn at(&self, t: f32) -> [f32; 4] {
+        let straight = |c: Color| [c.r() as f32, c.g() as f32, c.b() as f32, c.a() as f32];
+        let first = self.stops[0];
+        let last = self.stops[self.stops.len() - 1];
+        if t <= first.position {
+            return straight(first.color);
+        }
+        if t >= last.position {
+            return straight(last.color);
+        }
+        let n = self.stops.iter().position(|stop| stop.position > t).unwrap();
+        let (a, b) = (self.stops[n - 1], self.stops[n]);
+        let k = (t - a.position) / (b.position - a.position);
+        let (ca, cb) = (straight(a.color), straight(b.color));
+        let alpha = ca[3] + (cb[3] - ca[3]) * k;
+        if alpha <= 0.0 {
+            return [0.0; 4];
+        }
+        let mut c = [0.0, 0.0, 0.0, alpha];
+        for i in 0..3 {
+            c[i] = (ca[i] * ca[3] + (cb[i] * cb[3] - ca[i] * ca[3]) * k) / alpha;
+        }
+        c
+    }
+}
+
+///stops written as "position:#rrggbb" or "position:#rrggbbaa" separated by spaces,
+///like "0:#000000 0.5:#ff000080 1:#ffffff"
+pub fn parse_stops(text: &str) -> Result<Vec<Stop>, String> {
+    let mut stops = Vec::new();
+    for item in text.split_whitespace() {
+        let mut parts = item.splitn(2, ':');
+        let position = parts.next().unwrap_or("").parse::<f32>()
+            .map_err(|_| format!("invalid position in {}", item))?;
+        let hex = parts.next().unwrap_or("").trim_start_matches('#');
+        let channel = |n: usize| hex.get(2 * n..2 * n + 2).and_then(|h| u8::from_str_radix(h, 16).ok());
+        let color = match (hex.len(), channel(0), channel(1), channel(2)) {
+            (6, Some(r), Some(g), Some(b)) => Color::rgb(r, g, b),
+            (8, Some(r), Some(g), Some(b)) => Color::rgba(r, g, b, channel(3).ok_or(format!("invalid color in {}", item))?),
+            _ => return Err(format!("invalid color in {}", item)),
+        };
+        stops.push(Stop { position: position.max(0.0).min(1.0), color });
+    }
+    if stops.is_empty() {
+        return Err("no stops".to_string());
+    }
+    Ok(stops)
+}
+
+///stops as parse_stops reads them
+pub fn stops_text(stops: &[Stop]) -> String {
+    stops.iter()
+        .map(|stop| {
+            let c = stop.color;
+            format!("{}:#{:02x}{:02x}{:02x}{:02x}", stop.position, c.r(), c.g(), c.b(), c.a())
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
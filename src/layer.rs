@@ -0,0 +1,134 @@
+//layers of a canvas document
+
+use orbclient::Color;
+use orbimage::Image;
+
+use std::str::FromStr;
+
+///how a layer is combined with the layers below it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    Difference,
+    Add,
+}
+
+impl BlendMode {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            BlendMode::Normal => "normal",
+            BlendMode::Multiply => "multiply",
+            BlendMode::Screen => "screen",
+            BlendMode::Overlay => "overlay",
+            BlendMode::Darken => "darken",
+            BlendMode::Lighten => "lighten",
+            BlendMode::Difference => "difference",
+            BlendMode::Add => "add",
+        }
+    }
+
+    ///blend one channel (0.0..1.0) of source over backdrop
+    fn channel(&self, backdrop: f32, source: f32) -> f32 {
+        match *self {
+            BlendMode::Normal => source,
+            BlendMode::Multiply => backdrop * source,
+            BlendMode::Screen => backdrop + source - backdrop * source,
+            BlendMode::Overlay => if backdrop <= 0.5 {
+                                      2.0 * backdrop * source
+                                  } else {
+                                      1.0 - 2.0 * (1.0 - backdrop) * (1.0 - source)
+                                  },
+            BlendMode::Darken => backdrop.min(source),
+            BlendMode::Lighten => backdrop.max(source),
+            BlendMode::Difference => (backdrop - source).abs(),
+            BlendMode::Add => (backdrop + source).min(1.0),
+        }
+    }
+}
+
+impl FromStr for BlendMode {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.trim().to_lowercase().as_ref() {
+            "normal" => Ok(BlendMode::Normal),
+            "multiply" => Ok(BlendMode::Multiply),
+            "screen" => Ok(BlendMode::Screen),
+            "overlay" => Ok(BlendMode::Overlay),
+            "darken" => Ok(BlendMode::Darken),
+            "lighten" => Ok(BlendMode::Lighten),
+            "difference" => Ok(BlendMode::Difference),
+            "add" => Ok(BlendMode::Add),
+            _ => Err(format!("Unknown blend mode '{}'", name)),
+        }
+    }
+}
+
+pub const BLEND_MODES: [BlendMode; 8] = [BlendMode::Normal, BlendMode::Multiply, BlendMode::Screen,
+    BlendMode::Overlay, BlendMode::Darken, BlendMode::Lighten, BlendMode::Difference, BlendMode::Add];
+
+#[derive(Clone)]
+pub struct Layer {
+    pub name: String,
+    //pixels of the layer, the active layer keeps its pixels in Canvas::image instead
+    pub image: Image,
+    //0..100 %
+    pub opacity: u8,
+    pub visible: bool,
+    pub locked: bool,
+    pub blend: BlendMode,
+}
+
+impl Layer {
+    pub fn new(name: &str, image: Image) -> Self {
+        Layer {
+            name: name.to_owned(),
+            image,
+            opacity: 100,
+            visible: true,
+            locked: false,
+            blend: BlendMode::Normal,
+        }
+    }
+
+    ///true if compositing this layer is the same as copying it
+    pub fn is_plain(&self) -> bool {
+        self.visible && self.opacity == 100 && self.blend == BlendMode::Normal
+    }
+}
+
+///composite source color over backdrop with blend mode and opacity (0..100 %)
+#[inline]
+pub fn blend(mode: BlendMode, backdrop: Color, source: Color, opacity: u8) -> Color {
+    let sa = source.a() as f32 / 255.0 * opacity as f32 / 100.0;
+    if sa <= 0.0 {
+        return backdrop;
+    }
+    let ba = backdrop.a() as f32 / 255.0;
+    let ra = sa + ba * (1.0 - sa);
+
+    let mix = |b: u8, s: u8| -> u8 {
+        let b = b as f32 / 255.0;
+        let s = s as f32 / 255.0;
+        //blended color where both are present, plain source or backdrop elsewhere
+        let c = sa * ba * mode.channel(b, s) + sa * (1.0 - ba) * s + (1.0 - sa) * ba * b;
+        (c / ra * 255.0 + 0.5) as u8
+    };
+
+    Color::rgba(mix(backdrop.r(), source.r()),
+                mix(backdrop.g(), source.g()),
+                mix(backdrop.b(), source.b()),
+                (ra * 255.0 + 0.5) as u8)
+}
+
+///old color turned into new by amount, 0..255
+pub fn mix(old: Color, new: Color, amount: u8) -> Color {
+    let amount = amount as u32;
+    let channel = |a: u8, b: u8| ((a as u32 * (255 - amount) + b as u32 * amount + 127) / 255) as u8;
+    Color::rgba(channel(old.r(), new.r()), channel(old.g(), new.g()), channel(old.b(), new.b()), channel(old.a(), new.a()))
+}
@@ -0,0 +1,153 @@
+use orbclient::{Color, Renderer};
+
+use std::cell::Cell;
+use std::sync::Arc;
+
+use orbtk::cell::CheckSet;
+use orbtk::event::Event;
+use orbtk::rect::Rect;
+use orbtk::traits::Place;
+use orbtk::widgets::Widget;
+
+use orbtk::theme::{Theme};
+
+use crate::canvas::Canvas;
+
+const ROW_HEIGHT: i32 = 20;
+//columns of the eye (visibility) and lock toggles
+const EYE_WIDTH: i32 = 20;
+const LOCK_WIDTH: i32 = 20;
+
+///list of the canvas layers, top layer first
+pub struct LayersPanel {
+    pub rect: Cell<Rect>,
+    canvas: Arc<Canvas>,
+    pressed: Cell<bool>,
+    pub visible: Cell<bool>,
+}
+
+impl LayersPanel {
+    pub fn new(canvas: Arc<Canvas>) -> Arc<Self> {
+        Arc::new(LayersPanel {
+            rect: Cell::new(Rect::default()),
+            canvas,
+            pressed: Cell::new(false),
+            visible: Cell::new(true),
+        })
+    }
+
+    ///layer index at y (relative to the panel), if any
+    fn layer_at(&self, y: i32) -> Option<usize> {
+        let row = y / ROW_HEIGHT - 1; //first row is the title
+        let count = self.canvas.layers().len() as i32;
+        if y < ROW_HEIGHT || row >= count {
+            None
+        } else {
+            Some((count - 1 - row) as usize)
+        }
+    }
+
+    fn draw_text(&self, renderer: &mut dyn Renderer, x: i32, y: i32, max_x: i32, text: &str, color: Color) {
+        let mut x = x;
+        for c in text.chars() {
+            if x + 8 > max_x {
+                break;
+            }
+            renderer.char(x, y, c, color);
+            x += 8;
+        }
+    }
+}
+
+impl Place for LayersPanel {}
+
+impl Widget for LayersPanel {
+    fn rect(&self) -> &Cell<Rect> {
+        &self.rect
+    }
+
+    fn draw(&self, renderer: &mut dyn Renderer, _focused: bool, _theme: &Theme) {
+        if !self.visible.get() {
+            return;
+        }
+        let rect = self.rect.get();
+        let fg = Color::rgb(0, 0, 0);
+        let border = Color::rgb(207, 214, 230);
+
+        renderer.rect(rect.x, rect.y, rect.width, rect.height, Color::rgb(255, 255, 255));
+        renderer.rounded_rect(rect.x, rect.y, rect.width, rect.height, 2, false, border);
+        let max_x = rect.x + rect.width as i32;
+        self.draw_text(renderer, rect.x + 4, rect.y + 2, max_x, "Layers", fg);
+
+        let layers = self.canvas.layers();
+        let active = self.canvas.active_layer();
+        for (row, n) in (0..layers.len()).rev().enumerate() {
+            let layer = &layers[n];
+            let y = rect.y + (row as i32 + 1) * ROW_HEIGHT;
+            if y + ROW_HEIGHT > rect.y + rect.height as i32 {
+                break;
+            }
+            if n == active {
+                renderer.rect(rect.x + 1, y, rect.width - 2, ROW_HEIGHT as u32, Color::rgb(190, 210, 240));
+            }
+            renderer.rect(rect.x, y, rect.width, 1, border);
+
+            //eye: filled when visible
+            renderer.rounded_rect(rect.x + 5, y + 5, 10, 10, 2, layer.visible, fg);
+            if layer.locked {
+                renderer.char(rect.x + EYE_WIDTH + 6, y + 2, 'L', fg);
+            }
+            let text = format!("{} {}%", layer.name, layer.opacity);
+            self.draw_text(renderer, rect.x + EYE_WIDTH + LOCK_WIDTH + 4, y + 2, max_x, &text, fg);
+        }
+    }
+
+    fn event(&self, event: Event, focused: bool, redraw: &mut bool) -> bool {
+        if self.visible.get() {
+            match event {
+                Event::Mouse { point, left_button, .. } => {
+                    let mut click = false;
+
+                    let rect = self.rect.get();
+                    if rect.contains(point) {
+                        if left_button {
+                            self.pressed.check_set(true);
+                        } else if self.pressed.check_set(false) {
+                            click = true;
+                        }
+                    } else if !left_button {
+                        self.pressed.check_set(false);
+                    }
+
+                    if click {
+                        let p = point - rect.point();
+                        if let Some(n) = self.layer_at(p.y) {
+                            let (visible, locked) = {
+                                let layer = &self.canvas.layers()[n];
+                                (layer.visible, layer.locked)
+                            };
+                            if p.x < EYE_WIDTH {
+                                self.canvas.set_layer_visible(n, !visible);
+                            } else if p.x < EYE_WIDTH + LOCK_WIDTH {
+                                self.canvas.set_layer_locked(n, !locked);
+                            } else {
+                                self.canvas.select_layer(n);
+                            }
+                            *redraw = true;
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        focused
+    }
+
+    fn visible(&self, flag: bool) {
+        self.visible.set(flag);
+    }
+
+    fn name(&self) -> &str {
+        "LayersPanel"
+    }
+}
@@ -0,0 +1,46 @@
+//pastel drawing core: document model and raster operations without any window,
+//the orbtk front-end in main.rs and other tools are built on top of it
+//
+//The library does not use orbtk, but it still links orbclient (Color, Renderer,
+//Mode) and orbimage. orbclient 0.3 always builds its SDL2 window backend off
+//Redox: sdl2 is not an optional dependency and features are shared with orbtk,
+//so it cannot be turned off for the library alone. pastel-batch only uses the
+//library and never opens a window, but it still needs SDL2 to link.
+
+#![allow(dead_code)]
+#![allow(unused_variables)]
+
+pub mod layer;
+pub mod undo;
+pub mod raster;
+pub mod region;
+pub mod selection;
+pub mod floating;
+pub mod resample;
+pub mod gradient;
+pub mod stamp;
+pub mod brush;
+pub mod stabilizer;
+pub mod document;
+pub mod parallel;
+pub mod task;
+pub mod project;
+pub mod batch;
+
+// undo history memory budget in megabytes
+pub const UNDOMEMORY: usize = 256;
+
+///rectangle in document coordinates
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+        Rect { x, y, width, height }
+    }
+}
@@ -12,7 +12,7 @@ extern crate image;
 extern crate orbclient;
 extern crate dirs;
 */
-use orbclient::Mode;
+use orbclient::{EventOption, Mode, K_DOWN, K_ENTER, K_ESC, K_LEFT, K_RIGHT, K_UP};
 
 use orbtk::{Color, Action, Button, ComboBox, Image, Label, Menu, Point, ProgressBar, Rect,
      Separator, TextBox, Window, WindowBuilder, Widget};
@@ -21,23 +21,25 @@ use orbtk::traits::{Click, Enter, Place, Text, Style};
 use orbtk::theme::Theme;
 
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::sync::Arc;
 use std::process;
 use std::process::Command;
 use std::path::{Path, PathBuf};
-use std::{env, fs, cmp, io};
-use std::cmp::Ordering;
+use std::{env, fs, io};
+use std::cmp::{self, Ordering};
 use std::ffi::OsStr;
+use std::thread;
+use std::time::Duration;
 
 mod dialogs;
-use crate::dialogs::{dialog, popup, new_dialog};
+use crate::dialogs::{dialog, choice_dialog, image_dialog, popup, new_dialog};
 
 mod palette;
 use crate::palette::Palette;
 
 mod addons;
-use crate::addons::AddOnsToOrbimage;
+use crate::addons::InteractOrbimage;
 
 mod canvas;
 use crate::canvas::Canvas;
@@ -59,6 +61,25 @@ use control_knob::ControlKnob;
 mod tools;
 use crate::tools::{Property, Tools};
 
+use pastel::layer::{BlendMode, BLEND_MODES};
+use pastel::raster::ColorDistance;
+use pastel::region::{ColorMatch, Connectivity};
+use pastel::selection::{Combine, Refine, Selection};
+use pastel::document::{self, Document, Job};
+use pastel::floating::Quad;
+use pastel::gradient::{self, Gradient, Spread, Stop};
+use pastel::brush::{Brush, Erase, Tip};
+use pastel::stamp::{self, Stamp};
+use pastel::stabilizer::Stabilizer;
+use pastel::resample::{Filter, Fit, Interpolation};
+use pastel::task::Task;
+
+mod layers_panel;
+use crate::layers_panel::LayersPanel;
+
+use pastel::batch;
+use pastel::project::{self, Project};
+
 #[derive(Clone)]
 struct MySize {
     x: u32,
@@ -68,13 +89,19 @@ struct MySize {
 //canvas y offset position relative to main window
 const CANVASOFFSET: i32 = 200;
 
-// undo stack depth
-const UNDODEPTH: usize = 20;
-
 // enable disable help and status line
 const STATUSLINE: bool = true;
 
-const ZOOMSTEP: f32 = 0.5;
+// zoom is a view transform: each step multiplies (or divides) the zoom factor
+const ZOOMSTEP: f32 = 2.0;
+const ZOOMMIN: f32 = 0.125;
+const ZOOMMAX: f32 = 32.0;
+
+// width of the layers panel column on the right of the window
+const LAYERSWIDTH: u32 = 160;
+
+// brush library, under the user home directory
+const BRUSHESDIR: &str = ".pastel/brushes/";
 
 //default font location
 #[cfg(target_os = "linux")]
@@ -92,6 +119,12 @@ const DEFAULTFONT : &str = "/ui/pastel/fonts/Pacifico-Regular.ttf";
 const DEFAULTFONT : &str = "C:/Windows/Fonts/arial.ttf";
 
 fn main() {
+    //batch mode runs before anything needs a display or changes the current directory
+    if env::args().any(|arg| arg == "--batch") {
+        let args: Vec<String> = env::args().skip(1).collect();
+        process::exit(batch::run(&args));
+    }
+
     // deal with icons path under diferent os
     #[cfg(target_os = "linux")]
     let root = Path::new("./res/");
@@ -117,6 +150,11 @@ fn main() {
         None => println!("Impossible to get your home dir!"),
     }
 
+    //brushes defined or imported before
+    let brushes_dir = format!("{}{}", home_dir, BRUSHESDIR);
+    let stamps: Rc<RefCell<Vec<Arc<Stamp>>>> =
+        Rc::new(RefCell::new(stamp::library(&brushes_dir).into_iter().map(Arc::new).collect()));
+
     //canvas default size
     let mut size = MySize { x: 1024, y:500 };
 
@@ -140,8 +178,7 @@ fn main() {
     }
 
     //load canvas from existing file or create new one with filename size
-    let canvas = load_image(&filename, &size);
-    canvas.undo_save();
+    let (canvas, project) = load_image(&filename, &size);
     size.x = canvas.width();
     size.y = canvas.height();
 
@@ -153,30 +190,64 @@ fn main() {
     tools.insert("pen",vec![Property::new("Size","1"),Property::new("Opacity","100")]);
     tools.insert("line",vec![Property::new("Opacity","100")]);
     tools.insert("polyline",vec![Property::new("Size","1"),Property::new("Opacity","100")]); 
-    tools.insert("brush",vec![Property::new("Size","10"),Property::new("Opacity","100"),Property::new("Shape","0")]);
-    tools.insert("fill",vec![Property::new("Opacity","100")]);
+    // Shape 0 round, 1 square, 2 copy buffer, 3 Stamp (name of a brush in the library),
+    // Hardness Spacing Flow and jitters in %, Angle in degrees
+    tools.insert("brush",vec![Property::new("Size","10"),Property::new("Opacity","100"),Property::new("Shape","0"),
+        Property::new("Hardness","100"),Property::new("Spacing","25"),Property::new("Flow","100"),Property::new("Angle","0"),
+        Property::new("SizeJitter","0"),Property::new("OpacityJitter","0"),Property::new("AngleJitter","0"),
+        Property::new("Scatter","0"),Property::new("Stamp","")]);
+    // erases with the brush shape and settings, Mode 0 transparency or background on a layer without it,
+    // 1 transparency, 2 background, 3 back to how it was Steps undo steps ago
+    tools.insert("eraser",vec![Property::new("Size","10"),Property::new("Opacity","100"),Property::new("Mode","0"),
+        Property::new("Steps","1")]);
+    tools.insert("fill",vec![Property::new("Opacity","100"),Property::new("Fuzziness","1"),
+        Property::new("Contiguous","1"),Property::new("Connectivity","4"),Property::new("SampleAll","0"),
+        Property::new("Perceptual","0"),Property::new("Antialias","1")]);
     tools.insert("rectangle",vec![Property::new("Opacity","100"),Property::new("Size","1"),Property::new("Filled","0")]);
     tools.insert("circle",vec![Property::new("Opacity","100"),Property::new("Size","1"),Property::new("Filled","0")]);
     tools.insert("paste",vec![Property::new("Opacity","100")]);
     tools.insert("marquee",vec![Property::new("Opacity","100")]);
+    tools.insert("ellipse_marquee",vec![Property::new("Opacity","100")]);
+    tools.insert("lasso",vec![Property::new("Opacity","100")]);
+    tools.insert("polygon_lasso",vec![Property::new("Opacity","100")]);
+    // Transparent 1 leaves transparency where the pixels are lifted, 0 what Clear leaves,
+    // Mode 0 scale, 1 skew, 2 perspective, Interpolation 0 nearest, 1 bilinear, 2 bicubic
+    tools.insert("move",vec![Property::new("Opacity","100"),Property::new("Transparent","0"),
+        Property::new("Mode","0"),Property::new("Interpolation","1")]);
+    // Shape 0 linear, 1 radial, 2 conical, 3 diamond, Spread 0 pad, 1 repeat, 2 reflect,
+    // Colors 0 current to white, 1 current to transparent, 2 Stops ("position:#rrggbbaa ...")
+    tools.insert("gradient",vec![Property::new("Opacity","100"),Property::new("Shape","0"),Property::new("Spread","0"),
+        Property::new("Colors","0"),Property::new("Stops","0:#000000ff 0.5:#ff0000ff 1:#ffff0000"),Property::new("Dither","1")]);
     tools.insert("polygon",vec![Property::new("Opacity","100"),Property::new("Sides","6")]);
     tools.insert("text",vec![Property::new("Opacity","100"),Property::new("Size","8"),Property::new("Text","Pastel"),Property::new("Font",DEFAULTFONT)]);
     tools.insert("pan",vec![Property::new("Opacity","100")]);
-    tools.insert("magicwand",vec![Property::new("Opacity","100"),Property::new("Fuzziness","1")]);
+    // Fuzziness in percent
+    tools.insert("magicwand",vec![Property::new("Opacity","100"),Property::new("Fuzziness","1"),
+        Property::new("Contiguous","1"),Property::new("Connectivity","4"),Property::new("SampleAll","0"),Property::new("Perceptual","0"),
+        Property::new("Antialias","1")]);
     // not a real tool but a way to store general preferences
-    tools.insert("preferences",vec![Property::new("Antialias","1")]); 
+    // Combine: new selections 0 replace (= key), 1 add to (+), 2 subtract from (-), 3 intersect with (*) the current one
+    //Image > Rotate remembers its choices: Interpolation 0 nearest, 1 bilinear, 2 bicubic,
+    // Fit 0 crop, 1 expand canvas, 2 crop to inner rectangle, Fill 0 transparent, 1 current color
+    tools.insert("rotate",vec![Property::new("Interpolation","1"),Property::new("Fit","0"),Property::new("Fill","0")]);
+    //Image > Resize: Units 0 pixels, 1 percent, 2 inches, 3 centimeters at DPI, Aspect 0 locked, 1 free,
+    // Filter 0 nearest, 1 triangle, 2 Catmull-Rom, 3 gaussian, 4 Lanczos3
+    tools.insert("resize",vec![Property::new("Units","0"),Property::new("DPI","72"),Property::new("Aspect","0"),Property::new("Filter","4")]);
+    //Image > Canvas size: Anchor 0..8 row by row from top left, Fill 0 transparent, 1 current color
+    tools.insert("canvas_size",vec![Property::new("Anchor","4"),Property::new("Fill","0")]);
+    // Stabilizer: strength in % for freehand tools, 0 follows the pointer as it is
+    tools.insert("preferences",vec![Property::new("Antialias","1"),Property::new("Combine","0"),Property::new("Stabilizer","0")]); 
     // where to store current active tool
     tools.insert("tool",vec![Property::new("Current","pen")]); 
 
     //set initial active tool
     tools.select("pen");
 
-    //define current selection
-    let selection :  Rc<RefCell<Option<Rect>>> = Rc::new(RefCell::new(None));
-
     //if pastel_copy_buffer.png exists load it into canvas copy_buffer
-    //for copy/paste between instances 
-    *canvas.copy_buffer.borrow_mut() = load_buffer("/tmp/pastel_copy_buffer.png");
+    //for copy/paste between instances, a project keeps its own
+    if project.as_ref().map_or(true, |p| p.copy_buffer.is_none()) {
+        *canvas.copy_buffer.borrow_mut() = load_buffer("/tmp/pastel_copy_buffer.png");
+    }
 
     //implement GUI
     
@@ -187,7 +258,7 @@ fn main() {
 
     //themed resizable main window
     let theme = Theme::from_path("pasteltheme.css").unwrap();
-    let mut window_builder = WindowBuilder::new(Rect::new(100, 100, 1024, wy), &title);
+    let mut window_builder = WindowBuilder::new(Rect::new(100, 100, 1024 + LAYERSWIDTH, wy), &title);
     window_builder = window_builder.theme(theme);
     window_builder = window_builder.flags(&[orbclient::WindowFlag::Resizable]);
     let mut window = window_builder.build();
@@ -215,6 +286,13 @@ fn main() {
         .text("Ready")
         .visible(STATUSLINE);
 
+    //progress of long operations, at the end of the status line
+    let job_bar = ProgressBar::new();
+    job_bar
+        .position(1024 - 204, (window.height()-16) as i32)
+        .size(200, 12);
+    job_bar.visible(false);
+
     //define marquee widget (visible selection rectangle)
     let marquee = Marquee::new();
     marquee.visible(false);
@@ -474,12 +552,17 @@ fn main() {
 */
 
     // create a new palette at x,y,width,height linked to swatch and color picker
-    let palette=Palette::new(10,120,window.width(),50,swatch_clone,red_bar,
+    let palette=Palette::new(10,120,1024,50,swatch_clone,red_bar,
                             green_bar,blue_bar, red_label, green_label, blue_label);
 
     // show on window the standard palette
     palette.prepare(&window);
 
+    //palette and tool settings saved in a project
+    if let Some(ref project) = project {
+        restore_project(project, &palette, &tools);
+    }
+
     //clickable icon
     match Image::from_path( "pastel100.png" ) {
         Ok(image) => {
@@ -941,6 +1024,7 @@ fn main() {
                 .tooltip("Circular shape".to_owned())
                 .on_click(move |_image: &ToolbarIcon, _point: Point| {
                     tools_clone.set("brush","Shape",0);
+                    tools_clone.set("brush","Hardness",100);
                     //toggle shape in toolbar2
                     unsafe{(*toolbar2_clone).toggle();}
                     });
@@ -960,7 +1044,8 @@ fn main() {
             item.position(x, y)
                 .tooltip("Smooth edges circular shape".to_owned())
                 .on_click(move |_image: &ToolbarIcon, _point: Point| {
-                    tools_clone.set("brush","Shape",3);
+                    tools_clone.set("brush","Shape",0);
+                    tools_clone.set("brush","Hardness",0);
 
                     //toggle shape in toolbar2
                     unsafe{(*toolbar2_clone).toggle();}
@@ -1123,8 +1208,10 @@ fn main() {
     {
         let action = Action::new("Save");
         let canvas_clone = canvas.clone();
+        let palette_clone = palette.clone();
+        let tools_clone = tools.clone();
         action.on_click(move |_action: &Action, _point: Point| {
-                            match canvas_clone.save(&filename){
+                            match save_document(&filename, &canvas_clone, &palette_clone, &tools_clone){
                                 Ok(_) => (),
                                 Err(e) => popup("Error",&format!("{}",e)[..]),
                                 }  
@@ -1135,12 +1222,14 @@ fn main() {
     {
         let action = Action::new("Save As");
         let canvas_clone = canvas.clone();
+        let palette_clone = palette.clone();
+        let tools_clone = tools.clone();
         let home_dir_clone = home_dir.clone();
         //FIXME change filename after a SaveAs 
         action.on_click(move |_action: &Action, _point: Point| {
                             match dialog("Save As", "path:",&home_dir_clone[..]) {
                             Some(response) => {
-                                match canvas_clone.save(&(String::from(response))){
+                                match save_document(&response, &canvas_clone, &palette_clone, &tools_clone){
                                     Ok(_) => (),
                                     Err(e) => popup("Error",&format!("{}",e)[..]),
                                 }
@@ -1169,25 +1258,37 @@ fn main() {
 
     //Menu entries for edit
     
+    let undo_action = Action::new("Undo     Ctrl+Z");
     {
-        let action = Action::new("Undo     Ctrl+Z");
         let canvas_clone = canvas.clone();
         let status_clone = status.clone();
-        action.on_click(move |_action: &Action, _point: Point| {
+        undo_action.on_click(move |_action: &Action, _point: Point| {
             canvas_clone.undo();
         });
-        menuedit.add(&action);
+        menuedit.add(&undo_action);
     }
 
+    let redo_action = Action::new("Redo     ");
     {
-        let action = Action::new("Redo     ");
         let canvas_clone = canvas.clone();
         let status_clone = status.clone();
-        action.on_click(move |_action: &Action, _point: Point| {
+        redo_action.on_click(move |_action: &Action, _point: Point| {
             canvas_clone.redo();
         });
-        menuedit.add(&action);
-    }
+        menuedit.add(&redo_action);
+    }
+
+    //name the steps undo and redo would apply
+    canvas.on_history_change(move |document: &Document| {
+        match document.undo_label() {
+            Some(label) => undo_action.text(format!("Undo {}     Ctrl+Z", label)),
+            None => undo_action.text("Undo     Ctrl+Z"),
+        };
+        match document.redo_label() {
+            Some(label) => redo_action.text(format!("Redo {}", label)),
+            None => redo_action.text("Redo     "),
+        };
+    });
 
     menuedit.add(&Separator::new());
 
@@ -1203,19 +1304,123 @@ fn main() {
     }
 
     {
-        let action = Action::new("Clear selection");
+        let action = Action::new("Select ellipse");
+        let tools_clone = tools.clone();
+        let status_clone = status.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+            tools_clone.select("ellipse_marquee");
+            status_clone.text("Selecting... (click on canvas, move cursor to define an ellipse then click again)");
+        });
+        menuedit.add(&action);
+    }
+
+    {
+        let action = Action::new("Lasso");
+        let tools_clone = tools.clone();
+        let status_clone = status.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+            tools_clone.select("lasso");
+            status_clone.text("Selecting... (drag around the region, release the button to close it)");
+        });
+        menuedit.add(&action);
+    }
+
+    {
+        let action = Action::new("Polygonal lasso");
+        let tools_clone = tools.clone();
+        let status_clone = status.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+            tools_clone.select("polygon_lasso");
+            status_clone.text("Selecting... (click to place corners, click on the first one or press Enter to close)");
+        });
+        menuedit.add(&action);
+    }
+
+    {
+        let action = Action::new("Move selection");
+        let tools_clone = tools.clone();
+        let status_clone = status.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+            tools_clone.set("move", "Mode", 0);
+            tools_clone.select("move");
+            status_clone.text("Moving... (drag the selection, its handles scale it, the one on top turns it)");
+        });
+        menuedit.add(&action);
+    }
+
+    {
+        let action = Action::new("Skew");
+        let tools_clone = tools.clone();
+        let status_clone = status.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+            tools_clone.set("move", "Mode", 1);
+            tools_clone.select("move");
+            status_clone.text("Skewing... (click the selection, drag the handles on its sides)");
+        });
+        menuedit.add(&action);
+    }
+
+    {
+        let action = Action::new("Perspective");
+        let tools_clone = tools.clone();
+        let status_clone = status.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+            tools_clone.set("move", "Mode", 2);
+            tools_clone.select("move");
+            status_clone.text("Distorting... (click the selection, drag its corners anywhere)");
+        });
+        menuedit.add(&action);
+    }
+
+    {
+        let action = Action::new("Select all");
+        let canvas_clone = canvas.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+            canvas_clone.select_all();
+        });
+        menuedit.add(&action);
+    }
+
+    {
+        let action = Action::new("Deselect");
         let canvas_clone = canvas.clone();
-        let selection_clone = selection.clone();
         let size_clone = size.clone();
         let marquee_clone = marquee.clone();
         let status_clone = status.clone();
         action.on_click(move |_action: &Action, _point: Point| {
-            *selection_clone.borrow_mut()=None;
+            canvas_clone.deselect();
             marquee_clone.visible(false);
         });
         menuedit.add(&action);
     }
 
+    {
+        let action = Action::new("Invert selection");
+        let canvas_clone = canvas.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+            canvas_clone.invert_selection();
+        });
+        menuedit.add(&action);
+    }
+
+    {
+        let action = Action::new("Selection from mask");
+        let canvas_clone = canvas.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+            canvas_clone.selection_from_mask();
+        });
+        menuedit.add(&action);
+    }
+
+    {
+        let action = Action::new("Selection from alpha");
+        let canvas_clone = canvas.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+            canvas_clone.selection_from_alpha();
+        });
+        menuedit.add(&action);
+    }
+
     menuedit.add(&Separator::new());
 
     {
@@ -1286,9 +1491,40 @@ fn main() {
    // menuedit.add(&Separator::new());    
 
 
+    //Menu select
+    let menuselect = Menu::new("Select");
+    menuselect.position(90, 0).size(48, 16);
+
+    //Menu entries for select, each asks for a size in pixels
+    for &(name, text, suggestion, refine) in [
+        ("Feather", "pixels:", "5", Refine::Feather as fn(u32) -> Refine),
+        ("Grow", "pixels:", "2", Refine::Grow),
+        ("Shrink", "pixels:", "2", Refine::Shrink),
+        ("Border", "width:", "4", Refine::Border),
+        ("Smooth", "radius:", "2", Refine::Smooth),
+        ("Remove islands", "smaller than (pixels):", "16", Refine::RemoveIslands),
+    ].iter() {
+        let action = Action::new(name);
+        let canvas_clone = canvas.clone();
+        let status_clone = status.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+            if canvas_clone.selection().is_none() {
+                status_clone.text("Nothing is selected");
+                return;
+            }
+            match dialog(name, text, suggestion) {
+                Some(response) => {
+                    canvas_clone.refine_selection(refine(response.parse::<u32>().unwrap_or(0)));
+                },
+                None => {println!("Cancelled");},
+            }
+        });
+        menuselect.add(&action);
+    }
+
     //Menu mask
     let menumask = Menu::new("Mask");
-    menumask.position (90,0).size (36,16);
+    menumask.position (140,0).size (36,16);
     
     //Menu entries for mask
     {
@@ -1366,7 +1602,7 @@ fn main() {
 
     //Menu tool
     let menutools = Menu::new("Tools");
-    menutools.position(130, 0).size(48, 16);
+    menutools.position(180, 0).size(48, 16);
 
     //Menu entries for tools
     {
@@ -1413,7 +1649,7 @@ fn main() {
         let size_bar_clone = size_bar.clone();
         let size_label_clone = size_label.clone();
         let trans_bar_clone = trans_bar.clone();
-        //let trans_label_clone = trans_label.clone();
+        let trans_label_clone = trans_label.clone();
         action.on_click(move |_action: &Action, _point: Point| {
             tools_clone.select("brush");
             status_clone.text("Painting...");
@@ -1424,12 +1660,176 @@ fn main() {
             size_label_clone.text(format!("Size: {}",v));
             let o = tools_clone.get("brush","Opacity").unwrap();
             trans_bar_clone.value.set(o);
-            trans_label.text(format!("Opacity: {}%",o));
+            trans_label_clone.text(format!("Opacity: {}%",o));
             unsafe{(*toolbar2_clone).visible(true);}
         });
         menutools.add(&action);
     }
 
+    {
+        let action = Action::new("Eraser");
+        let tools_clone = tools.clone();
+        let status_clone = status.clone();
+        let toolbar2_clone = &mut toolbar2 as *mut Toolbar;
+        let size_bar_clone = size_bar.clone();
+        let size_label_clone = size_label.clone();
+        let trans_bar_clone = trans_bar.clone();
+        let trans_label_clone = trans_label.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+            let steps = tools_clone.get_str("eraser","Steps").unwrap();
+            let choices: [(&str, &[&str], usize); 1] = [
+                ("Erase to", &["Transparency or background", "Transparency", "Background", "Undo history"],
+                    tools_clone.get("eraser","Mode").unwrap() as usize),
+            ];
+            let (response, picked) = match choice_dialog("Eraser", &[("Undo steps back:", &steps[..])], &choices) {
+                Some(result) => result,
+                None => {println!("Cancelled"); return;},
+            };
+            tools_clone.set("eraser","Mode",picked[0] as i32);
+            if let Ok(steps) = response[0].trim().parse::<i32>() {
+                tools_clone.set("eraser","Steps",cmp::max(1, steps));
+            }
+            tools_clone.select("eraser");
+            status_clone.text("Erasing... (with the shape of the brush)");
+            size_label_clone.visible(true);
+            size_bar_clone.visible(true);
+            let v = tools_clone.get("eraser","Size").unwrap();
+            size_bar_clone.value.set(v);
+            size_label_clone.text(format!("Size: {}",v));
+            let o = tools_clone.get("eraser","Opacity").unwrap();
+            trans_bar_clone.value.set(o);
+            trans_label_clone.text(format!("Opacity: {}%",o));
+            unsafe{(*toolbar2_clone).visible(true);}
+        });
+        menutools.add(&action);
+    }
+
+    {
+        let action = Action::new("Brush settings");
+        let tools_clone = tools.clone();
+        let status_clone = status.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+            let names = ["Hardness", "Spacing", "Flow", "Angle", "SizeJitter", "OpacityJitter", "AngleJitter", "Scatter"];
+            let values: Vec<String> = names.iter().map(|name| tools_clone.get_str("brush", name).unwrap()).collect();
+            let fields = [("Hardness %:", &values[0][..]), ("Spacing %:", &values[1][..]), ("Flow %:", &values[2][..]),
+                ("Angle °:", &values[3][..]), ("Size jitter %:", &values[4][..]), ("Opacity jitter %:", &values[5][..]),
+                ("Angle jitter %:", &values[6][..]), ("Scatter %:", &values[7][..])];
+            let choices: [(&str, &[&str], usize); 1] = [
+                ("Tip", &["Round", "Square", "Copy buffer", "Chosen brush"], tools_clone.get("brush","Shape").unwrap() as usize),
+            ];
+            match choice_dialog("Brush settings", &fields, &choices) {
+                Some((response, picked)) => {
+                    tools_clone.set("brush","Shape",picked[0] as i32);
+                    for (name, value) in names.iter().zip(response.iter()) {
+                        match value.trim().parse::<i32>() {
+                            //spacing of nothing would never move on
+                            Ok(v) if *name == "Spacing" => tools_clone.set("brush", name, cmp::max(1, v)),
+                            Ok(v) if *name == "Angle" => tools_clone.set("brush", name, v),
+                            Ok(v) => tools_clone.set("brush", name, cmp::min(100, cmp::max(0, v))),
+                            Err(_) => { status_clone.text(format!("{} not changed, not a number", name)); },
+                        }
+                    }
+                    tools_clone.select("brush");
+                },
+                None => {println!("Cancelled");},
+            }
+        });
+        menutools.add(&action);
+    }
+
+    {
+        let action = Action::new("Choose brush");
+        let tools_clone = tools.clone();
+        let status_clone = status.clone();
+        let stamps_clone = stamps.clone();
+        let size_bar_clone = size_bar.clone();
+        let size_label_clone = size_label.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+            let stamps = stamps_clone.borrow();
+            if stamps.is_empty() {
+                status_clone.text("No brushes yet, define one from a selection or import one");
+                return;
+            }
+            let thumbnails: Vec<(String, orbimage::Image)> = stamps.iter()
+                .map(|stamp| (stamp.name.clone(), stamp.thumbnail(48)))
+                .collect();
+            if let Some(n) = image_dialog("Choose brush", &thumbnails) {
+                let stamp = &stamps[n];
+                let (w, h) = stamp.size();
+                let size = cmp::min(100, cmp::max(1, cmp::max(w, h) as i32 / 2));
+                tools_clone.select("brush");
+                tools_clone.set("brush","Shape",3);
+                tools_clone.set("brush","Stamp",stamp.name.clone());
+                tools_clone.set("brush","Spacing",cmp::max(1, (stamp.spacing * 100.0).round() as i32));
+                tools_clone.set("brush","Size",size);
+                size_bar_clone.value.set(size);
+                size_label_clone.text(format!("Size: {}",size));
+                status_clone.text(format!("Painting with {}...", stamp.name));
+            }
+        });
+        menutools.add(&action);
+    }
+
+    {
+        let action = Action::new("Define brush");
+        let canvas_clone = canvas.clone();
+        let status_clone = status.clone();
+        let stamps_clone = stamps.clone();
+        let brushes_dir = brushes_dir.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+            let image = match canvas_clone.selected_image() {
+                Some(image) => image,
+                None => { status_clone.text("Select what the brush paints first"); return; },
+            };
+            if let Some(name) = dialog("Define brush", "name:", "brush") {
+                let stamp = Stamp::from_image(name.trim(), &image);
+                let saved = fs::create_dir_all(&brushes_dir)
+                    .and_then(|_| stamp.save(format!("{}{}.gbr", brushes_dir, stamp_file(&stamp.name))));
+                match saved {
+                    Ok(_) => { status_clone.text(format!("Brush {} defined", stamp.name)); },
+                    Err(err) => { status_clone.text(format!("Brush {} not saved, {}", stamp.name, err)); },
+                }
+                add_stamp(&stamps_clone, stamp);
+            }
+        });
+        menutools.add(&action);
+    }
+
+    {
+        let action = Action::new("Import brush");
+        let status_clone = status.clone();
+        let stamps_clone = stamps.clone();
+        let home_dir_clone = home_dir.clone();
+        let brushes_dir = brushes_dir.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+            let mut f = FileDialog::new();
+            f.title = "Import brush (.gbr, .gih or image)".to_owned();
+            f.path = PathBuf::from(home_dir_clone.to_owned());
+            if let Some(path) = f.exec() {
+                match Stamp::load(&path) {
+                    Ok(stamp) => {
+                        //GIMP brushes are kept as they are, other images as .gbr
+                        let extension = path.extension().and_then(OsStr::to_str).unwrap_or("").to_lowercase();
+                        let saved = fs::create_dir_all(&brushes_dir).and_then(|_| {
+                            if stamp::STAMP_EXTENSIONS.contains(&&extension[..]) {
+                                fs::copy(&path, format!("{}{}.{}", brushes_dir, stamp_file(&stamp.name), extension)).map(|_| ())
+                            } else {
+                                stamp.save(format!("{}{}.gbr", brushes_dir, stamp_file(&stamp.name)))
+                            }
+                        });
+                        match saved {
+                            Ok(_) => { status_clone.text(format!("Brush {} imported", stamp.name)); },
+                            Err(err) => { status_clone.text(format!("Brush {} not kept, {}", stamp.name, err)); },
+                        }
+                        add_stamp(&stamps_clone, stamp);
+                    },
+                    Err(err) => { status_clone.text(format!("Brush not imported, {}", err)); },
+                }
+            }
+        });
+        menutools.add(&action);
+    }
+
     {
         let action = Action::new("Fill");
         let tools_clone = tools.clone();
@@ -1441,6 +1841,39 @@ fn main() {
         menutools.add(&action);
     }
     
+    {
+        let action = Action::new("Gradient");
+        let tools_clone = tools.clone();
+        let status_clone = status.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+            let stops = tools_clone.get_str("gradient","Stops").unwrap();
+            let choices: [(&str, &[&str], usize); 4] = [
+                ("Shape", &["Linear", "Radial", "Conical", "Diamond"], tools_clone.get("gradient","Shape").unwrap() as usize),
+                ("Colors", &["Current to white", "Current to transparent", "Stops"], tools_clone.get("gradient","Colors").unwrap() as usize),
+                ("Past the end", &["Last color", "Repeat", "Reflect"], tools_clone.get("gradient","Spread").unwrap() as usize),
+                ("Dither", &["Off", "On"], tools_clone.get("gradient","Dither").unwrap() as usize),
+            ];
+            match choice_dialog("Gradient", &[("Stops (pos:#rrggbbaa):", &stops[..])], &choices) {
+                Some((response, picked)) => {
+                    tools_clone.set("gradient","Shape",picked[0] as i32);
+                    tools_clone.set("gradient","Colors",picked[1] as i32);
+                    tools_clone.set("gradient","Spread",picked[2] as i32);
+                    tools_clone.set("gradient","Dither",picked[3] as i32);
+                    tools_clone.select("gradient");
+                    match gradient::parse_stops(&response[0]) {
+                        Ok(stops) => {
+                            tools_clone.set("gradient","Stops",gradient::stops_text(&stops));
+                            status_clone.text("Gradient... (click where it starts, then where it ends)");
+                        },
+                        Err(err) => { status_clone.text(format!("Gradient stops not changed, {}", err)); },
+                    }
+                },
+                None => {println!("Cancelled");},
+            }
+        });
+        menutools.add(&action);
+    }
+
     {
         let action = Action::new("Rectangle");
         let tools_clone = tools.clone();
@@ -1527,6 +1960,26 @@ fn main() {
 
     menutools.add(&Separator::new());
 
+    {
+        let action = Action::new("Stabilizer");
+        let status_clone = status.clone();
+        let tools_clone = tools.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+            let strength = tools_clone.get("preferences","Stabilizer").unwrap();
+            if let Some(response) = dialog("Stabilizer", "strength % (0 off):", &strength.to_string()) {
+                match response.trim().parse::<i32>() {
+                    Ok(v) => {
+                        let v = cmp::min(100, cmp::max(0, v));
+                        tools_clone.set("preferences","Stabilizer",v);
+                        status_clone.text(if v == 0 { "Stabilizer off".to_string() } else { format!("Stabilizer at {}%", v) });
+                    },
+                    Err(_) => { status_clone.text("Stabilizer not changed, not a number"); },
+                }
+            }
+        });
+        menutools.add(&action);
+    }
+
     {
         let action = Action::new("Antialias \u{2611}");
         let status_clone = status.clone();
@@ -1548,21 +2001,22 @@ fn main() {
 
     //Menu image
     let menuimage = Menu::new("Image");
-    menuimage.position (180,0).size (48,16);
+    menuimage.position (230,0).size (48,16);
 
     //Menu entries for image
 
     {
         let action = Action::new("Blur");
         let canvas_clone = canvas.clone();
-        let selection_clone = selection.clone();
+        let status_clone = status.clone();
+        let job_bar_clone = job_bar.clone();
+        let window_clone = &mut window as *mut Window;
         action.on_click(move |_action: &Action, _point: Point| {
                         match dialog("Blur", "quantity:","5.1") {
                             Some(response) => {
-                                canvas_clone.trans_selection(selection_clone.borrow()
-                                .unwrap_or(Rect{x:0,y:0, width: canvas_clone.rect.get().width -1 ,
-                                height: canvas_clone.rect.get().height-1}),
+                                let job = canvas_clone.transformation_job(canvas_clone.selection(),
                                 "blur",response.parse::<f32>().unwrap_or(0.0),0);
+                                unsafe { run_job(job, &canvas_clone, &mut *window_clone, &status_clone, &job_bar_clone); }
                             },
                             None => {println!("Cancelled");},
                         }
@@ -1573,14 +2027,15 @@ fn main() {
     {
         let action = Action::new("Unsharpen");
         let canvas_clone = canvas.clone();
-        let selection_clone = selection.clone();
+        let status_clone = status.clone();
+        let job_bar_clone = job_bar.clone();
+        let window_clone = &mut window as *mut Window;
         action.on_click(move |_action: &Action, _point: Point| {
                         match dialog("Unsharpen", "quantity:","5.1") {
                             Some(response) => {
-                                canvas_clone.trans_selection(selection_clone.borrow()
-                                .unwrap_or(Rect{x:0,y:0, width: canvas_clone.rect.get().width -1 ,
-                                height: canvas_clone.rect.get().height-1}),
+                                let job = canvas_clone.transformation_job(canvas_clone.selection(),
                                 "unsharpen",response.parse::<f32>().unwrap_or(0.0),0);
+                                unsafe { run_job(job, &canvas_clone, &mut *window_clone, &status_clone, &job_bar_clone); }
                             },
                             None => {println!("Cancelled");},
                         }
@@ -1591,11 +2046,12 @@ fn main() {
     {
         let action = Action::new("Verical flip");
         let canvas_clone = canvas.clone();
-        let selection_clone = selection.clone();
+        let status_clone = status.clone();
+        let job_bar_clone = job_bar.clone();
+        let window_clone = &mut window as *mut Window;
         action.on_click(move |_action: &Action, _point: Point| {
-                        canvas_clone.trans_selection(selection_clone.borrow()
-                        .unwrap_or(Rect{x:0,y:0, width: canvas_clone.rect.get().width -1 ,
-                             height: canvas_clone.rect.get().height-1}),"flip_vertical",0.0,0);
+                        let job = canvas_clone.transformation_job(canvas_clone.selection(), "flip_vertical",0.0,0);
+                        unsafe { run_job(job, &canvas_clone, &mut *window_clone, &status_clone, &job_bar_clone); }
                     });
         menuimage.add(&action);
     }
@@ -1603,11 +2059,12 @@ fn main() {
     {
         let action = Action::new("Horizontal flip");
         let canvas_clone = canvas.clone();
-        let selection_clone = selection.clone();
+        let status_clone = status.clone();
+        let job_bar_clone = job_bar.clone();
+        let window_clone = &mut window as *mut Window;
         action.on_click(move |_action: &Action, _point: Point| {
-                        canvas_clone.trans_selection(selection_clone.borrow()
-                        .unwrap_or(Rect{x:0,y:0, width: canvas_clone.rect.get().width -1 ,
-                             height: canvas_clone.rect.get().height-1}),"flip_horizontal",0.0,0);
+                        let job = canvas_clone.transformation_job(canvas_clone.selection(), "flip_horizontal",0.0,0);
+                        unsafe { run_job(job, &canvas_clone, &mut *window_clone, &status_clone, &job_bar_clone); }
                     });
         menuimage.add(&action);
     }
@@ -1615,20 +2072,17 @@ fn main() {
     {
         let action = Action::new("Rotate 90");
         let canvas_clone = canvas.clone();
-        let selection_clone = selection.clone();
-        let marquee_clone = marquee.clone();
+        let status_clone = status.clone();
+        let job_bar_clone = job_bar.clone();
+        let window_clone = &mut window as *mut Window;
         action.on_click(move |_action: &Action, _point: Point| {
-                        canvas_clone.trans_selection(selection_clone.borrow()
-                        .unwrap_or(Rect{x:0,y:0, width: canvas_clone.rect.get().width -1 ,
-                             height: canvas_clone.rect.get().height-1}),"rotate90",0.0,0);
-                        //rotate also selection if exists 
-                        let rect = selection_clone.borrow()
-                            .unwrap_or(Rect{x: 0, y: 0 , width: 0, height: 0});
-                        if rect.width >0 {
-                            *selection_clone.borrow_mut() = Some(Rect{x:rect.x, y:rect.y,
-                                width: rect.height, height: rect.width});
-                            //and marquee
-                            marquee_clone.size(rect.height, rect.width);
+                        //without a selection the whole canvas turns, all layers and its size
+                        let selection = canvas_clone.selection();
+                        let job = canvas_clone.transformation_job(selection, "rotate90", 0.0, 0);
+                        unsafe { run_job(job, &canvas_clone, &mut *window_clone, &status_clone, &job_bar_clone); }
+                        if selection.is_some() {
+                            //the marquee follows
+                            canvas_clone.select(canvas_clone.selection_shape().map(|shape| shape.rotated90()));
                         }
                     });
         menuimage.add(&action);
@@ -1637,15 +2091,29 @@ fn main() {
     {
         let action = Action::new("Rotate");
         let canvas_clone = canvas.clone();
-        let selection_clone = selection.clone();
+        let status_clone = status.clone();
+        let job_bar_clone = job_bar.clone();
+        let window_clone = &mut window as *mut Window;
         let marquee_clone = marquee.clone();
+        let tools_clone = tools.clone();
+        let swatch_clone = swatch.clone();
         action.on_click(move |_action: &Action, _point: Point| {
-                        match dialog("Rotate", "degrees:","45") {
-                            Some(response) => {
-                                canvas_clone.trans_selection(selection_clone.borrow()
-                                .unwrap_or(Rect{x:0,y:0, width: canvas_clone.rect.get().width -1 ,
-                                height: canvas_clone.rect.get().height-1}),
-                                "rotate",response.parse::<f32>().unwrap_or(0.0),0);
+                        let choices: [(&str, &[&str], usize); 3] = [
+                            ("Interpolation", &["Nearest", "Bilinear", "Bicubic"], tools_clone.get("rotate","Interpolation").unwrap() as usize),
+                            ("Canvas", &["Crop", "Expand to fit", "Crop to inner rectangle"], tools_clone.get("rotate","Fit").unwrap() as usize),
+                            ("Corners", &["Transparent", "Current color"], tools_clone.get("rotate","Fill").unwrap() as usize),
+                        ];
+                        match choice_dialog("Rotate", &[("Degrees (clockwise):", "45")], &choices) {
+                            Some((response, picked)) => {
+                                tools_clone.set("rotate","Interpolation",picked[0] as i32);
+                                tools_clone.set("rotate","Fit",picked[1] as i32);
+                                tools_clone.set("rotate","Fill",picked[2] as i32);
+                                let swc = swatch_clone.read();
+                                let fill = if picked[2] == 1 { Some(Color::rgb(swc.r(),swc.g(),swc.b())) } else { None };
+                                let job = canvas_clone.rotate_job(canvas_clone.selection(),
+                                    response[0].parse::<f32>().unwrap_or(0.0),
+                                    Interpolation::from_i32(picked[0] as i32), Fit::from_i32(picked[1] as i32), fill);
+                                unsafe { run_job(job, &canvas_clone, &mut *window_clone, &status_clone, &job_bar_clone); }
                             },
                             None => {println!("Cancelled");},
                         }
@@ -1656,11 +2124,12 @@ fn main() {
     {
         let action = Action::new("Brighten");
         let canvas_clone = canvas.clone();
-        let selection_clone = selection.clone();
+        let status_clone = status.clone();
+        let job_bar_clone = job_bar.clone();
+        let window_clone = &mut window as *mut Window;
         action.on_click(move |_action: &Action, _point: Point| {
-                        canvas_clone.trans_selection(selection_clone.borrow()
-                        .unwrap_or(Rect{x:0,y:0, width: canvas_clone.rect.get().width -1 ,
-                             height: canvas_clone.rect.get().height-1}),"brighten",0.0,0);
+                        let job = canvas_clone.transformation_job(canvas_clone.selection(), "brighten",0.0,0);
+                        unsafe { run_job(job, &canvas_clone, &mut *window_clone, &status_clone, &job_bar_clone); }
                     });
         menuimage.add(&action);
     }
@@ -1668,11 +2137,12 @@ fn main() {
     {
         let action = Action::new("Darken");
         let canvas_clone = canvas.clone();
-        let selection_clone = selection.clone();
+        let status_clone = status.clone();
+        let job_bar_clone = job_bar.clone();
+        let window_clone = &mut window as *mut Window;
         action.on_click(move |_action: &Action, _point: Point| {
-                        canvas_clone.trans_selection(selection_clone.borrow()
-                        .unwrap_or(Rect{x:0,y:0, width: canvas_clone.rect.get().width -1 ,
-                             height: canvas_clone.rect.get().height-1}),"darken",0.0,0);
+                        let job = canvas_clone.transformation_job(canvas_clone.selection(), "darken",0.0,0);
+                        unsafe { run_job(job, &canvas_clone, &mut *window_clone, &status_clone, &job_bar_clone); }
                     });
         menuimage.add(&action);
     }
@@ -1680,15 +2150,16 @@ fn main() {
     {
         let action = Action::new("Contrast");
         let canvas_clone = canvas.clone();
-        let selection_clone = selection.clone();
+        let status_clone = status.clone();
+        let job_bar_clone = job_bar.clone();
+        let window_clone = &mut window as *mut Window;
         let marquee_clone = marquee.clone();
         action.on_click(move |_action: &Action, _point: Point| {
                         match dialog("Contrast", "value (+/-):","10.0") {
                             Some(response) => {
-                                canvas_clone.trans_selection(selection_clone.borrow()
-                                .unwrap_or(Rect{x:0,y:0, width: canvas_clone.rect.get().width -1 ,
-                                height: canvas_clone.rect.get().height-1}),
+                                let job = canvas_clone.transformation_job(canvas_clone.selection(),
                                 "contrast",response.parse::<f32>().unwrap_or(0.0),0);
+                                unsafe { run_job(job, &canvas_clone, &mut *window_clone, &status_clone, &job_bar_clone); }
                             },
                             None => {println!("Cancelled");},
                         }
@@ -1699,12 +2170,13 @@ fn main() {
     {
         let action = Action::new("Invert");
         let canvas_clone = canvas.clone();
-        let selection_clone = selection.clone();
+        let status_clone = status.clone();
+        let job_bar_clone = job_bar.clone();
+        let window_clone = &mut window as *mut Window;
         action.on_click(move |_action: &Action, _point: Point| {
-            canvas_clone.trans_selection(selection_clone.borrow()
-                .unwrap_or(Rect{x:0,y:0, width: canvas_clone.rect.get().width -1 ,
-                    height: canvas_clone.rect.get().height-1}),"invert",0.0,0);
-        });
+                        let job = canvas_clone.transformation_job(canvas_clone.selection(), "invert",0.0,0);
+                        unsafe { run_job(job, &canvas_clone, &mut *window_clone, &status_clone, &job_bar_clone); }
+                    });
         menuimage.add(&action);
     }
     //menuimage.add(&Separator::new());
@@ -1712,9 +2184,12 @@ fn main() {
     {
         let action = Action::new("Grayscale");
         let canvas_clone = canvas.clone();
-        let selection_clone = selection.clone();
+        let status_clone = status.clone();
+        let job_bar_clone = job_bar.clone();
+        let window_clone = &mut window as *mut Window;
         action.on_click(move |_action: &Action, _point: Point| {
-                        canvas_clone.transformation("grayscale",0.0,0);
+                        let job = canvas_clone.transformation_job(canvas_clone.selection(), "grayscale",0.0,0);
+                        unsafe { run_job(job, &canvas_clone, &mut *window_clone, &status_clone, &job_bar_clone); }
                     });
         menuimage.add(&action);
     }
@@ -1722,15 +2197,48 @@ fn main() {
     {
         let action = Action::new("Resize");
         let canvas_clone = canvas.clone();
-        let selection_clone = selection.clone();
+        let tools_clone = tools.clone();
+        let status_clone = status.clone();
+        let job_bar_clone = job_bar.clone();
+        let window_clone = &mut window as *mut Window;
         action.on_click(move |_action: &Action, _point: Point| {
                         
-                        match new_dialog(&"Resize".to_owned()) { 
-                                Some(resolution) => {
-                                    let val: Vec<&str> = resolution.split('x').collect();
-                                    let x: i32 = val[0].parse().unwrap_or(640);
-                                    let y: i32 = val[1].parse().unwrap_or(480);
-                                    canvas_clone.transformation("resize",x as f32,y);
+                        let (width, height) = (canvas_clone.width(), canvas_clone.height());
+                        let units = tools_clone.get("resize","Units").unwrap() as usize;
+                        let dpi = tools_clone.get("resize","DPI").unwrap();
+                        let width_text = from_pixels(width, units, dpi as f32);
+                        let height_text = from_pixels(height, units, dpi as f32);
+                        let dpi_text = dpi.to_string();
+                        let fields = [("Width:", &width_text[..]), ("Height:", &height_text[..]), ("DPI (inches, cm):", &dpi_text[..])];
+                        let choices: [(&str, &[&str], usize); 3] = [
+                            ("Units", &["Pixels", "Percent", "Inches", "Centimeters"], units),
+                            ("Aspect ratio", &["Locked, height follows width", "Free"], tools_clone.get("resize","Aspect").unwrap() as usize),
+                            ("Filter", &["Nearest", "Triangle", "Catmull-Rom", "Gaussian", "Lanczos3"], tools_clone.get("resize","Filter").unwrap() as usize),
+                        ];
+                        match choice_dialog("Resize", &fields, &choices) {
+                                Some((response, picked)) => {
+                                    let dpi = response[2].trim().parse::<f32>().ok().filter(|&dpi| dpi >= 1.0).unwrap_or(dpi as f32);
+                                    tools_clone.set("resize","Units",picked[0] as i32);
+                                    tools_clone.set("resize","Aspect",picked[1] as i32);
+                                    tools_clone.set("resize","Filter",picked[2] as i32);
+                                    tools_clone.set("resize","DPI",dpi.round() as i32);
+                                    let new_width = to_pixels(&response[0], picked[0], dpi, width);
+                                    let new_height = to_pixels(&response[1], picked[0], dpi, height);
+                                    //with the ratio locked a missing width follows the height
+                                    let ratio = |n: u32, from: u32, to: u32| cmp::max(1, (n as f32 * to as f32 / from as f32).round() as u32);
+                                    let size = match (new_width, new_height, picked[1] == 0) {
+                                        (Some(w), _, true) => Some((w, ratio(w, width, height))),
+                                        (None, Some(h), true) => Some((ratio(h, height, width), h)),
+                                        (Some(w), Some(h), false) => Some((w, h)),
+                                        _ => None,
+                                    };
+                                    match size {
+                                        Some((w, h)) => {
+                                            let job = canvas_clone.resize_job(w, h, Filter::from_i32(picked[2] as i32));
+                                            unsafe { run_job(job, &canvas_clone, &mut *window_clone, &status_clone, &job_bar_clone); }
+                                        },
+                                        None => { status_clone.text("Resize: invalid size"); },
+                                    }
                                                 },
                                     None => println!("Resize cancelled"),
                                 }
@@ -1738,12 +2246,88 @@ fn main() {
         menuimage.add(&action);
     }
 
+    {
+        let action = Action::new("Canvas size");
+        let canvas_clone = canvas.clone();
+        let status_clone = status.clone();
+        let job_bar_clone = job_bar.clone();
+        let window_clone = &mut window as *mut Window;
+        let tools_clone = tools.clone();
+        let swatch_clone = swatch.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+                        let (width, height) = (canvas_clone.width(), canvas_clone.height());
+                        let (width_text, height_text) = (width.to_string(), height.to_string());
+                        let choices: [(&str, &[&str], usize); 2] = [
+                            ("Anchor", &["Top left", "Top", "Top right", "Left", "Center", "Right",
+                                         "Bottom left", "Bottom", "Bottom right"], tools_clone.get("canvas_size","Anchor").unwrap() as usize),
+                            ("New area", &["Transparent", "Current color"], tools_clone.get("canvas_size","Fill").unwrap() as usize),
+                        ];
+                        match choice_dialog("Canvas size", &[("Width:", &width_text[..]), ("Height:", &height_text[..])], &choices) {
+                            Some((response, picked)) => {
+                                tools_clone.set("canvas_size","Anchor",picked[0] as i32);
+                                tools_clone.set("canvas_size","Fill",picked[1] as i32);
+                                match (to_pixels(&response[0], 0, 1.0, width), to_pixels(&response[1], 0, 1.0, height)) {
+                                    (Some(new_width), Some(new_height)) => {
+                                        //the anchor stays put, the canvas grows or shrinks away from it
+                                        let (column, row) = ((picked[0] % 3) as i32, (picked[0] / 3) as i32);
+                                        let x = (new_width as i32 - width as i32) * column / 2;
+                                        let y = (new_height as i32 - height as i32) * row / 2;
+                                        let swc = swatch_clone.read();
+                                        let fill = if picked[1] == 1 { Some(Color::rgb(swc.r(),swc.g(),swc.b())) } else { None };
+                                        let job = canvas_clone.canvas_size_job(new_width, new_height, x, y, fill);
+                                        unsafe { run_job(job, &canvas_clone, &mut *window_clone, &status_clone, &job_bar_clone); }
+                                    },
+                                    _ => { status_clone.text("Canvas size: invalid size"); },
+                                }
+                            },
+                            None => println!("Canvas size cancelled"),
+                        }
+                    });
+        menuimage.add(&action);
+    }
+
+    {
+        let action = Action::new("Crop to selection");
+        let canvas_clone = canvas.clone();
+        let status_clone = status.clone();
+        let job_bar_clone = job_bar.clone();
+        let window_clone = &mut window as *mut Window;
+        action.on_click(move |_action: &Action, _point: Point| {
+                        match canvas_clone.selection() {
+                            Some(rect) => {
+                                let job = canvas_clone.crop_job(rect);
+                                unsafe { run_job(job, &canvas_clone, &mut *window_clone, &status_clone, &job_bar_clone); }
+                            },
+                            None => { status_clone.text("Nothing to crop to, select something first"); },
+                        }
+                    });
+        menuimage.add(&action);
+    }
+
+    {
+        let action = Action::new("Autocrop");
+        let canvas_clone = canvas.clone();
+        let status_clone = status.clone();
+        let job_bar_clone = job_bar.clone();
+        let window_clone = &mut window as *mut Window;
+        action.on_click(move |_action: &Action, _point: Point| {
+                        match canvas_clone.autocrop_job() {
+                            Some(job) => unsafe { run_job(Some(job), &canvas_clone, &mut *window_clone, &status_clone, &job_bar_clone); },
+                            None => { status_clone.text("No border to crop"); },
+                        }
+                    });
+        menuimage.add(&action);
+    }
+
     {
         let action = Action::new("Edge detection");
         let canvas_clone = canvas.clone();
-        let selection_clone = selection.clone();
+        let status_clone = status.clone();
+        let job_bar_clone = job_bar.clone();
+        let window_clone = &mut window as *mut Window;
         action.on_click(move |_action: &Action, _point: Point| {
-                        canvas_clone.transformation("edge",0.0,0);
+                        let job = canvas_clone.transformation_job(canvas_clone.selection(), "edge",0.0,0);
+                        unsafe { run_job(job, &canvas_clone, &mut *window_clone, &status_clone, &job_bar_clone); }
                     });
         menuimage.add(&action);
     }
@@ -1759,7 +2343,7 @@ fn main() {
 
     //Menu palette
     let menupalette = Menu::new("Palette");
-        menupalette.position (230, 0).size(64, 16);
+        menupalette.position (280, 0).size(64, 16);
 
     //Menu entries for palette
     {
@@ -1829,43 +2413,23 @@ fn main() {
 
     //Menu view
     let menuview = Menu::new("View");
-    menuview.position(295, 0).size(32, 16);
+    menuview.position(345, 0).size(32, 16);
     
     //menu entries for view
     {
         let action = Action::new("Zoom in");
-        let window_clone = &mut window as *mut Window;
-        let size_clone = size.clone();
-        //let tools_clone = tools.clone();
         let canvas_clone = canvas.clone();
-        let status_clone = status.clone();
         action.on_click(move |_action: &Action, _point: Point| {
                             canvas_clone.zoom_in();
-                            //resize window to fit image + status bar
-                            let newx = *cmp::max(&size_clone.x, &canvas_clone.width());
-                            let newy = *cmp::max(&size_clone.y, &(canvas_clone.height() + 18 + CANVASOFFSET as u32));
-                            unsafe{ (*window_clone).set_size(newx, newy) }
-                            status_clone.text(format!("Zoomed in ...({}%)",(100.0 * canvas_clone.zoom_factor.get()) as i32));
-                            status_clone.position(4, canvas_clone.height()as i32 + CANVASOFFSET);
                         });
         menuview.add(&action);
     }
 
     {
         let action = Action::new("Zoom out");
-        let window_clone = &mut window as *mut Window;
-        let size_clone = size.clone();
-        //let tools_clone = tools.clone();
         let canvas_clone = canvas.clone();
-        let status_clone = status.clone();
         action.on_click(move |_action: &Action, _point: Point| {
                             canvas_clone.zoom_out();
-                            //resize window to fit image + status bar
-                            let newx = *cmp::max(&size_clone.x, &canvas_clone.width());
-                            let newy = *cmp::max(&size_clone.y, &(canvas_clone.height() + 18 + CANVASOFFSET as u32));
-                            unsafe{ (*window_clone).set_size(newx, newy) }
-                            status_clone.text(format!("Zoomed out ...({}%)",(100.0 * canvas_clone.zoom_factor.get())as i32));
-                            status_clone.position(4, canvas_clone.height()as i32 + CANVASOFFSET );
                         });
         menuview.add(&action);
     }
@@ -1886,10 +2450,136 @@ fn main() {
     }
     
 
+    //Menu layer
+    let menulayer = Menu::new("Layer");
+    menulayer.position(385, 0).size(40, 16);
+
+    //menu entries for layer
+    {
+        let action = Action::new("New");
+        let canvas_clone = canvas.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+                            canvas_clone.new_layer();
+                        });
+        menulayer.add(&action);
+    }
+
+    {
+        let action = Action::new("Duplicate");
+        let canvas_clone = canvas.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+                            canvas_clone.duplicate_layer();
+                        });
+        menulayer.add(&action);
+    }
+
+    {
+        let action = Action::new("Merge down");
+        let canvas_clone = canvas.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+                            if !canvas_clone.merge_down() {
+                                popup("Error", "Cannot merge: layer is hidden, lowest or the one below is locked");
+                            }
+                        });
+        menulayer.add(&action);
+    }
+
+    {
+        let action = Action::new("Flatten");
+        let canvas_clone = canvas.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+                            canvas_clone.flatten();
+                        });
+        menulayer.add(&action);
+    }
+
+    menulayer.add(&Separator::new());
+
+    {
+        let action = Action::new("Move up");
+        let canvas_clone = canvas.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+                            canvas_clone.move_layer(true);
+                        });
+        menulayer.add(&action);
+    }
+
+    {
+        let action = Action::new("Move down");
+        let canvas_clone = canvas.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+                            canvas_clone.move_layer(false);
+                        });
+        menulayer.add(&action);
+    }
+
+    {
+        let action = Action::new("Delete");
+        let canvas_clone = canvas.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+                            if !canvas_clone.delete_layer() {
+                                popup("Error", "Cannot delete the only layer");
+                            }
+                        });
+        menulayer.add(&action);
+    }
+
+    menulayer.add(&Separator::new());
+
+    {
+        let action = Action::new("Opacity");
+        let canvas_clone = canvas.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+                            let n = canvas_clone.active_layer();
+                            let opacity = canvas_clone.layers()[n].opacity;
+                            match dialog("Layer opacity", "opacity %:", &opacity.to_string()) {
+                                Some(response) => match response.trim().parse::<u8>() {
+                                    Ok(value) if value <= 100 => canvas_clone.set_layer_opacity(n, value),
+                                    _ => popup("Error", "Opacity must be a number from 0 to 100"),
+                                },
+                                None => {println!("Cancelled");},
+                            }
+                        });
+        menulayer.add(&action);
+    }
+
+    {
+        let action = Action::new("Blend mode");
+        let canvas_clone = canvas.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+                            let n = canvas_clone.active_layer();
+                            let blend = canvas_clone.layers()[n].blend;
+                            let modes: Vec<&str> = BLEND_MODES.iter().map(|mode| mode.name()).collect();
+                            let text = format!("{}:", modes.join(", "));
+                            match dialog("Layer blend mode", &text, blend.name()) {
+                                Some(response) => match response.parse::<BlendMode>() {
+                                    Ok(mode) => canvas_clone.set_layer_blend(n, mode),
+                                    Err(e) => popup("Error", &e),
+                                },
+                                None => {println!("Cancelled");},
+                            }
+                        });
+        menulayer.add(&action);
+    }
+
+    {
+        let action = Action::new("Rename");
+        let canvas_clone = canvas.clone();
+        action.on_click(move |_action: &Action, _point: Point| {
+                            let n = canvas_clone.active_layer();
+                            let name = canvas_clone.layers()[n].name.clone();
+                            match dialog("Rename layer", "name:", &name) {
+                                Some(response) => canvas_clone.rename_layer(n, &response),
+                                None => {println!("Cancelled");},
+                            }
+                        });
+        menulayer.add(&action);
+    }
+
     //Menu help
 
     let menuhelp = Menu::new("Help");
-    menuhelp.position(335, 0).size(32, 16);
+    menuhelp.position(430, 0).size(32, 16);
 
     //menu entries for help
 
@@ -1906,12 +2596,59 @@ fn main() {
     let click_pos: Rc<RefCell<Option<Point>>> = Rc::new(RefCell::new(None));
     let window_clone = &mut window as *mut Window;
     let click_pos_clone = click_pos.clone();
-    let selection_clone = selection.clone();
+    let stamps_clone = stamps.clone();
+    let freehand: Rc<RefCell<Option<Freehand>>> = Rc::new(RefCell::new(None));
+    let freehand_clone = freehand.clone();
     let marquee_clone = marquee.clone();
     let tools_clone = tools.clone();
     let status_clone = status.clone();
     let combo_box_clone = combo_box.clone();
     let paths_clone = paths.clone();
+    let job_bar_clone = job_bar.clone();
+    //status and progress of fills started by a click
+    let fill_status = status.clone();
+    let fill_job_bar = job_bar.clone();
+    let move_status = status.clone();
+
+    //layers panel on the right of the palette and canvas
+    let layers_panel = LayersPanel::new(canvas.clone());
+    layers_panel
+        .position(1024, 10)
+        .size(LAYERSWIDTH - 10, window.height() - 28);
+    let layers_panel_clone = layers_panel.clone();
+    canvas.viewport(1024, window.height() - CANVASOFFSET as u32 - 18);
+
+    //keep marquee and status line in sync with zoom and pan
+    {
+        let marquee_clone = marquee.clone();
+        let status_clone = status.clone();
+        let last_zoom = Cell::new(canvas.zoom_factor.get());
+        canvas.on_view_change(move |canvas: &Canvas| {
+            match canvas.selection() {
+                Some(selection) => {
+                    let rect = canvas.to_screen_rect(selection);
+                    let corner = Point::new(rect.x, rect.y);
+                    //a rectangle is drawn by the marquee itself
+                    let outline = if canvas.selection_shape().map_or(true, |shape| shape.is_rectangle()) {
+                        Vec::new()
+                    } else {
+                        canvas.selection_outline().into_iter().map(|(p1, p2)| (p1 - corner, p2 - corner)).collect()
+                    };
+                    marquee_clone.outline(outline);
+                    marquee_clone
+                        .position(rect.x, rect.y + CANVASOFFSET)
+                        .size(rect.width, rect.height)
+                        .visible(true);
+                },
+                None => marquee_clone.visible(false),
+            }
+            let zoom = canvas.zoom_factor.get();
+            if zoom != last_zoom.get() {
+                last_zoom.set(zoom);
+                status_clone.text(format!("Zoom {}%", (100.0 * zoom) as i32));
+            }
+        });
+    }
 
     canvas
         .position(0, CANVASOFFSET)
@@ -1936,8 +2673,25 @@ fn main() {
                         canvas.paint_on_mask();
                         canvas.emit_click(Point{x: 0, y: 0});  //trigger redraw
                 },
+                //how the next selections go with the current one
+                '=' | '+' | '-' | '*' => {
+                        let (combine, text) = match key {
+                            '+' => (1, "New selections add to the current one"),
+                            '-' => (2, "New selections subtract from the current one"),
+                            '*' => (3, "New selections intersect with the current one"),
+                            _ => (0, "New selections replace the current one"),
+                        };
+                        tools_clone.set("preferences", "Combine", combine);
+                        status_clone.text(text);
+                },
                 '@' => {
-                        status_clone.position(4, canvas.height()as i32 + CANVASOFFSET);
+                        let (width, height) = unsafe { ((*window_clone).width(), (*window_clone).height()) };
+                        status_clone.position(4, height as i32 - 18);
+                        job_bar_clone.position(width as i32 - LAYERSWIDTH as i32 - 204, height as i32 - 16);
+                        layers_panel_clone
+                            .position(width as i32 - LAYERSWIDTH as i32, 10)
+                            .size(LAYERSWIDTH - 10, height - 28);
+                        canvas.viewport(width - LAYERSWIDTH, height - CANVASOFFSET as u32 - 18);
                 },
                 _ => (),
             }
@@ -1950,13 +2704,25 @@ fn main() {
         }
     })
 
-    .on_clear_click(move |_ , _point:Point|{
+    .on_clear_click(move |canvas: &Canvas, _point:Point|{
         // clears last cursor position 
         let mut ck=click_pos_clone.borrow_mut();
         *ck = None;
+        //a stabilized stroke catches up with the pointer
+        if let Some(freehand) = freehand_clone.borrow_mut().take() {
+            freehand.finish(canvas);
+        }
+        canvas.end_stroke();
     })
 
-    .on_click(move |canvas: &Canvas, point: Point| {
+    .on_click(move |canvas: &Canvas, screen_point: Point| {
+        //stabilized freehand tools draw the smoothed path instead of each pointer move
+        if let Some(ref mut freehand) = *freehand.borrow_mut() {
+            freehand.to(canvas, canvas.to_document_f32(screen_point));
+            return;
+        }
+        //painting happens in document coordinates, interactive helpers work on screen
+        let point = canvas.to_document(screen_point);
         let click = click_pos.clone();
         let size = size_bar.clone().value.get();
         //let swatch_clone = swatch.clone();
@@ -1970,31 +2736,37 @@ fn main() {
             let color = Color::rgba(swc.r(),swc.g(),swc.b(),a);
             //let tools_clone = tools.clone();
             let antialias = tools.get("preferences","Antialias").unwrap(); //tools_clone.get("preferences","Antialias").unwrap();
+            //how selection tools and the magic wand go with the current selection
+            let combine = Combine::from_i32(tools.get("preferences","Combine").unwrap());
+            let stabilizer = tools.get("preferences","Stabilizer").unwrap() as f32 / 100.0;
             let selected_tool = tools.current(); //tools_clone.current();
 
             //tools that dont need prev_position
             match selected_tool.as_ref() {
                 "pen"  => canvas.pixel(point.x, point.y, color),
-                "fill" => canvas.fill(point.x, point.y,color),
+                "fill" => {
+                    let job = canvas.fill_job(point.x, point.y, color, &color_match(&tools, "fill"));
+                    unsafe { run_job(job, canvas, &mut *window_clone, &fill_status, &fill_job_bar); }
+                },
                 "magicwand" => {
-                    canvas.magicwand(point.x, point.y);
+                    canvas.magicwand(point.x, point.y, &color_match(&tools, "magicwand"), combine);
                     tools.select("pen"); //#FIXME dirty workaround to avoid multiple clicks for now
                     },
                 "rectangle" => {
-                    canvas.undo_save();
+                    canvas.undo_save("Rectangle");
                     let filled = tools.get("rectangle","Filled").unwrap(); //tools_clone.get("rectangle","Filled").unwrap();
                     let mut width = tools.get("rectangle","Size").unwrap(); //tools_clone.get("rectangle","Size").unwrap();
                     let mut myselection = Rect::new(0,0,0,0);
                     if let Some(selection) = unsafe {
                         canvas.image.borrow_mut().new_select_rect(
-                            point.x,
-                            point.y,
+                            screen_point.x,
+                            screen_point.y,
                             Color::rgb(100,100,100),
                             0,
                             &mut *window_clone
                         )
                         } {
-                            myselection = selection;
+                            myselection = canvas.to_document_rect(selection);
                         }
                         if filled == 1 {
                             if myselection.height > myselection.width {
@@ -2013,19 +2785,42 @@ fn main() {
                             );
                         }
                 },
+                "gradient" => {
+                    //the line the gradient follows
+                    if let Some((x1, y1, x2, y2)) = unsafe {
+                        canvas.image.borrow_mut().interact_line(screen_point.x,
+                            screen_point.y,
+                            Color::rgb(100,100,100),
+                            1,
+                            false,
+                            &mut *window_clone
+                        )
+                    } {
+                        if (x1, y1) != (x2, y2) {
+                            canvas.undo_save("Gradient");
+                            canvas.gradient(&tool_gradient(&tools, color),
+                                canvas.to_document_f32(Point::new(x1, y1)),
+                                canvas.to_document_f32(Point::new(x2, y2)));
+                        }
+                    }
+                },
                 "polyline" => { 
-                    canvas.undo_save();
+                    canvas.undo_save("Polyline");
                     let width = tools.get("polyline","Size").unwrap(); //tools_clone.get("polyline","Size").unwrap();
                     let mut tu = (point.x,point.y,point.x,point.y);
-                    if let Some(tuple) = unsafe {
-                        canvas.image.borrow_mut().interact_line(point.x,
-                            point.y,
+                    if let Some((x1, y1, x2, y2)) = unsafe {
+                        canvas.image.borrow_mut().interact_line(screen_point.x,
+                            screen_point.y,
                             color,
                             width,
                             antialias == 1,
                             &mut *window_clone
                         )
-                    } { tu = tuple }
+                    } {
+                        let p1 = canvas.to_document(Point::new(x1, y1));
+                        let p2 = canvas.to_document(Point::new(x2, y2));
+                        tu = (p1.x, p1.y, p2.x, p2.y);
+                    }
                     let (x1, y1, x2, y2) = tu;
                     for d in 0..width {
                         if antialias == 1 {
@@ -2037,15 +2832,10 @@ fn main() {
                 },
                 "copy" => {
                     //let mut image = canvas.image.borrow_mut();
-                    match *selection_clone.borrow() {
-                        Some(selection) => {
-                             //*canvas.copy_buffer.borrow_mut() = image.copy_selection(
-                             canvas.copy_selection(
-                                selection.x,
-                                selection.y,
-                                selection.width,
-                                selection.height
-                            );
+                    match canvas.selection() {
+                        Some(_selection) => {
+                             //shaped selections leave what is outside them transparent
+                             canvas.copy_selected();
                              //save buffer to disk as pastel_copy_buffer.png so we can reload when starting new program instance
                              let newcanvas = Canvas::from_image(canvas.copy_buffer.borrow().clone());
                              let path = "/tmp/pastel_copy_buffer.png".to_string();
@@ -2056,33 +2846,64 @@ fn main() {
                 },
                "marquee"=> {
                     marquee_clone.visible(false);
-                    canvas.undo_save();
-                    let mut image = canvas.image.borrow_mut();
-                    if let Some(selection) =
-                        unsafe{ image.select_rect(point.x, point.y,&mut *window_clone) }
-                    {
-                        *selection_clone.borrow_mut() = Some(selection);
-                        marquee_clone
-                            .position(selection.x,selection.y+CANVASOFFSET)
-                            .size(selection.width,selection.height)
-                            .visible(true);
+                    let selection = unsafe{ canvas.image.borrow_mut().select_rect(screen_point.x, screen_point.y,&mut *window_clone) };
+                    if let Some(selection) = selection {
+                        let rect = canvas.to_document_rect(selection);
+                        canvas.select_with(Selection::rectangle(pastel::Rect::new(rect.x, rect.y, rect.width, rect.height)), combine);
+                    }
+                },
+                "ellipse_marquee" => {
+                    marquee_clone.visible(false);
+                    let selection = unsafe{ canvas.image.borrow_mut().select_ellipse(screen_point.x, screen_point.y, &mut *window_clone) };
+                    if let Some(selection) = selection {
+                        let rect = canvas.to_document_rect(selection);
+                        if rect.width > 0 && rect.height > 0 {
+                            canvas.select_with(Selection::ellipse(pastel::Rect::new(rect.x, rect.y, rect.width, rect.height)), combine);
+                        }
                     }
                 },
+                "lasso" | "polygon_lasso" => {
+                    marquee_clone.visible(false);
+                    let points = unsafe {
+                        let mut image = canvas.image.borrow_mut();
+                        if selected_tool == "lasso" {
+                            image.select_lasso(screen_point.x, screen_point.y, stabilizer, &mut *window_clone)
+                        } else {
+                            image.select_polygon(screen_point.x, screen_point.y, &mut *window_clone)
+                        }
+                    };
+                    if let Some(points) = points {
+                        let points = points.into_iter()
+                            .map(|(x, y)| { let p = canvas.to_document(Point::new(x, y)); (p.x, p.y) })
+                            .collect();
+                        if let Some(selection) = Selection::polygon(points) {
+                            canvas.select_with(selection, combine);
+                        }
+                    }
+                },
+                "move" => {
+                    let transparent = tools.get("move","Transparent").unwrap() == 1;
+                    let mode = TransformMode::from_i32(tools.get("move","Mode").unwrap());
+                    let interpolation = Interpolation::from_i32(tools.get("move","Interpolation").unwrap());
+                    unsafe { move_selection(canvas, &mut *window_clone, &marquee_clone, &move_status,
+                        transparent, mode, interpolation, screen_point); }
+                },
                 "paste" => {
-                    canvas.undo_save();
+                    canvas.undo_save("Paste");
                     if let Some(tuple) = unsafe {
                         canvas.interact_paste(
-                            point.x,
-                            point.y,
+                            screen_point.x,
+                            screen_point.y,
                             a,
                             &mut *window_clone
                         )
                     } {
-                        canvas.paste_buffer(tuple.0, tuple.1 , a);
+                        let position = canvas.to_document(Point::new(tuple.0, tuple.1));
+                        canvas.paste_buffer(position.x, position.y , a);
                     }
                 },
                 "circle" => {
-                    canvas.undo_save();
+                    canvas.undo_save("Circle");
                     let filled = tools.get("circle","Filled").unwrap(); //tools_clone.get("circle","Filled").unwrap();
                     let width = tools.get("circle","Size").unwrap(); //tools_clone.get("circle","Size").unwrap();
                     let radius;
@@ -2090,8 +2911,8 @@ fn main() {
                     {
                         let mut image = canvas.image.borrow_mut();
                         if let Some((r,angle)) = unsafe {
-                            image.interact_circle(point.x, point.y, color, &mut *window_clone)
-                        } {myr = r;}
+                            image.interact_circle(screen_point.x, screen_point.y, color, &mut *window_clone)
+                        } {myr = (r as f32 / canvas.zoom_factor.get()) as i32;}
                     }
                     {
                         if filled == 1 {
@@ -2109,19 +2930,19 @@ fn main() {
                 },
                 "polygon" => {
                     let sides = tools.get("polygon","Sides").unwrap(); //tools_clone.get("polygon","Sides").unwrap(); 
-                    canvas.undo_save();
+                    canvas.undo_save("Polygon");
                     let mut aangle=0_f32;
                     let mut rr=0;
                     {
                         let mut image = canvas.image.borrow_mut();
                         if let Some((r,angle)) = unsafe{
                             image.interact_circle(
-                                point.x,
-                                point.y,
+                                screen_point.x,
+                                screen_point.y,
                                 color,
                                 &mut *window_clone
                             )
-                        } {aangle = angle; rr = r;  }
+                        } {aangle = angle; rr = (r as f32 / canvas.zoom_factor.get()) as i32;  }
                     }
                     canvas.polygon(point.x,point.y,rr,sides as u32, aangle, color, antialias==1);
                 },
@@ -2132,15 +2953,16 @@ fn main() {
                     let font_path = tools.get_str("text","Font").unwrap(); //tools_clone.get_str("text","Font").unwrap();
                     let font_n = combo_box_clone.selected() as usize;
                     let path = &paths_clone.borrow()[font_n];
-                    canvas.text(&text, path, point.x, point.y - CANVASOFFSET, color, size );
+                    canvas.text(&text, path, point.x, point.y, color, size );
                 },
                 _ => (),
                 }
 
             //tools that need prev_position to work
-            if let Some(prev_position) = *prev_opt {
+            if let Some(prev_screen) = *prev_opt {
+                let prev_position = canvas.to_document(prev_screen);
                 match selected_tool.as_ref() {
-                     "pan" => {canvas.pan(point.x - prev_position.x, point.y - prev_position.y);},
+                     "pan" => {canvas.pan(screen_point.x - prev_screen.x, screen_point.y - prev_screen.y);},
                     "line" => { 
                         if antialias == 1 {
                             canvas.wu_line(prev_position.x,
@@ -2156,49 +2978,52 @@ fn main() {
                                         color);
                         }
                     },
-                    "brush" => {
-                        match tools.get("brush","Shape") {
-                            Some(0) => canvas.brush_line(prev_position.x,
-                                            prev_position.y,
-                                            point.x,
-                                            point.y,
-                                            -size,
-                                            color),
-                            Some(1) => canvas.rect_line(prev_position.x,
-                                            prev_position.y,point.x ,point.y,size as u32, size as u32,
-                                                    color),
-                            Some(2) => canvas.paste_buffer(point.x,point.y,
-                                                    a),
-                            Some(3) => canvas.smooth_circle(point.x,point.y,
-                                                    size as u32, a, color),
-                            None | Some(_) => println!("no Shape match!"),
-                        }
+                    "brush" | "eraser" => {
+                        let (x, y) = canvas.to_document_f32(screen_point);
+                        canvas.stroke_to(x, y);
                     },
                     _ => (),
                     }
-                *prev_opt = Some(point);
+                *prev_opt = Some(screen_point);
             } else {
-                *prev_opt = Some(point);
-                if selected_tool == "line" || selected_tool =="pen" || selected_tool =="brush"
+                *prev_opt = Some(screen_point);
+                if selected_tool == "line" || selected_tool =="pen" || selected_tool =="brush" || selected_tool == "eraser"
                     || selected_tool=="brush_line"
-                    || selected_tool=="text" {canvas.undo_save();} //prepare for undo
+                    || selected_tool=="text" {canvas.undo_save(&document::label(&selected_tool));} //prepare for undo
+                if selected_tool == "brush" {
+                    let (x, y) = canvas.to_document_f32(screen_point);
+                    canvas.begin_stroke(tool_brush(&tools, size, &stamps_clone.borrow()), color, x, y);
+                }
+                if selected_tool == "eraser" {
+                    let (x, y) = canvas.to_document_f32(screen_point);
+                    let erase = Erase::from_i32(tools.get("eraser","Mode").unwrap(), tools.get("eraser","Steps").unwrap() as usize);
+                    canvas.begin_erase(tool_brush(&tools, size, &stamps_clone.borrow()), a, erase, x, y);
+                }
+                if stabilizer > 0.0 && ["pen", "line", "brush", "eraser"].contains(&&selected_tool[..]) {
+                    *freehand.borrow_mut() = Some(Freehand::new(&selected_tool, stabilizer, canvas,
+                        canvas.to_document_f32(screen_point), color, antialias == 1));
+                }
             }
         }
     });
 
     window.add(&canvas);
+    window.add(&layers_panel);
     window.add(&marquee);
     window.add(&status);
+    window.add(&job_bar);
     window.add(&combo_box);
 
     // add menus
     window.add(&menufile);
     window.add(&menuedit);
+    window.add(&menuselect);
     window.add(&menutools);
     window.add(&menumask);
     window.add(&menuimage);
     window.add(&menupalette);
     window.add(&menuview);
+    window.add(&menulayer);
     window.add(&menuhelp);
 
     window.exec();
@@ -2206,10 +3031,21 @@ fn main() {
 
 //Helper functions
 
-///Load an image from path if exists, otherwise create new empty canvas
-fn load_image(path: &str, size: &MySize) -> Arc<canvas::Canvas> {  
+///Load an image or project from path if exists, otherwise create new empty canvas
+fn load_image(path: &str, size: &MySize) -> (Arc<canvas::Canvas>, Option<Project>) {  
     if cfg!(feature = "debug"){print!("Loading image from:  {} .....", path);}
-    match Canvas::from_path(&path) {
+    if project::is_project(path) && Path::new(path).exists() {
+        match Project::load(path) {
+            Ok(project) => {
+                if cfg!(feature = "debug"){println!(" OK (project)");}
+                return (project_canvas(&project), Some(project));
+            }
+            Err(err) => {
+                println!("Failed to load project {}: {}", path, err);
+            }
+        }
+    }
+    let canvas = match Canvas::from_path(&path) {
         Ok(image) => {
             if cfg!(feature = "debug"){println!(" OK");}
             image
@@ -2218,6 +3054,477 @@ fn load_image(path: &str, size: &MySize) -> Arc<canvas::Canvas> {
             if cfg!(feature = "debug"){println!("Failed: {} \n Creating new one ", err);}
             Canvas::from_color(size.x, size.y, Color::rgb(255, 255, 255))
         }
+    };
+    (canvas, None)
+}
+
+///which colors the fill tool or the magic wand take as the same, from their properties
+fn color_match(tools: &Tools, tool: &str) -> ColorMatch {
+    ColorMatch {
+        tolerance: tools.get(tool,"Fuzziness").unwrap().max(0).min(100) as f32 / 100.0,
+        metric: if tools.get(tool,"Perceptual").unwrap() == 1 {ColorDistance::Perceptual} else {ColorDistance::Rgb},
+        contiguous: tools.get(tool,"Contiguous").unwrap() == 1,
+        connectivity: Connectivity::from_i32(tools.get(tool,"Connectivity").unwrap()),
+        sample_all: tools.get(tool,"SampleAll").unwrap() == 1,
+        antialias: tools.get(tool,"Antialias").unwrap() == 1,
+    }
+}
+
+///run job on a worker thread while the window shows its progress in the status line,
+///Escape cancels it; the result goes into canvas as one undo step
+fn run_job(job: Option<Job>, canvas: &Canvas, window: &mut Window, status: &Label, job_bar: &ProgressBar) {
+    let job = match job {
+        Some(job) => job,
+        None => return,
+    };
+    let label = job.label().to_owned();
+    let task = Task::spawn(move |progress| job.run(progress));
+    status.text(format!("{}... (Esc to cancel)", label));
+    job_bar.value.set(0);
+    job_bar.visible(true);
+
+    //poll events instead of waiting for them, so progress keeps moving
+    window.inner.borrow_mut().set_async(true);
+    let mut quit = false;
+    let result = loop {
+        if let Some(result) = task.poll() {
+            break result;
+        }
+        for event in window.inner.borrow_mut().events() {
+            match event.to_option() {
+                EventOption::Key(key_event) => if key_event.pressed && key_event.scancode == K_ESC {
+                    task.cancel();
+                },
+                EventOption::Quit(_quit_event) => {
+                    task.cancel();
+                    quit = true;
+                },
+                _ => (),
+            }
+        }
+        job_bar.value.set(task.progress().percent());
+        window.draw();
+        thread::sleep(Duration::from_millis(30));
+    };
+    window.inner.borrow_mut().set_async(false);
+    job_bar.visible(false);
+
+    match result {
+        Some(done) => {
+            canvas.finish_job(done);
+            status.text(format!("{} done", label));
+        },
+        None => {
+            status.text(format!("{} cancelled", label));
+        },
+    }
+    if quit {
+        window.close();
+    }
+    window.needs_redraw();
+}
+
+//screen pixels between the top side of floating pixels and the handle that turns them
+const KNOB: f32 = 24.0;
+
+///what the side and corner handles of floating pixels do
+#[derive(Clone, Copy, PartialEq)]
+enum TransformMode {
+    ///scale, the pixels keep their angles
+    Free,
+    ///slide a side along itself
+    Skew,
+    ///move each corner anywhere
+    Perspective,
+}
+
+impl TransformMode {
+    ///0 free, 1 skew, 2 perspective, as stored in tool properties
+    fn from_i32(n: i32) -> Self {
+        match n {
+            1 => TransformMode::Skew,
+            2 => TransformMode::Perspective,
+            _ => TransformMode::Free,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            TransformMode::Free => "Free transform",
+            TransformMode::Skew => "Skew",
+            TransformMode::Perspective => "Perspective",
+        }
+    }
+}
+
+///what a press on floating pixels grabbed, sides and corners numbered clockwise from the top
+#[derive(Clone, Copy, PartialEq)]
+enum Handle {
+    Corner(usize),
+    Side(usize),
+    ///turns the pixels around the pivot
+    Knob,
+    Pivot,
+    Inside,
+    Nothing,
+}
+
+///lift the selected pixels and move them with the mouse or the arrow keys; the handles
+///on sides and corners scale, skew or distort them as mode says (F, K and P keys switch),
+///the one on top turns them around the pivot that can be dragged too; Enter puts them
+///down resampled with interpolation, Escape puts them back; start is where the move
+///tool was clicked, in canvas coordinates
+fn move_selection(canvas: &Canvas, window: &mut Window, marquee: &Marquee, status: &Label,
+                  transparent: bool, mut mode: TransformMode, interpolation: Interpolation, start: Point) {
+    if !canvas.float_selection(transparent) {
+        status.text("Nothing to move, select something first");
+        return;
+    }
+    let origin = canvas.rect.get().point();
+    let mut quad = canvas.floating_quad().unwrap();
+    //the button is still down from the click that started the tool
+    let mut pressed = true;
+    let mut pointer = start;
+    let mut press = (start, quad, floating_hit(canvas, start));
+    let mut quit = false;
+    let commit = 'events: loop {
+        show_floating(canvas, marquee, status, mode);
+        window.draw();
+        let mut next = quad;
+        for event in window.inner.borrow_mut().events() {
+            match event.to_option() {
+                EventOption::Mouse(evt) => {
+                    pointer = Point::new(evt.x, evt.y) - origin;
+                    if pressed {
+                        next = drag_floating(canvas, press, pointer, mode);
+                    }
+                },
+                EventOption::Button(btn) => {
+                    if btn.left && !pressed {
+                        press = (pointer, next, floating_hit(canvas, pointer));
+                    }
+                    pressed = btn.left;
+                },
+                EventOption::Key(key_event) => if key_event.pressed {
+                    match key_event.scancode {
+                        K_ENTER => break 'events true,
+                        K_ESC => break 'events false,
+                        K_UP => next = next.translated(0.0, -1.0),
+                        K_DOWN => next = next.translated(0.0, 1.0),
+                        K_LEFT => next = next.translated(-1.0, 0.0),
+                        K_RIGHT => next = next.translated(1.0, 0.0),
+                        _ => match key_event.character {
+                            'f' | 'F' => mode = TransformMode::Free,
+                            'k' | 'K' => mode = TransformMode::Skew,
+                            'p' | 'P' => mode = TransformMode::Perspective,
+                            _ => (),
+                        },
+                    }
+                },
+                EventOption::Quit(_quit_event) => {
+                    quit = true;
+                    break 'events false;
+                },
+                _ => (),
+            }
+        }
+        //a quad folded over itself is refused, the pixels stay at the last good one
+        if next != quad && canvas.place_floating(next) {
+            quad = next;
+        }
+    };
+
+    if commit {
+        canvas.commit_floating(interpolation);
+        status.text(format!("{} done", mode.name()));
+    } else {
+        canvas.cancel_floating();
+        status.text(format!("{} cancelled", mode.name()));
+    }
+    marquee.handles(Vec::new());
+    canvas.emit_view_change();
+    if quit {
+        window.close();
+    }
+    window.needs_redraw();
+}
+
+///handles of floating pixels on quad, in canvas coordinates
+fn floating_handles(canvas: &Canvas, quad: &Quad) -> Vec<(Point, Handle)> {
+    let screen = |p: (f32, f32)| canvas.to_screen_f32(p.0, p.1);
+    let top = screen(quad.side(0));
+    let (sin, cos) = quad.angle().sin_cos();
+    let knob = Point::new(top.x + (sin * KNOB).round() as i32, top.y - (cos * KNOB).round() as i32);
+    let mut handles: Vec<(Point, Handle)> = (0..4).map(|n| (screen(quad.corners[n]), Handle::Corner(n)))
+        .chain((0..4).map(|n| (screen(quad.side(n)), Handle::Side(n))))
+        .collect();
+    handles.push((knob, Handle::Knob));
+    handles.push((screen(quad.pivot), Handle::Pivot));
+    handles
+}
+
+///what a press at point (canvas coordinates) grabs
+fn floating_hit(canvas: &Canvas, point: Point) -> Handle {
+    let quad = match canvas.floating_quad() {
+        Some(quad) => quad,
+        None => return Handle::Nothing,
+    };
+    //the pivot is usually in the middle, over the inside
+    for (handle_point, handle) in floating_handles(canvas, &quad).into_iter().rev() {
+        if (handle_point.x - point.x).abs() <= 4 && (handle_point.y - point.y).abs() <= 4 {
+            return handle;
+        }
+    }
+    let (x, y) = canvas.to_document_f32(point);
+    if quad.contains(x, y) { Handle::Inside } else { Handle::Nothing }
+}
+
+///quad after dragging from press (where, quad then, what was grabbed) to pointer
+fn drag_floating(canvas: &Canvas, press: (Point, Quad, Handle), pointer: Point, mode: TransformMode) -> Quad {
+    let (start, quad, handle) = press;
+    let from = canvas.to_document_f32(start);
+    let to = canvas.to_document_f32(pointer);
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    //axes of the pixels: along the top side and down from it
+    let (sin, cos) = quad.angle().sin_cos();
+    let (u, v) = ((cos, sin), (-sin, cos));
+    let dot = |a: (f32, f32), b: (f32, f32)| a.0 * b.0 + a.1 * b.1;
+    let mut next = quad;
+    match (handle, mode) {
+        //whole pixels, so moved pixels stay sharp
+        (Handle::Inside, _) => next = quad.translated(dx.round(), dy.round()),
+        (Handle::Pivot, _) => next.pivot = (quad.pivot.0 + dx, quad.pivot.1 + dy),
+        (Handle::Knob, _) => {
+            let (px, py) = quad.pivot;
+            next = quad.rotated((to.1 - py).atan2(to.0 - px) - (from.1 - py).atan2(from.0 - px));
+        },
+        (Handle::Corner(n), TransformMode::Perspective) => {
+            let c = &mut next.corners[n];
+            *c = (c.0 + dx, c.1 + dy);
+        },
+        (Handle::Side(n), TransformMode::Perspective) => for &m in [n, (n + 1) % 4].iter() {
+            let c = &mut next.corners[m];
+            *c = (c.0 + dx, c.1 + dy);
+        },
+        //top and bottom slide sideways, left and right up and down
+        (Handle::Side(n), TransformMode::Skew) => {
+            let axis = if n % 2 == 0 { u } else { v };
+            let shift = dot((dx, dy), axis);
+            for &m in [n, (n + 1) % 4].iter() {
+                let c = &mut next.corners[m];
+                *c = (c.0 + shift * axis.0, c.1 + shift * axis.1);
+            }
+        },
+        //the opposite side or corner stays where it is
+        (Handle::Corner(n), _) | (Handle::Side(n), _) => {
+            let (held, fixed) = match handle {
+                Handle::Corner(_) => (quad.corners[n], quad.corners[(n + 2) % 4]),
+                _ => (quad.side(n), quad.side((n + 2) % 4)),
+            };
+            let scale = |axis: (f32, f32)| {
+                let span = dot((held.0 - fixed.0, held.1 - fixed.1), axis);
+                if span.abs() < 0.5 { 1.0 } else { dot((to.0 - fixed.0, to.1 - fixed.1), axis) / span }
+            };
+            //sides scale across themselves only
+            let (ku, kv) = match handle {
+                Handle::Side(n) if n % 2 == 0 => (1.0, scale(v)),
+                Handle::Side(_) => (scale(u), 1.0),
+                _ => (scale(u), scale(v)),
+            };
+            let stretch = |p: (f32, f32)| {
+                let (a, b) = (dot((p.0 - fixed.0, p.1 - fixed.1), u) * ku, dot((p.0 - fixed.0, p.1 - fixed.1), v) * kv);
+                (fixed.0 + a * u.0 + b * v.0, fixed.1 + a * u.1 + b * v.1)
+            };
+            for c in next.corners.iter_mut() {
+                *c = stretch(*c);
+            }
+            next.pivot = stretch(quad.pivot);
+        },
+        (Handle::Nothing, _) => (),
+    }
+    next
+}
+
+///marquee around the floating pixels with their handles, and what is being done to them
+fn show_floating(canvas: &Canvas, marquee: &Marquee, status: &Label, mode: TransformMode) {
+    let quad = match canvas.floating_quad() {
+        Some(quad) => quad,
+        None => return,
+    };
+    let corners: Vec<Point> = quad.corners.iter().map(|c| canvas.to_screen_f32(c.0, c.1)).collect();
+    let handles: Vec<Point> = floating_handles(canvas, &quad).into_iter().map(|(point, _)| point).collect();
+    let x0 = handles.iter().map(|p| p.x).min().unwrap();
+    let y0 = handles.iter().map(|p| p.y).min().unwrap();
+    let x1 = handles.iter().map(|p| p.x).max().unwrap();
+    let y1 = handles.iter().map(|p| p.y).max().unwrap();
+    let corner = Point::new(x0, y0);
+    marquee.outline((0..4).map(|n| (corners[n] - corner, corners[(n + 1) % 4] - corner)).collect());
+    marquee.handles(handles.iter().map(|&p| p - corner).collect());
+    marquee
+        .position(x0, y0 + CANVASOFFSET)
+        .size((x1 - x0) as u32 + 1, (y1 - y0) as u32 + 1)
+        .visible(true);
+    let (x, y) = quad.corners[0];
+    status.text(format!("{} at {}, {} angle {:.1}  (F scale, K skew, P perspective, Enter to apply, Esc to cancel)",
+        mode.name(), x.round(), y.round(), quad.angle().to_degrees()));
+}
+
+///gradient as the gradient tool is set, from color (the current one, its alpha the opacity)
+fn tool_gradient(tools: &Tools, color: Color) -> Gradient {
+    let stop = |position: f32, color: Color| Stop { position, color };
+    let opaque = Color::rgb(color.r(), color.g(), color.b());
+    let fade = |c: Color| Color::rgba(c.r(), c.g(), c.b(), (c.a() as u32 * color.a() as u32 / 255) as u8);
+    let stops = match tools.get("gradient","Colors").unwrap() {
+        1 => vec![stop(0.0, opaque), stop(1.0, Color::rgba(color.r(), color.g(), color.b(), 0))],
+        2 => gradient::parse_stops(&tools.get_str("gradient","Stops").unwrap())
+                .unwrap_or_else(|_| vec![stop(0.0, opaque)]),
+        //white is what a cleared canvas shows
+        _ => vec![stop(0.0, opaque), stop(1.0, Color::rgb(255, 255, 255))],
+    };
+    let stops: Vec<Stop> = stops.into_iter().map(|s| stop(s.position, fade(s.color))).collect();
+    Gradient::new(gradient::Shape::from_i32(tools.get("gradient","Shape").unwrap()),
+                  Spread::from_i32(tools.get("gradient","Spread").unwrap()),
+                  &stops,
+                  tools.get("gradient","Dither").unwrap() == 1)
+}
+
+///brush as the brush tool is set, size is the radius from the size bar
+fn tool_brush(tools: &Tools, size: i32, stamps: &[Arc<Stamp>]) -> Brush {
+    let percent = |name: &str| tools.get("brush", name).unwrap() as f32 / 100.0;
+    let mut brush = Brush::new(Tip::from_i32(tools.get("brush","Shape").unwrap()), cmp::max(1, 2 * size) as f32);
+    brush.hardness = percent("Hardness");
+    brush.spacing = percent("Spacing").max(0.01);
+    brush.flow = percent("Flow");
+    brush.angle = (tools.get("brush","Angle").unwrap() as f32).to_radians();
+    brush.size_jitter = percent("SizeJitter");
+    brush.opacity_jitter = percent("OpacityJitter");
+    brush.angle_jitter = percent("AngleJitter");
+    brush.scatter = percent("Scatter");
+    let name = tools.get_str("brush","Stamp").unwrap();
+    brush.stamp = stamps.iter().find(|stamp| stamp.name == name).cloned();
+    brush
+}
+
+///freehand tool stroke going through a stabilizer, in document coordinates
+struct Freehand {
+    stabilizer: Stabilizer,
+    tool: String,
+    color: Color,
+    antialias: bool,
+    last: (f32, f32),
+}
+
+impl Freehand {
+    fn new(tool: &str, strength: f32, canvas: &Canvas, start: (f32, f32), color: Color, antialias: bool) -> Self {
+        Freehand {
+            stabilizer: Stabilizer::new(strength, 1.0 / canvas.zoom_factor.get(), start.0, start.1),
+            tool: tool.to_string(),
+            color,
+            antialias,
+            last: start,
+        }
+    }
+
+    fn to(&mut self, canvas: &Canvas, pointer: (f32, f32)) {
+        for p in self.stabilizer.to(pointer.0, pointer.1) {
+            self.draw(canvas, p);
+        }
+    }
+
+    fn finish(mut self, canvas: &Canvas) {
+        for p in self.stabilizer.finish() {
+            self.draw(canvas, p);
+        }
+    }
+
+    fn draw(&mut self, canvas: &Canvas, p: (f32, f32)) {
+        let (x0, y0) = (self.last.0.floor() as i32, self.last.1.floor() as i32);
+        let (x1, y1) = (p.0.floor() as i32, p.1.floor() as i32);
+        match self.tool.as_ref() {
+            "brush" | "eraser" => canvas.stroke_to(p.0, p.1),
+            "line" if self.antialias => canvas.wu_line(x0, y0, x1, y1, self.color),
+            _ => canvas.line(x0, y0, x1, y1, self.color),
+        }
+        self.last = p;
+    }
+}
+
+///put stamp in the library, in place of one with the same name
+fn add_stamp(stamps: &RefCell<Vec<Arc<Stamp>>>, stamp: Stamp) {
+    let mut stamps = stamps.borrow_mut();
+    stamps.retain(|old| old.name != stamp.name);
+    stamps.push(Arc::new(stamp));
+    stamps.sort_by(|a, b| a.name.cmp(&b.name));
+}
+
+///file name for a brush name, without the characters paths do not like
+fn stamp_file(name: &str) -> String {
+    let file: String = name.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect();
+    if file.is_empty() { "brush".to_string() } else { file }
+}
+
+///pixels of value typed in units (0 pixels, 1 percent of current, 2 inches, 3 centimeters) at dpi
+fn to_pixels(value: &str, units: usize, dpi: f32, current: u32) -> Option<u32> {
+    let value = value.trim().parse::<f32>().ok().filter(|&v| v > 0.0)?;
+    let pixels = match units {
+        1 => value * current as f32 / 100.0,
+        2 => value * dpi,
+        3 => value * dpi / 2.54,
+        _ => value,
+    };
+    Some(cmp::max(1, pixels.round() as u32))
+}
+
+///pixels written in units, as to_pixels reads them
+fn from_pixels(pixels: u32, units: usize, dpi: f32) -> String {
+    match units {
+        1 => "100".to_string(),
+        2 => format!("{:.2}", pixels as f32 / dpi),
+        3 => format!("{:.2}", pixels as f32 * 2.54 / dpi),
+        _ => pixels.to_string(),
+    }
+}
+
+///save as project if path has the project extension, otherwise flatten to an image
+fn save_document(path: &str, canvas: &Canvas, palette: &Palette, tools: &Tools) -> Result<i32, io::Error> {
+    if Path::new(path).extension().map_or(false, |ext| ext == project::PROJECT_EXTENSION) {
+        project_of(canvas, palette, tools).save(path)
+    } else {
+        canvas.save(path)
+    }
+}
+
+///collect the state of canvas, palette and tools in a project
+fn project_of(canvas: &Canvas, palette: &Palette, tools: &Tools) -> Project {
+    let buffer = canvas.copy_buffer.borrow();
+    Project {
+        width: canvas.width(),
+        height: canvas.height(),
+        layers: canvas.export_layers(),
+        active_layer: canvas.active_layer(),
+        mask: canvas.mask().clone(),
+        mask_enabled: canvas.mask_enabled(),
+        palette: palette.custom(),
+        tools: tools.properties(),
+        copy_buffer: if buffer.width() > 0 && buffer.height() > 0 { Some(buffer.clone()) } else { None },
+    }
+}
+
+///new canvas with the layers, mask and copy buffer of the project
+fn project_canvas(project: &Project) -> Arc<Canvas> {
+    let canvas = Canvas::from_layers(project.layers.clone(), project.active_layer, project.mask.clone());
+    canvas.enable_mask(project.mask_enabled);
+    if let Some(ref buffer) = project.copy_buffer {
+        *canvas.copy_buffer.borrow_mut() = buffer.clone();
+    }
+    canvas
+}
+
+///apply palette and tool settings of the project, the canvas is built by project_canvas
+fn restore_project(project: &Project, palette: &Palette, tools: &Tools) {
+    palette.restore(&project.palette);
+    for (tool, property, value) in &project.tools {
+        tools.restore(tool, property, value);
     }
 }
 
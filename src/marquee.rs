@@ -26,6 +26,10 @@ pub struct Marquee {
     pressed: Cell<bool>,
     pub visible: Cell<bool>,
     pub id:Cell<usize>,
+    //lines of the outline relative to rect, the rectangle itself when empty
+    outline: RefCell<Vec<(Point, Point)>>,
+    //centers of the transform handles relative to rect
+    handles: RefCell<Vec<Point>>,
 }
 
 impl Marquee {
@@ -45,6 +49,8 @@ impl Marquee {
             pressed: Cell::new(false),
             visible: Cell::new(true),
             id: Cell::new(0),
+            outline: RefCell::new(Vec::new()),
+            handles: RefCell::new(Vec::new()),
         })
     }
     
@@ -60,6 +66,14 @@ impl Marquee {
     pub fn get_id(&self) ->usize {
         self.id.get()
     }
+    ///ants follow these lines instead of the rectangle, empty for the rectangle
+    pub fn outline(&self, lines: Vec<(Point, Point)>) {
+        *self.outline.borrow_mut() = lines;
+    }
+    ///small squares to drag, none when empty
+    pub fn handles(&self, points: Vec<Point>) {
+        *self.handles.borrow_mut() = points;
+    }
 }
 
 /*
@@ -118,10 +132,23 @@ impl Widget for Marquee {
             let w = rect.width as i32;
             let h = rect.height as i32;
 
-            ant_line(renderer,rect.x, rect.y, rect.x, rect.y+rect.height as i32, Color::rgba(200,0,0,255),2);
-            ant_line(renderer,rect.x, rect.y+rect.height as i32, rect.x+rect.width as i32, rect.y+rect.height as i32, Color::rgba(200,0,0,255),2);
-            ant_line(renderer,rect.x+rect.width as i32, rect.y , rect.x+rect.width as i32, rect.y+rect.height as i32 , Color::rgba(200,0,0,255),2);
-            ant_line(renderer,rect.x, rect.y, rect.x+rect.width as i32, rect.y as i32, Color::rgba(200,0,0,255),2);
+            let outline = self.outline.borrow();
+            if outline.is_empty() {
+                ant_line(renderer,rect.x, rect.y, rect.x, rect.y+rect.height as i32, Color::rgba(200,0,0,255),2);
+                ant_line(renderer,rect.x, rect.y+rect.height as i32, rect.x+rect.width as i32, rect.y+rect.height as i32, Color::rgba(200,0,0,255),2);
+                ant_line(renderer,rect.x+rect.width as i32, rect.y , rect.x+rect.width as i32, rect.y+rect.height as i32 , Color::rgba(200,0,0,255),2);
+                ant_line(renderer,rect.x, rect.y, rect.x+rect.width as i32, rect.y as i32, Color::rgba(200,0,0,255),2);
+            } else {
+                for &(p1, p2) in outline.iter() {
+                    ant_line(renderer, rect.x + p1.x, rect.y + p1.y, rect.x + p2.x, rect.y + p2.y, Color::rgba(200,0,0,255),2);
+                }
+            }
+
+            for handle in self.handles.borrow().iter() {
+                let (x, y) = (rect.x + handle.x, rect.y + handle.y);
+                renderer.rect(x - 3, y - 3, 7, 7, Color::rgba(200,0,0,255));
+                renderer.rect(x - 2, y - 2, 5, 5, Color::rgba(255,255,255,255));
+            }
 
             let text = self.text.borrow();
 
@@ -219,6 +219,25 @@ impl Palette {
         self.order.set(16);    
     }
     
+    ///custom swatches, without the 16 default ones
+    pub fn custom (&self) -> Vec<Color> {
+        self.swatches.borrow()[16..].to_vec()
+    }
+
+    ///replace custom swatches with colors, next empty swatch follows the last one
+    pub fn restore (&self, colors: &[Color]) {
+        self.reset();
+        let mut k = 16;
+        for color in colors {
+            if k >= self.count() {
+                break;
+            }
+            self.change(k, *color);
+            k += 1;
+        }
+        self.order.set(k);
+    }
+
     pub fn count (&self) -> usize {
         self.swatches.borrow().len()
         
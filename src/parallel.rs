@@ -0,0 +1,86 @@
+//row strips for the multicore feature: work on an image is split in horizontal
+//strips, each one handled by its own thread; without the feature everything
+//runs on the calling thread as a single strip
+
+use std::cmp;
+use std::sync::Mutex;
+use std::thread;
+
+use crate::task::Progress;
+
+//strips thinner than this are not worth a thread
+const MIN_ROWS: usize = 16;
+//strips per thread when progress is reported, so it moves smoothly
+const STRIPS_PER_THREAD: usize = 8;
+
+///number of threads used for image operations
+pub fn threads() -> usize {
+    if cfg!(feature = "multicore") {
+        thread::available_parallelism().map_or(1, |n| n.get())
+    } else {
+        1
+    }
+}
+
+///call f(first_row, strip) for consecutive strips of rows covering data,
+///a row is stride elements long; f must give each row the same result
+///whatever strip it is in, so the output does not depend on the threads
+pub fn for_strips<T, F>(data: &mut [T], stride: usize, f: F)
+where
+    T: Send,
+    F: Fn(usize, &mut [T]) + Sync,
+{
+    let rows = if stride == 0 { 0 } else { data.len() / stride };
+    let threads = cmp::min(threads(), rows / MIN_ROWS);
+    if threads <= 1 {
+        f(0, data);
+        return;
+    }
+    let per_strip = (rows + threads - 1) / threads;
+    let f = &f;
+    thread::scope(|scope| {
+        for (n, strip) in data.chunks_mut(per_strip * stride).enumerate() {
+            scope.spawn(move || f(n * per_strip, strip));
+        }
+    });
+}
+
+///like for_strips, but in smaller strips whose rows are added to progress as they
+///are done; stops early and returns false when progress is cancelled
+pub fn for_strips_with<T, F>(data: &mut [T], stride: usize, progress: &Progress, f: F) -> bool
+where
+    T: Send,
+    F: Fn(usize, &mut [T]) + Sync,
+{
+    let rows = if stride == 0 { 0 } else { data.len() / stride };
+    if rows == 0 {
+        return !progress.is_cancelled();
+    }
+    let threads = threads();
+    let per_strip = cmp::max(MIN_ROWS, (rows + threads * STRIPS_PER_THREAD - 1) / (threads * STRIPS_PER_THREAD));
+    //threads take the next strip when they are done with one
+    let strips = Mutex::new(data.chunks_mut(per_strip * stride).enumerate());
+    let work = || loop {
+        let next = strips.lock().unwrap().next();
+        match next {
+            Some((n, strip)) if !progress.is_cancelled() => {
+                let count = strip.len() / stride;
+                f(n * per_strip, strip);
+                progress.advance(count);
+            },
+            _ => break,
+        }
+    };
+    let threads = cmp::min(threads, (rows + per_strip - 1) / per_strip);
+    if threads <= 1 {
+        work();
+    } else {
+        let work = &work;
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(move || work());
+            }
+        });
+    }
+    !progress.is_cancelled()
+}
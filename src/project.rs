@@ -0,0 +1,412 @@
+//native .pastel project file
+//
+//A project keeps everything needed to continue working on a document:
+//layers, quick mask, custom palette, tool settings and copy buffer.
+//
+//Layout (all integers little endian):
+//
+//    magic    8 bytes  "PASTELPJ"
+//    version  u32      PROJECT_VERSION
+//    chunks   until end of file, each one is
+//             tag u32 (4 ascii chars), length u32, payload of length bytes
+//
+//Chunks:
+//
+//    HEAD  width u32, height u32, active layer u32
+//    LAYR  one per layer from bottom to top: name str, opacity u8 (0..100),
+//          visible u8, locked u8, blend mode str, pixels
+//    MASK  enabled u8, pixels
+//    PALT  count u32, count * rgba
+//    TOOL  count u32, count * (tool str, property str, value str)
+//    BUFF  width u32, height u32, pixels
+//
+//str is length u32 followed by utf8 bytes, pixels are width*height rgba
+//quadruplets (4 bytes each) row by row, uncompressed.
+//Readers skip chunks they do not know, so new chunks can be added without
+//changing the version; the version only changes when existing chunks do.
+
+use orbclient::Color;
+use orbimage::Image;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter, Error, ErrorKind};
+use std::path::Path;
+
+use crate::layer::{BlendMode, Layer};
+
+const MAGIC: &[u8; 8] = b"PASTELPJ";
+pub const PROJECT_VERSION: u32 = 1;
+pub const PROJECT_EXTENSION: &str = "pastel";
+
+///content of a project file
+pub struct Project {
+    pub width: u32,
+    pub height: u32,
+    pub layers: Vec<Layer>,
+    pub active_layer: usize,
+    pub mask: Image,
+    pub mask_enabled: bool,
+    pub palette: Vec<Color>,
+    //(tool, property, value)
+    pub tools: Vec<(String, String, String)>,
+    pub copy_buffer: Option<Image>,
+}
+
+impl Project {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<i32, Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        write_u32(&mut file, PROJECT_VERSION)?;
+
+        let mut head = Vec::new();
+        write_u32(&mut head, self.width)?;
+        write_u32(&mut head, self.height)?;
+        write_u32(&mut head, self.active_layer as u32)?;
+        write_chunk(&mut file, b"HEAD", &head)?;
+
+        for layer in &self.layers {
+            let mut chunk = Vec::new();
+            write_str(&mut chunk, &layer.name)?;
+            chunk.push(layer.opacity);
+            chunk.push(layer.visible as u8);
+            chunk.push(layer.locked as u8);
+            write_str(&mut chunk, layer.blend.name())?;
+            write_pixels(&mut chunk, layer.image.data());
+            write_chunk(&mut file, b"LAYR", &chunk)?;
+        }
+
+        let mut mask = vec![self.mask_enabled as u8];
+        write_pixels(&mut mask, self.mask.data());
+        write_chunk(&mut file, b"MASK", &mask)?;
+
+        let mut palette = Vec::new();
+        write_u32(&mut palette, self.palette.len() as u32)?;
+        write_pixels(&mut palette, &self.palette);
+        write_chunk(&mut file, b"PALT", &palette)?;
+
+        let mut tools = Vec::new();
+        write_u32(&mut tools, self.tools.len() as u32)?;
+        for (tool, property, value) in &self.tools {
+            write_str(&mut tools, tool)?;
+            write_str(&mut tools, property)?;
+            write_str(&mut tools, value)?;
+        }
+        write_chunk(&mut file, b"TOOL", &tools)?;
+
+        if let Some(ref buffer) = self.copy_buffer {
+            let mut chunk = Vec::new();
+            write_u32(&mut chunk, buffer.width())?;
+            write_u32(&mut chunk, buffer.height())?;
+            write_pixels(&mut chunk, buffer.data());
+            write_chunk(&mut file, b"BUFF", &chunk)?;
+        }
+
+        file.flush()?;
+        Ok(0)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a pastel project"));
+        }
+        let version = read_u32(&mut file)?;
+        if version > PROJECT_VERSION {
+            return Err(invalid(&format!("project version {} is newer than supported {}", version, PROJECT_VERSION)));
+        }
+
+        let mut project = Project {
+            width: 0,
+            height: 0,
+            layers: Vec::new(),
+            active_layer: 0,
+            mask: Image::new(0, 0),
+            mask_enabled: false,
+            palette: Vec::new(),
+            tools: Vec::new(),
+            copy_buffer: None,
+        };
+        let mut head = false;
+
+        loop {
+            let mut tag = [0; 4];
+            match file.read_exact(&mut tag) {
+                Ok(_) => (),
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            let length = read_u32(&mut file)? as usize;
+            //do not trust length for allocating, a damaged file would ask for gigabytes
+            let mut payload = Vec::new();
+            (&mut file).take(length as u64).read_to_end(&mut payload)?;
+            if payload.len() < length {
+                return Err(invalid("truncated chunk"));
+            }
+            let mut chunk = &payload[..];
+
+            if &tag != b"HEAD" && !head {
+                return Err(invalid("HEAD chunk must come first"));
+            }
+            let pixels = project.width as usize * project.height as usize;
+            match &tag {
+                b"HEAD" => {
+                    project.width = read_u32(&mut chunk)?;
+                    project.height = read_u32(&mut chunk)?;
+                    project.active_layer = read_u32(&mut chunk)? as usize;
+                    if project.width == 0 || project.height == 0 {
+                        return Err(invalid("empty document"));
+                    }
+                    head = true;
+                },
+                b"LAYR" => {
+                    let name = read_str(&mut chunk)?;
+                    let opacity = read_u8(&mut chunk)?;
+                    let visible = read_u8(&mut chunk)? != 0;
+                    let locked = read_u8(&mut chunk)? != 0;
+                    let blend = read_str(&mut chunk)?.parse::<BlendMode>().map_err(|e| invalid(&e))?;
+                    let data = read_pixels(&mut chunk, pixels)?;
+                    let mut layer = Layer::new(&name, image_from(project.width, project.height, data)?);
+                    layer.opacity = if opacity > 100 { 100 } else { opacity };
+                    layer.visible = visible;
+                    layer.locked = locked;
+                    layer.blend = blend;
+                    project.layers.push(layer);
+                },
+                b"MASK" => {
+                    project.mask_enabled = read_u8(&mut chunk)? != 0;
+                    let data = read_pixels(&mut chunk, pixels)?;
+                    project.mask = image_from(project.width, project.height, data)?;
+                },
+                b"PALT" => {
+                    let count = read_u32(&mut chunk)? as usize;
+                    project.palette = read_pixels(&mut chunk, count)?;
+                },
+                b"TOOL" => {
+                    let count = read_u32(&mut chunk)?;
+                    for _ in 0..count {
+                        let tool = read_str(&mut chunk)?;
+                        let property = read_str(&mut chunk)?;
+                        let value = read_str(&mut chunk)?;
+                        project.tools.push((tool, property, value));
+                    }
+                },
+                b"BUFF" => {
+                    let width = read_u32(&mut chunk)?;
+                    let height = read_u32(&mut chunk)?;
+                    let data = read_pixels(&mut chunk, width as usize * height as usize)?;
+                    project.copy_buffer = Some(image_from(width, height, data)?);
+                },
+                _ => {
+                    if cfg!(feature = "debug"){println!("Skip unknown project chunk {:?}", String::from_utf8_lossy(&tag));}
+                },
+            }
+        }
+
+        if project.layers.is_empty() {
+            return Err(invalid("project without layers"));
+        }
+        if project.active_layer >= project.layers.len() {
+            project.active_layer = project.layers.len() - 1;
+        }
+        if project.mask.width() != project.width || project.mask.height() != project.height {
+            project.mask = Image::from_color(project.width, project.height, Color::rgba(255, 0, 0, 25));
+        }
+        Ok(project)
+    }
+}
+
+///true if path is a project, by extension or content
+pub fn is_project<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    if path.extension().map_or(false, |ext| ext == PROJECT_EXTENSION) {
+        return true;
+    }
+    let mut magic = [0; 8];
+    match File::open(path) {
+        Ok(mut file) => file.read_exact(&mut magic).is_ok() && &magic == MAGIC,
+        Err(_) => false,
+    }
+}
+
+fn invalid(text: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, text)
+}
+
+fn image_from(width: u32, height: u32, data: Vec<Color>) -> Result<Image, Error> {
+    Image::from_data(width, height, data.into_boxed_slice()).map_err(|e| invalid(&e))
+}
+
+fn write_chunk<W: Write>(writer: &mut W, tag: &[u8; 4], payload: &[u8]) -> Result<(), Error> {
+    if payload.len() > u32::MAX as usize {
+        return Err(invalid("chunk too large for a project file"));
+    }
+    writer.write_all(tag)?;
+    write_u32(writer, payload.len() as u32)?;
+    writer.write_all(payload)
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> Result<(), Error> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_str<W: Write>(writer: &mut W, text: &str) -> Result<(), Error> {
+    write_u32(writer, text.len() as u32)?;
+    writer.write_all(text.as_bytes())
+}
+
+fn write_pixels(buffer: &mut Vec<u8>, data: &[Color]) {
+    buffer.reserve(data.len() * 4);
+    for color in data {
+        buffer.extend_from_slice(&[color.r(), color.g(), color.b(), color.a()]);
+    }
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8, Error> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, Error> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_str(chunk: &mut &[u8]) -> Result<String, Error> {
+    let length = read_u32(chunk)? as usize;
+    if chunk.len() < length {
+        return Err(invalid("truncated string"));
+    }
+    let text = String::from_utf8(chunk[..length].to_vec()).map_err(|_| invalid("invalid utf8 string"))?;
+    *chunk = &chunk[length..];
+    Ok(text)
+}
+
+fn read_pixels(chunk: &mut &[u8], count: usize) -> Result<Vec<Color>, Error> {
+    if chunk.len() / 4 < count {
+        return Err(invalid("truncated pixel data"));
+    }
+    let data = chunk[..count * 4]
+        .chunks(4)
+        .map(|p| Color::rgba(p[0], p[1], p[2], p[3]))
+        .collect();
+    *chunk = &chunk[count * 4..];
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("pastel-test-{}-{}.pastel", name, std::process::id()))
+    }
+
+    fn pixels(image: &Image) -> Vec<u32> {
+        image.data().iter().map(|c| c.data).collect()
+    }
+
+    fn image(width: u32, height: u32, seed: u32) -> Image {
+        let data: Vec<Color> = (0..width * height)
+            .map(|i| Color::rgba((i * 7 + seed) as u8, (i * 13) as u8, seed as u8, (i * 31 + seed) as u8))
+            .collect();
+        Image::from_data(width, height, data.into_boxed_slice()).unwrap()
+    }
+
+    fn sample() -> Project {
+        let mut bottom = Layer::new("Background", image(5, 3, 1));
+        bottom.locked = true;
+        let mut middle = Layer::new("Shadows", image(5, 3, 2));
+        middle.opacity = 40;
+        middle.blend = BlendMode::Multiply;
+        let mut top = Layer::new("Notes", image(5, 3, 3));
+        top.visible = false;
+        top.blend = BlendMode::Difference;
+        Project {
+            width: 5,
+            height: 3,
+            layers: vec![bottom, middle, top],
+            active_layer: 1,
+            mask: image(5, 3, 4),
+            mask_enabled: true,
+            palette: vec![Color::rgba(1, 2, 3, 4), Color::rgba(250, 128, 0, 255)],
+            tools: vec![
+                ("pen".to_string(), "Size".to_string(), "4".to_string()),
+                ("brush".to_string(), "Blend".to_string(), "multiply".to_string()),
+            ],
+            copy_buffer: Some(image(2, 4, 5)),
+        }
+    }
+
+    #[test]
+    fn save_and_load_keep_everything() {
+        let path = temp_path("round-trip");
+        let saved = sample();
+        saved.save(&path).unwrap();
+        let loaded = Project::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((loaded.width, loaded.height), (5, 3));
+        assert_eq!(loaded.active_layer, 1);
+        assert_eq!(loaded.layers.len(), saved.layers.len());
+        for (a, b) in saved.layers.iter().zip(&loaded.layers) {
+            assert_eq!(a.name, b.name);
+            assert_eq!(a.opacity, b.opacity);
+            assert_eq!(a.visible, b.visible);
+            assert_eq!(a.locked, b.locked);
+            assert_eq!(a.blend, b.blend);
+            assert_eq!(pixels(&a.image), pixels(&b.image));
+        }
+        assert!(loaded.mask_enabled);
+        assert_eq!(pixels(&loaded.mask), pixels(&saved.mask));
+        let palette = |p: &[Color]| p.iter().map(|c| c.data).collect::<Vec<u32>>();
+        assert_eq!(palette(&loaded.palette), palette(&saved.palette));
+        assert_eq!(loaded.tools, saved.tools);
+        let buffer = loaded.copy_buffer.unwrap();
+        assert_eq!((buffer.width(), buffer.height()), (2, 4));
+        assert_eq!(pixels(&buffer), pixels(saved.copy_buffer.as_ref().unwrap()));
+    }
+
+    #[test]
+    fn truncated_file_is_an_error() {
+        let path = temp_path("truncated");
+        sample().save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        //inside the first layer, inside the header and inside the magic
+        for &length in &[bytes.len() / 3, 14, 4] {
+            fs::write(&path, &bytes[..length]).unwrap();
+            assert!(Project::load(&path).is_err(), "{} bytes loaded", length);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn bad_magic_is_an_error() {
+        let path = temp_path("magic");
+        sample().save(&path).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[..8].copy_from_slice(b"PASTELXX");
+        fs::write(&path, &bytes).unwrap();
+        assert!(Project::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn newer_version_is_an_error() {
+        let path = temp_path("version");
+        sample().save(&path).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[8..12].copy_from_slice(&(PROJECT_VERSION + 1).to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        assert!(Project::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
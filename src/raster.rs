@@ -0,0 +1,231 @@
+//raster operations on orbimage images, no window needed
+
+use orbimage;
+use orbimage::ResizeType;
+use orbclient;
+use orbclient::{Color, Renderer};
+
+use crate::region::{self, ColorMatch};
+
+//  which ones to be added directly to orbclient ?
+pub trait AddOnsToOrbimage {
+    fn fill(&mut self, x: i32 , y: i32, color: Color);
+    fn flood_fill4(&mut self, x:i32, y:i32, new_color: u32 , old_color: u32);
+    fn flood_fill_scanline(&mut self, x:i32, y:i32, new_color: u32 , old_color: u32);
+    fn flood_fill_line(&mut self, x:i32, y:i32, new_color: u32 , old_color: u32);
+    fn pixcol(&self, x:i32, y:i32) -> Color;
+    fn pixraw(&self, x:i32, y:i32) -> u32;
+    fn copy_selection(&self, x: i32,y: i32,w: u32, h: u32) -> orbimage::Image;
+    fn paste_selection(&mut self, x: i32, y:i32, opacity: u8, buffer: orbimage::Image);
+    fn smooth_circle(&mut self, x: i32, y:i32, size: u32, color: Color);
+    fn colorize (&self, color: Color) -> orbimage::Image;
+    
+}
+
+
+impl AddOnsToOrbimage for orbimage::Image {
+    ///return pixel color safely
+    fn pixcol(&self, x:i32, y:i32) -> Color {
+        let p = (self.width()as i32 * y + x) as usize;
+        if p < self.data().len() {
+            self.data()[p] //return rgba value if exist
+        }else { Color::rgba(0,0,0,0)}
+    }
+    
+    fn pixraw (&self, x:i32, y:i32) -> u32 {
+        self.pixcol(x,y).data 
+    }
+    
+    ///wrapper for flood fill 
+    fn fill(&mut self, x: i32, y: i32 , color: Color) {
+        //get current pixel color 
+        let rgba = self.pixcol(x,y);
+        self.flood_fill_scanline(x,y,color.data,rgba.data);  //use rgba and color as i32 values 
+    }
+
+    ///4-way floodfill, the same as flood_fill_scanline
+    fn flood_fill4(&mut self, x:i32, y:i32, new_color: u32 , old_color: u32) {
+        self.flood_fill_scanline(x, y, new_color, old_color);
+    }
+
+    ///fill pixels of exactly old_color connected to (x,y), works with transparency too;
+    ///the span fill keeps its own stack, so big regions cannot overflow the thread one
+    fn flood_fill_scanline( &mut self, x:i32, y:i32, new_color: u32, old_color:u32) {
+        if old_color == new_color {
+            return;
+        }
+        if x < 0 || y < 0 || x >= self.width() as i32 || y >= self.height() as i32
+            || self.pixcol(x,y).data != old_color {
+            return;
+        }
+        let coverage = region::similar(self.data(), self.width(), self.height(), x, y, &ColorMatch::default());
+        region::paint(self, &coverage, None, Color{data:new_color});
+    }
+
+    ///line by line floodfill, the same as flood_fill_scanline
+    fn flood_fill_line(&mut self, x:i32, y:i32, new_color: u32 , old_color: u32) {
+        self.flood_fill_scanline(x, y, new_color, old_color);
+    }
+
+    ///crop new image from current image (copy) tranforming pure white into transparent
+    fn copy_selection(&self, x: i32,y: i32,w: u32, h: u32) -> orbimage::Image {
+
+        let mut vec = vec![];
+        let mut col : Color;
+        
+        for y1 in y..y+h as i32 {
+            for x1 in x..x+w as i32 {
+                col=self.pixcol(x1,y1);
+                if col.r()==255 && col.g()==255 && col.b()==255 {
+                    col = Color::rgba(0,0,0,0);
+                }
+                vec.push(col);
+            }
+        }
+        //println!("buffer {:?}",&vec);
+        orbimage::Image::from_data(w ,h ,vec.into_boxed_slice()).unwrap()
+    }
+
+    ///draws an image into current image starting at x,y (paste) with transparency
+    fn paste_selection (&mut self, x: i32, y:i32, opacity: u8, buffer: orbimage::Image, ){
+        
+        let w = buffer.width() as i32;
+        let h = buffer.height() as i32;
+        let xc=x-w/2; //center buffer at cursor 
+        let yc=y-h/2;
+        let data = buffer.into_data();
+        let mut i:usize = 0;
+        let mut r;
+        let mut g;
+        let mut b;
+        let mut a;
+
+        for y1 in yc..yc+h {
+            for x1 in xc..xc+w {
+                if i < data.len(){
+                    r = data[i].r();
+                    g = data[i].g();
+                    b = data[i].b();
+                    a = data[i].a();
+                    if a != 0 {a = opacity}
+                    self.pixel(x1,y1,Color::rgba(r,g,b,a));
+                }
+                i += 1;
+            }
+        }
+    }
+    
+    //experimental smooth brush : work in progress....
+    fn smooth_circle (&mut self, x: i32, y:i32, size: u32, color: Color) {
+        //let mut sb= orbimage::Image::from_color(2*size, 2*size, Color::rgba(255,255,255,0));
+        let sb = orbimage::Image::from_path("smooth_circle_black.png").unwrap();
+        let rb = sb.resize(size, size, ResizeType::Lanczos3).unwrap();
+        
+        let r = color.r();
+        let g = color.g();
+        let b = color.b();
+        let a = color.a();
+        
+        
+        /*
+        for n in 0..size {
+            //sb.circle(size as i32 , size as i32 , ((size -n) as i32), Color::rgba(r,g,b,(2*n) as u8));
+            sb.pixel(n as i32,n as i32, Color::rgba(r,g,b,(4*n)as u8)); //Does NOT work as intended!!
+            //sb.pixel(n as i32,n as i32, Color::rgba(r,g,b,(4*n)as u8));
+        }
+        */
+        //self.paste_selection(x,y,80,sb);
+        self.image(x,y,rb.width(),rb.height(),rb.data());
+        //println!("{:?}",sb.data());
+        
+    }
+
+    /// colorize a grayscale image 
+    fn colorize (&self, color: Color) -> orbimage::Image {
+        let w = self.width();
+        let h = self.height();
+        let mut data = self.clone().into_data();
+        let mut a;
+        for i in 0..data.len() {
+            a = data[i].a();
+            data[i]=Color::rgba(color.r(),color.g(),color.b(),a);
+        }
+        orbimage::Image::from_data(w ,h ,data).unwrap()
+    }
+
+
+
+
+}
+
+///how the difference between two colors is measured
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorDistance {
+    ///straight distance between rgba values
+    Rgb,
+    ///distance in CIE Lab, closer to what the eye sees
+    Perceptual,
+}
+
+pub trait AddOnsToOrbclientColor {
+    fn compare(first_color: Color, second_color: Color, thresold: u8) -> bool;
+    fn eq(&self, other: Color) -> bool ;
+    fn distance(&self, other: Color, metric: ColorDistance) -> f32;
+}
+
+impl AddOnsToOrbclientColor for orbclient::Color{
+        /// Compare between two colors, return Bool indicating if they are similar considering thresold value
+    fn compare(first_color: Color, second_color: Color, thresold: u8) -> bool {
+        let r_check = (first_color.r() as i32 - second_color.r() as i32).abs();
+        let g_check = (first_color.g() as i32 - second_color.g() as i32).abs();
+        let b_check = (first_color.b() as i32 - second_color.b() as i32).abs();
+        //Do not check for alpha
+        let thresold = thresold as i32;
+        r_check <= thresold && g_check <= thresold && b_check <= thresold
+    }
+
+    fn eq(&self, other: Color) -> bool {
+        self.r() == other.r() &&
+        self.g() == other.g() &&
+        self.b() == other.b()
+    }
+
+    ///difference between two colors, 0.0 (same) to 1.0 (black and white, or opaque and transparent)
+    fn distance(&self, other: Color, metric: ColorDistance) -> f32 {
+        let da = (self.a() as f32 - other.a() as f32) / 255.0;
+        let d = match metric {
+            ColorDistance::Rgb => {
+                let dr = (self.r() as f32 - other.r() as f32) / 255.0;
+                let dg = (self.g() as f32 - other.g() as f32) / 255.0;
+                let db = (self.b() as f32 - other.b() as f32) / 255.0;
+                //black to white is sqrt(3)
+                ((dr * dr + dg * dg + db * db) / 3.0).sqrt()
+            },
+            ColorDistance::Perceptual => {
+                let (l1, a1, b1) = lab(*self);
+                let (l2, a2, b2) = lab(other);
+                //CIE76, black to white is 100
+                ((l1 - l2).powi(2) + (a1 - a2).powi(2) + (b1 - b2).powi(2)).sqrt() / 100.0
+            },
+        };
+        d.max(da.abs()).min(1.0)
+    }
+}
+
+///CIE Lab of an sRGB color (D65 white)
+fn lab(color: Color) -> (f32, f32, f32) {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    };
+    let (r, g, b) = (linear(color.r()), linear(color.g()), linear(color.b()));
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f32| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+
+
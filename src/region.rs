@@ -0,0 +1,243 @@
+//regions of similar color for the magic wand and the fill tool, as a coverage per pixel:
+//0 outside, 255 inside, values in between on soft edges
+
+use orbclient::{Color, Renderer};
+use orbimage::Image;
+
+use crate::parallel;
+use crate::Rect;
+use crate::raster::{AddOnsToOrbclientColor, ColorDistance};
+use crate::task::Progress;
+
+//past the tolerance, colors this much further still get some coverage on antialiased edges
+const SOFT_EDGE: f32 = 0.1;
+//spans filled between looks at the cancel flag
+const SPANS_PER_CHECK: usize = 4096;
+
+///which pixels count as the same color
+#[derive(Clone, Copy, Debug)]
+pub struct ColorMatch {
+    ///0.0 only the very same color, 1.0 any color
+    pub tolerance: f32,
+    pub metric: ColorDistance,
+    ///only pixels connected to the start point, otherwise every similar pixel of the image
+    pub contiguous: bool,
+    pub connectivity: Connectivity,
+    ///compare with all visible layers instead of the active one
+    pub sample_all: bool,
+    ///soft edge instead of a staircase
+    pub antialias: bool,
+}
+
+impl Default for ColorMatch {
+    fn default() -> Self {
+        ColorMatch {
+            tolerance: 0.0,
+            metric: ColorDistance::Rgb,
+            contiguous: true,
+            connectivity: Connectivity::Four,
+            sample_all: false,
+            antialias: false,
+        }
+    }
+}
+
+///which neighbours a contiguous region spreads to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Connectivity {
+    ///left, right, up and down
+    Four,
+    ///through corners too
+    Eight,
+}
+
+impl Connectivity {
+    ///4 or 8, as stored in tool properties
+    pub fn from_i32(n: i32) -> Self {
+        if n == 8 { Connectivity::Eight } else { Connectivity::Four }
+    }
+}
+
+///coverage of the pixels matching the color at (x,y) in an image of width x height
+pub fn similar(data: &[Color], width: u32, height: u32, x: i32, y: i32, matching: &ColorMatch) -> Vec<u8> {
+    similar_with(data, width, height, x, y, matching, &Progress::new()).unwrap()
+}
+
+///like similar, adding 2 * height rows to progress as it goes; None if progress is cancelled
+pub fn similar_with(data: &[Color], width: u32, height: u32, x: i32, y: i32, matching: &ColorMatch, progress: &Progress) -> Option<Vec<u8>> {
+    let w = width as usize;
+    let h = height as usize;
+    let mut coverage = vec![0u8; w * h];
+    if x < 0 || y < 0 || x as usize >= w || y as usize >= h {
+        progress.advance(2 * h);
+        return Some(coverage);
+    }
+    let seed = data[y as usize * w + x as usize];
+
+    //distance of every pixel from the seed, the perceptual one is worth the threads
+    let mut distance = vec![0.0f32; w * h];
+    let done = parallel::for_strips_with(&mut distance, w, progress, |first, strip| {
+        let start = first * w;
+        for (n, d) in strip.iter_mut().enumerate() {
+            *d = data[start + n].distance(seed, matching.metric);
+        }
+    });
+    if !done {
+        return None;
+    }
+    let tolerance = matching.tolerance;
+    let inside = |i: usize| distance[i] <= tolerance;
+
+    if matching.contiguous {
+        //rows count as done when the region first reaches them, the rest at the end
+        let mut reached = vec![false; h];
+        let mut spans = 0;
+        //span stack: fill a whole row run at once, then queue the runs above and below it
+        let mut stack = vec![(x as usize, y as usize)];
+        while let Some((x, y)) = stack.pop() {
+            let row = y * w;
+            if coverage[row + x] != 0 || !inside(row + x) {
+                continue;
+            }
+            let mut x0 = x;
+            while x0 > 0 && coverage[row + x0 - 1] == 0 && inside(row + x0 - 1) {
+                x0 -= 1;
+            }
+            let mut x1 = x;
+            while x1 + 1 < w && coverage[row + x1 + 1] == 0 && inside(row + x1 + 1) {
+                x1 += 1;
+            }
+            for c in &mut coverage[row + x0..=row + x1] {
+                *c = 255;
+            }
+            if !reached[y] {
+                reached[y] = true;
+                progress.advance(1);
+            }
+            spans += 1;
+            if spans % SPANS_PER_CHECK == 0 && progress.is_cancelled() {
+                return None;
+            }
+            //with 8-connectivity runs touching the span by a corner count too
+            let (sx0, sx1) = match matching.connectivity {
+                Connectivity::Four => (x0, x1),
+                Connectivity::Eight => (x0.saturating_sub(1), (x1 + 1).min(w - 1)),
+            };
+            let neighbours = [y.checked_sub(1), if y + 1 < h { Some(y + 1) } else { None }];
+            for ny in neighbours.iter().flatten() {
+                let nrow = ny * w;
+                let mut run = false;
+                for nx in sx0..=sx1 {
+                    let open = coverage[nrow + nx] == 0 && inside(nrow + nx);
+                    if open && !run {
+                        stack.push((nx, *ny));
+                    }
+                    run = open;
+                }
+            }
+        }
+        progress.advance(reached.iter().filter(|&&r| !r).count());
+    } else {
+        for (c, &d) in coverage.iter_mut().zip(&distance) {
+            if d <= tolerance {
+                *c = 255;
+            }
+        }
+        progress.advance(h);
+    }
+
+    if matching.antialias {
+        soften(&mut coverage, &distance, w, h, tolerance);
+    }
+    Some(coverage)
+}
+
+///antialias the edge of a coverage: pixels just outside get partial coverage from how close
+///their color is, then a small blur takes the staircase off the outline
+fn soften(coverage: &mut [u8], distance: &[f32], w: usize, h: usize, tolerance: f32) {
+    let full = coverage.to_vec();
+    let covered = |x: usize, y: usize| full[y * w + x] == 255;
+    for y in 0..h {
+        for x in 0..w {
+            let i = y * w + x;
+            if full[i] != 0 {
+                continue;
+            }
+            let edge = (x > 0 && covered(x - 1, y)) || (x + 1 < w && covered(x + 1, y))
+                || (y > 0 && covered(x, y - 1)) || (y + 1 < h && covered(x, y + 1));
+            if edge && distance[i] < tolerance + SOFT_EDGE {
+                coverage[i] = (255.0 * (1.0 - (distance[i] - tolerance) / SOFT_EDGE)) as u8;
+            }
+        }
+    }
+
+    //1-2-1 blur, pixels out of the image repeat the border
+    let ramp = coverage.to_vec();
+    let at = |x: isize, y: isize| {
+        let x = x.max(0).min(w as isize - 1) as usize;
+        let y = y.max(0).min(h as isize - 1) as usize;
+        ramp[y * w + x] as u32
+    };
+    for y in 0..h as isize {
+        for x in 0..w as isize {
+            let mut sum = 0;
+            for (dy, ky) in [(-1, 1), (0, 2), (1, 1)].iter() {
+                for (dx, kx) in [(-1, 1), (0, 2), (1, 1)].iter() {
+                    sum += at(x + dx, y + dy) * ky * kx;
+                }
+            }
+            coverage[y as usize * w + x as usize] = ((sum + 8) / 16) as u8;
+        }
+    }
+}
+
+///paint color over image where coverage is, clip tells how much each pixel can be painted if given
+pub fn paint(image: &mut Image, coverage: &[u8], clip: Option<&[u8]>, color: Color) {
+    paint_with(image, coverage, clip, color, &Progress::new());
+}
+
+///like paint, adding each row to progress; stops and returns false if progress is cancelled
+pub fn paint_with(image: &mut Image, coverage: &[u8], clip: Option<&[u8]>, color: Color, progress: &Progress) -> bool {
+    let width = image.width() as usize;
+    if width == 0 {
+        return true;
+    }
+    for (y, row) in coverage.chunks(width).enumerate() {
+        if progress.is_cancelled() {
+            return false;
+        }
+        for (x, &c) in row.iter().enumerate() {
+            if c == 0 {
+                continue;
+            }
+            let open = clip.map_or(255, |clip| clip[y * width + x] as u32);
+            let alpha = color.a() as u32 * c as u32 * open / (255 * 255);
+            if alpha > 0 {
+                image.pixel(x as i32, y as i32, Color::rgba(color.r(), color.g(), color.b(), alpha as u8));
+            }
+        }
+        progress.advance(1);
+    }
+    true
+}
+
+///smallest rectangle holding every pixel that differs from the top left one, so the border
+///around it is one color; when that pixel is transparent any transparent pixel is border too;
+///None if there is only border
+pub fn content_bounds(data: &[Color], width: u32, height: u32) -> Option<Rect> {
+    let border = *data.first()?;
+    let is_border = |c: &Color| c.data == border.data || (border.a() == 0 && c.a() == 0);
+    let w = width as usize;
+    let rows: Vec<usize> = (0..height as usize).filter(|&y| !data[y * w..(y + 1) * w].iter().all(is_border)).collect();
+    let (&top, &bottom) = (rows.first()?, rows.last()?);
+    let mut left = w;
+    let mut right = 0;
+    for y in top..bottom + 1 {
+        let row = &data[y * w..(y + 1) * w];
+        if let Some(x) = row.iter().position(|c| !is_border(c)) {
+            left = left.min(x);
+            right = right.max(row.iter().rposition(|c| !is_border(c)).unwrap());
+        }
+    }
+    Some(Rect::new(left as i32, top as i32, (right - left + 1) as u32, (bottom - top + 1) as u32))
+}
@@ -0,0 +1,220 @@
+//sampling an image between its pixels, for transforms that do not land on whole pixels
+
+use std::cmp;
+use std::f32::consts::PI;
+
+///how values between pixels are found
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    ///closest pixel, blocky but fast and exact for whole pixel moves
+    Nearest,
+    ///weighted average of the 4 closest pixels
+    Bilinear,
+    ///Catmull-Rom curve through the 16 closest pixels, sharper than bilinear
+    Bicubic,
+}
+
+impl Interpolation {
+    ///0 nearest, 1 bilinear, 2 bicubic, as stored in tool properties
+    pub fn from_i32(n: i32) -> Self {
+        match n {
+            0 => Interpolation::Nearest,
+            2 => Interpolation::Bicubic,
+            _ => Interpolation::Bilinear,
+        }
+    }
+}
+
+///filter for changing the size of an image, from blocky and fast to sharp and slow
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Nearest,
+    ///linear, soft
+    Triangle,
+    ///cubic, sharper
+    CatmullRom,
+    ///smooth, a little blurry
+    Gaussian,
+    ///sharpest, best to shrink photos
+    Lanczos3,
+}
+
+impl Filter {
+    ///0 nearest, 1 triangle, 2 Catmull-Rom, 3 gaussian, 4 Lanczos3
+    pub fn from_i32(n: i32) -> Self {
+        match n {
+            1 => Filter::Triangle,
+            2 => Filter::CatmullRom,
+            3 => Filter::Gaussian,
+            4 => Filter::Lanczos3,
+            _ => Filter::Nearest,
+        }
+    }
+
+    ///how far from a pixel center the filter reaches, in pixels
+    fn support(&self) -> f32 {
+        match *self {
+            Filter::Nearest => 0.0,
+            Filter::Triangle => 1.0,
+            Filter::CatmullRom => 2.0,
+            Filter::Gaussian | Filter::Lanczos3 => 3.0,
+        }
+    }
+
+    ///weight of a pixel x pixels away
+    fn kernel(&self, x: f32) -> f32 {
+        let a = x.abs();
+        match *self {
+            Filter::Nearest => 1.0,
+            Filter::Triangle => (1.0 - a).max(0.0),
+            Filter::CatmullRom => if a < 1.0 {
+                1.5 * a * a * a - 2.5 * a * a + 1.0
+            } else if a < 2.0 {
+                -0.5 * a * a * a + 2.5 * a * a - 4.0 * a + 2.0
+            } else {
+                0.0
+            },
+            Filter::Gaussian => (-2.0 * a * a).exp(),
+            Filter::Lanczos3 => if a < 3.0 { sinc(a) * sinc(a / 3.0) } else { 0.0 },
+        }
+    }
+
+    ///for each of new_size pixels of a row (or column) of size pixels, the first pixel
+    ///it is taken from and the weights of it and the following ones, same as imageops::resize
+    pub fn weights(&self, size: u32, new_size: u32) -> Vec<(usize, Vec<f32>)> {
+        let ratio = size as f32 / new_size as f32;
+        //shrinking widens the filter so every pixel counts
+        let scale = ratio.max(1.0);
+        let support = self.support() * scale;
+        (0..new_size).map(|n| {
+            let center = (n as f32 + 0.5) * ratio;
+            let left = cmp::min(cmp::max((center - support).floor() as i64, 0), size as i64 - 1);
+            let right = cmp::min(cmp::max((center + support).ceil() as i64, left + 1), size as i64);
+            let mut weights: Vec<f32> = (left..right).map(|i| self.kernel((i as f32 - center + 0.5) / scale)).collect();
+            let sum: f32 = weights.iter().sum();
+            for w in weights.iter_mut() {
+                *w /= sum;
+            }
+            (left as usize, weights)
+        }).collect()
+    }
+}
+
+///what a turned image keeps of its corners
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fit {
+    ///same size, the corners are cut off
+    Crop,
+    ///canvas grows to hold the whole turned image
+    Expand,
+    ///largest upright rectangle inside the turned image, no gaps at the corners
+    Inner,
+}
+
+impl Fit {
+    ///0 crop, 1 expand, 2 inner
+    pub fn from_i32(n: i32) -> Self {
+        match n {
+            1 => Fit::Expand,
+            2 => Fit::Inner,
+            _ => Fit::Crop,
+        }
+    }
+
+    ///size of a width x height image turned by theta radians
+    pub fn size(&self, width: u32, height: u32, theta: f32) -> (u32, u32) {
+        let (w, h) = (width as f32, height as f32);
+        let (sin, cos) = (theta.sin().abs(), theta.cos().abs());
+        //a little slack, so right angles do not gain a pixel by rounding
+        let whole = |v: f32| cmp::max(1, (v - 0.001).ceil() as u32);
+        match *self {
+            Fit::Crop => (width, height),
+            Fit::Expand => (whole(w * cos + h * sin), whole(w * sin + h * cos)),
+            Fit::Inner => {
+                let (long, short) = if w >= h { (w, h) } else { (h, w) };
+                //thin images touch the turned sides at two corners only
+                let (inner_w, inner_h) = if short <= 2.0 * sin * cos * long || (sin - cos).abs() < 1e-6 {
+                    let half = short / 2.0;
+                    if w >= h { (half / sin, half / cos) } else { (half / cos, half / sin) }
+                } else {
+                    let cos2 = cos * cos - sin * sin;
+                    ((w * cos - h * sin) / cos2, (h * cos - w * sin) / cos2)
+                };
+                (cmp::max(1, (inner_w + 0.001).floor() as u32), cmp::max(1, (inner_h + 0.001).floor() as u32))
+            },
+        }
+    }
+}
+
+///value at u,v of a w x h grid whose element i is fetch(i), zero outside it;
+///u,v are pixel centers, so 0,0 is the middle of the first element
+pub fn sample<F: Fn(usize) -> [f32; 4]>(w: usize, h: usize, u: f32, v: f32, interpolation: Interpolation, fetch: F) -> [f32; 4] {
+    interpolate(u, v, interpolation, |x, y| {
+        if x < 0 || y < 0 || x >= w as isize || y >= h as isize {
+            [0.0; 4]
+        } else {
+            fetch(y as usize * w + x as usize)
+        }
+    })
+}
+
+///like sample, but outside the grid the closest element on its border repeats
+pub fn sample_clamped<F: Fn(usize) -> [f32; 4]>(w: usize, h: usize, u: f32, v: f32, interpolation: Interpolation, fetch: F) -> [f32; 4] {
+    interpolate(u, v, interpolation, |x, y| {
+        let x = cmp::min(cmp::max(x, 0) as usize, w - 1);
+        let y = cmp::min(cmp::max(y, 0) as usize, h - 1);
+        fetch(y * w + x)
+    })
+}
+
+fn interpolate<A: Fn(isize, isize) -> [f32; 4]>(u: f32, v: f32, interpolation: Interpolation, at: A) -> [f32; 4] {
+    let mut sum = [0.0f32; 4];
+    match interpolation {
+        Interpolation::Nearest => sum = at(u.round() as isize, v.round() as isize),
+        Interpolation::Bilinear => {
+            let (x0, y0) = (u.floor(), v.floor());
+            let (fx, fy) = (u - x0, v - y0);
+            let (x0, y0) = (x0 as isize, y0 as isize);
+            for &(dx, dy, weight) in [(0, 0, (1.0 - fx) * (1.0 - fy)), (1, 0, fx * (1.0 - fy)),
+                                      (0, 1, (1.0 - fx) * fy), (1, 1, fx * fy)].iter() {
+                if weight != 0.0 {
+                    add(&mut sum, at(x0 + dx, y0 + dy), weight);
+                }
+            }
+        },
+        Interpolation::Bicubic => {
+            let (x0, y0) = (u.floor(), v.floor());
+            let (wx, wy) = (catmull_rom(u - x0), catmull_rom(v - y0));
+            let (x0, y0) = (x0 as isize, y0 as isize);
+            for (j, &ky) in wy.iter().enumerate() {
+                for (i, &kx) in wx.iter().enumerate() {
+                    if kx * ky != 0.0 {
+                        add(&mut sum, at(x0 + i as isize - 1, y0 + j as isize - 1), kx * ky);
+                    }
+                }
+            }
+        },
+    }
+    sum
+}
+
+fn add(sum: &mut [f32; 4], value: [f32; 4], weight: f32) {
+    for (s, v) in sum.iter_mut().zip(value.iter()) {
+        *s += v * weight;
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 { 1.0 } else { (x * PI).sin() / (x * PI) }
+}
+
+///weights of the pixels at -1, 0, 1, 2 for a point t (0..1) past pixel 0
+fn catmull_rom(t: f32) -> [f32; 4] {
+    let (t2, t3) = (t * t, t * t * t);
+    [
+        (-t3 + 2.0 * t2 - t) / 2.0,
+        (3.0 * t3 - 5.0 * t2 + 2.0) / 2.0,
+        (-3.0 * t3 + 4.0 * t2 + t) / 2.0,
+        (t3 - t2) / 2.0,
+    ]
+}
@@ -0,0 +1,447 @@
+//selections: how much each pixel is selected lives in the red channel of the document
+//mask (255 all, 0 nothing), shared by the marquee, the magic wand and the quick mask;
+//the shapes here are what selection tools draw and what the marching ants follow,
+//all in document coordinates
+
+use std::f32::consts::PI;
+
+use orbclient::Color;
+
+use crate::parallel;
+use crate::Rect;
+
+//samples per pixel side when measuring coverage of the outline
+const SAMPLES: usize = 4;
+
+///outline of a selection inside its bounds
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    Rectangle,
+    ///ellipse touching the sides of the bounds
+    Ellipse,
+    ///closed polygon through pixel positions, as drawn by the lasso tools
+    Polygon(Vec<(i32, i32)>),
+    ///made by combining selections, the magic wand or the quick mask: only the mask knows it
+    Free,
+}
+
+///how a new selection goes with the current one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Combine {
+    Replace,
+    Add,
+    Subtract,
+    Intersect,
+}
+
+impl Combine {
+    ///0 replace, 1 add, 2 subtract, 3 intersect, as stored in tool properties
+    pub fn from_i32(n: i32) -> Self {
+        match n {
+            1 => Combine::Add,
+            2 => Combine::Subtract,
+            3 => Combine::Intersect,
+            _ => Combine::Replace,
+        }
+    }
+
+    ///coverage of a pixel selected by current and new
+    pub fn apply(self, current: u8, new: u8) -> u8 {
+        let (current, new) = (current as u32, new as u32);
+        (match self {
+            Combine::Replace => new,
+            Combine::Add => current.max(new),
+            Combine::Subtract => current * (255 - new) / 255,
+            Combine::Intersect => current * new / 255,
+        }) as u8
+    }
+}
+
+///change to the edge of a selection, sizes in pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Refine {
+    ///soften the edge so it fades out over about this distance
+    Feather(u32),
+    Grow(u32),
+    Shrink(u32),
+    ///only a band this wide across the edge
+    Border(u32),
+    ///round off corners and jags smaller than this
+    Smooth(u32),
+    ///drop selected bits smaller than this many pixels
+    RemoveIslands(u32),
+}
+
+impl Refine {
+    ///menu name, used as undo label
+    pub fn label(&self) -> &'static str {
+        match self {
+            Refine::Feather(_) => "Feather",
+            Refine::Grow(_) => "Grow",
+            Refine::Shrink(_) => "Shrink",
+            Refine::Border(_) => "Border",
+            Refine::Smooth(_) => "Smooth",
+            Refine::RemoveIslands(_) => "Remove islands",
+        }
+    }
+
+    ///refined coverage of a width x height selection
+    pub fn apply(&self, coverage: &[u8], width: u32, height: u32) -> Vec<u8> {
+        let (w, h) = (width as usize, height as usize);
+        match *self {
+            Refine::Feather(n) => {
+                //three box blurs make a gaussian-like falloff, each spreads the edge by its radius
+                let radius = ((n as usize + 1) / 3).max(1);
+                let mut out = coverage.to_vec();
+                for _ in 0..3 {
+                    out = box_blur(&out, w, h, radius);
+                }
+                out
+            },
+            Refine::Grow(n) => morphology(coverage, w, h, n, true),
+            Refine::Shrink(n) => morphology(coverage, w, h, n, false),
+            Refine::Border(n) => {
+                //half the band outside the edge, half inside
+                let outside = morphology(coverage, w, h, n / 2, true);
+                let inside = morphology(coverage, w, h, n - n / 2, false);
+                outside.iter().zip(&inside).map(|(&o, &i)| (o as u32 * (255 - i as u32) / 255) as u8).collect()
+            },
+            Refine::Smooth(n) => {
+                //blur away the jags, then sharpen back to an edge about a pixel soft
+                let radius = n as usize;
+                let steep = 2 * radius as i32 + 1;
+                box_blur(coverage, w, h, radius).into_iter()
+                    .map(|c| ((c as i32 - 128) * steep + 128).max(0).min(255) as u8)
+                    .collect()
+            },
+            Refine::RemoveIslands(n) => remove_islands(coverage, w, h, n as usize),
+        }
+    }
+}
+
+///selected part of the document
+#[derive(Clone, Debug, PartialEq)]
+pub struct Selection {
+    ///bounds, what copy and filters take before the outline is applied
+    pub rect: Rect,
+    pub shape: Shape,
+}
+
+impl Selection {
+    pub fn rectangle(rect: Rect) -> Self {
+        Selection { rect, shape: Shape::Rectangle }
+    }
+
+    pub fn ellipse(rect: Rect) -> Self {
+        Selection { rect, shape: Shape::Ellipse }
+    }
+
+    ///polygon through points, None if it encloses nothing
+    pub fn polygon(points: Vec<(i32, i32)>) -> Option<Self> {
+        if points.len() < 3 {
+            return None;
+        }
+        let x0 = points.iter().map(|p| p.0).min().unwrap();
+        let x1 = points.iter().map(|p| p.0).max().unwrap();
+        let y0 = points.iter().map(|p| p.1).min().unwrap();
+        let y1 = points.iter().map(|p| p.1).max().unwrap();
+        if x0 == x1 || y0 == y1 {
+            return None;
+        }
+        let rect = Rect::new(x0, y0, (x1 - x0 + 1) as u32, (y1 - y0 + 1) as u32);
+        Some(Selection { rect, shape: Shape::Polygon(points) })
+    }
+
+    ///whatever the mask selects inside bounds
+    pub fn free(rect: Rect) -> Self {
+        Selection { rect, shape: Shape::Free }
+    }
+
+    pub fn is_rectangle(&self) -> bool {
+        self.shape == Shape::Rectangle
+    }
+
+    ///closed outline to draw, the ellipse is made of short segments; empty for a free selection
+    pub fn outline(&self) -> Vec<(i32, i32)> {
+        let r = self.rect;
+        let (x1, y1) = (r.x + r.width as i32, r.y + r.height as i32);
+        match self.shape {
+            Shape::Rectangle => vec![(r.x, r.y), (x1, r.y), (x1, y1), (r.x, y1)],
+            Shape::Ellipse => {
+                let (cx, cy) = (r.x as f32 + r.width as f32 / 2.0, r.y as f32 + r.height as f32 / 2.0);
+                let (rx, ry) = (r.width as f32 / 2.0, r.height as f32 / 2.0);
+                let segments = ((rx + ry) as usize).max(16).min(360);
+                (0..segments).map(|n| {
+                    let t = 2.0 * PI * n as f32 / segments as f32;
+                    ((cx + rx * t.cos()).round() as i32, (cy + ry * t.sin()).round() as i32)
+                }).collect()
+            },
+            Shape::Polygon(ref points) => points.clone(),
+            Shape::Free => Vec::new(),
+        }
+    }
+
+    ///how much of each pixel of the bounds is selected, row by row:
+    ///255 inside, 0 outside, in between where the outline crosses a pixel;
+    ///a free selection is all in the mask, so everything here
+    pub fn coverage(&self) -> Vec<u8> {
+        let w = self.rect.width as usize;
+        let h = self.rect.height as usize;
+        match self.shape {
+            Shape::Rectangle | Shape::Free => vec![255; w * h],
+            Shape::Ellipse => {
+                let (rx, ry) = (w as f32 / 2.0, h as f32 / 2.0);
+                let mut coverage = vec![0u16; w * h];
+                for sy in 0..h * SAMPLES {
+                    let dy = ((sy as f32 + 0.5) / SAMPLES as f32 - ry) / ry;
+                    let half = rx * (1.0 - dy * dy).max(0.0).sqrt();
+                    let row = (sy / SAMPLES) * w;
+                    add_span(&mut coverage[row..row + w], rx - half, rx + half);
+                }
+                to_coverage(&coverage)
+            },
+            Shape::Polygon(ref points) => {
+                //points are pixel centers, relative to the bounds
+                let points: Vec<(f32, f32)> = points.iter()
+                    .map(|p| ((p.0 - self.rect.x) as f32 + 0.5, (p.1 - self.rect.y) as f32 + 0.5))
+                    .collect();
+                let mut coverage = vec![0u16; w * h];
+                let mut crossings = Vec::new();
+                for sy in 0..h * SAMPLES {
+                    let y = (sy as f32 + 0.5) / SAMPLES as f32;
+                    //even-odd rule: spans between pairs of edge crossings are inside
+                    crossings.clear();
+                    for (n, &(xa, ya)) in points.iter().enumerate() {
+                        let (xb, yb) = points[(n + 1) % points.len()];
+                        if (ya <= y) != (yb <= y) {
+                            crossings.push(xa + (y - ya) / (yb - ya) * (xb - xa));
+                        }
+                    }
+                    crossings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                    let row = (sy / SAMPLES) * w;
+                    for span in crossings.chunks(2) {
+                        if span.len() == 2 {
+                            add_span(&mut coverage[row..row + w], span[0], span[1]);
+                        }
+                    }
+                }
+                to_coverage(&coverage)
+            },
+        }
+    }
+
+    ///the same selection moved by dx, dy
+    pub fn translated(&self, dx: i32, dy: i32) -> Self {
+        let rect = Rect::new(self.rect.x + dx, self.rect.y + dy, self.rect.width, self.rect.height);
+        let shape = match self.shape {
+            Shape::Polygon(ref points) => Shape::Polygon(points.iter().map(|p| (p.0 + dx, p.1 + dy)).collect()),
+            ref shape => shape.clone(),
+        };
+        Selection { rect, shape }
+    }
+
+    ///the selection after its content is turned 90 degrees clockwise, keeping the top left corner
+    pub fn rotated90(&self) -> Self {
+        let r = self.rect;
+        let rect = Rect::new(r.x, r.y, r.height, r.width);
+        let shape = match self.shape {
+            Shape::Polygon(ref points) => Shape::Polygon(points.iter()
+                .map(|p| (r.x + r.height as i32 - 1 - (p.1 - r.y), r.y + (p.0 - r.x)))
+                .collect()),
+            ref shape => shape.clone(),
+        };
+        Selection { rect, shape }
+    }
+}
+
+///how a coverage of 0..255 is stored in the mask: fully selected pixels look like a clear mask
+pub fn mask_color(coverage: u8) -> Color {
+    if coverage == 255 {
+        Color::rgba(255, 0, 0, 25)
+    } else {
+        Color::rgba(coverage, coverage, coverage, 255)
+    }
+}
+
+///smallest rectangle with every pixel of a width x height mask that is selected at all
+pub fn mask_bounds(mask: &[Color], width: u32) -> Option<Rect> {
+    let width = width as usize;
+    let mut bounds: Option<(usize, usize, usize, usize)> = None;
+    for (y, row) in mask.chunks(width).enumerate() {
+        let first = row.iter().position(|m| m.r() > 0);
+        let last = row.iter().rposition(|m| m.r() > 0);
+        if let (Some(x0), Some(x1)) = (first, last) {
+            bounds = Some(match bounds {
+                Some((bx0, by0, bx1, _)) => (bx0.min(x0), by0, bx1.max(x1), y),
+                None => (x0, y, x1, y),
+            });
+        }
+    }
+    bounds.map(|(x0, y0, x1, y1)| Rect::new(x0 as i32, y0 as i32, (x1 - x0 + 1) as u32, (y1 - y0 + 1) as u32))
+}
+
+///lines between pixels selected by half or more and the others, for marching ants
+///around a free selection; neighbouring pieces of a line are joined
+pub fn mask_edges(mask: &[Color], width: u32, height: u32) -> Vec<((i32, i32), (i32, i32))> {
+    let (w, h) = (width as i32, height as i32);
+    let inside = |x: i32, y: i32| x >= 0 && y >= 0 && x < w && y < h && mask[(y * w + x) as usize].r() >= 128;
+    let mut edges = Vec::new();
+    //horizontal lines at the top of row y, then vertical ones at the left of column x
+    for y in 0..=h {
+        let mut start = None;
+        for x in 0..=w {
+            let edge = x < w && inside(x, y - 1) != inside(x, y);
+            match (edge, start) {
+                (true, None) => start = Some(x),
+                (false, Some(x0)) => {
+                    edges.push(((x0, y), (x, y)));
+                    start = None;
+                },
+                _ => (),
+            }
+        }
+    }
+    for x in 0..=w {
+        let mut start = None;
+        for y in 0..=h {
+            let edge = y < h && inside(x - 1, y) != inside(x, y);
+            match (edge, start) {
+                (true, None) => start = Some(y),
+                (false, Some(y0)) => {
+                    edges.push(((x, y0), (x, y)));
+                    start = None;
+                },
+                _ => (),
+            }
+        }
+    }
+    edges
+}
+
+///average over a (2 * radius + 1) square, rows first then columns
+fn box_blur(coverage: &[u8], w: usize, h: usize, radius: usize) -> Vec<u8> {
+    if radius == 0 || w == 0 || h == 0 {
+        return coverage.to_vec();
+    }
+    let rows = blur_rows(coverage, w, radius);
+    let columns = blur_rows(&transpose(&rows, w, h), h, radius);
+    transpose(&columns, h, w)
+}
+
+//pixels past the ends of a row count as not selected
+fn blur_rows(data: &[u8], w: usize, radius: usize) -> Vec<u8> {
+    let mut out = vec![0u8; data.len()];
+    let size = (2 * radius + 1) as u32;
+    parallel::for_strips(&mut out, w, |first, strip| {
+        for (n, row) in strip.chunks_mut(w).enumerate() {
+            let src = &data[(first + n) * w..(first + n + 1) * w];
+            //running sum of the window around x
+            let mut sum: u32 = src[..radius.min(w)].iter().map(|&c| c as u32).sum();
+            for x in 0..w {
+                if x + radius < w {
+                    sum += src[x + radius] as u32;
+                }
+                if x > radius {
+                    sum -= src[x - radius - 1] as u32;
+                }
+                row[x] = ((sum + size / 2) / size) as u8;
+            }
+        }
+    });
+    out
+}
+
+fn transpose(data: &[u8], w: usize, h: usize) -> Vec<u8> {
+    let mut out = vec![0u8; data.len()];
+    for y in 0..h {
+        for x in 0..w {
+            out[x * h + y] = data[y * w + x];
+        }
+    }
+    out
+}
+
+///dilate (grow) or erode by radius steps, alternating a cross and a square so
+///the result is close to round; soft edges keep their softness
+fn morphology(coverage: &[u8], w: usize, h: usize, radius: u32, grow: bool) -> Vec<u8> {
+    let pick = |a: u8, b: u8| if grow { a.max(b) } else { a.min(b) };
+    //out of the image is never selected, so shrinking eats from the borders too
+    let outside = vec![0u8; w];
+    let mut out = coverage.to_vec();
+    for step in 0..radius {
+        let src = out.clone();
+        let square = step % 2 == 1;
+        parallel::for_strips(&mut out, w, |first, strip| {
+            let mut vertical = vec![0u8; w];
+            for (n, row) in strip.chunks_mut(w).enumerate() {
+                let y = first + n;
+                let above = if y > 0 { &src[(y - 1) * w..y * w] } else { &outside[..] };
+                let below = if y + 1 < h { &src[(y + 1) * w..(y + 2) * w] } else { &outside[..] };
+                let center = &src[y * w..(y + 1) * w];
+                for x in 0..w {
+                    vertical[x] = pick(center[x], pick(above[x], below[x]));
+                }
+                //the square takes the column extremes of the neighbours, the cross only the row
+                let sides = if square { &vertical[..] } else { center };
+                for x in 0..w {
+                    let left = if x > 0 { sides[x - 1] } else { 0 };
+                    let right = if x + 1 < w { sides[x + 1] } else { 0 };
+                    row[x] = pick(vertical[x], pick(left, right));
+                }
+            }
+        });
+    }
+    out
+}
+
+///unselect connected parts (through corners too) smaller than area pixels
+fn remove_islands(coverage: &[u8], w: usize, h: usize, area: usize) -> Vec<u8> {
+    let mut out = coverage.to_vec();
+    let mut seen = vec![false; w * h];
+    let mut island = Vec::new();
+    let mut stack = Vec::new();
+    for start in 0..w * h {
+        if seen[start] || coverage[start] == 0 {
+            continue;
+        }
+        island.clear();
+        seen[start] = true;
+        stack.push(start);
+        while let Some(i) = stack.pop() {
+            island.push(i);
+            let (x, y) = (i % w, i / w);
+            for ny in y.saturating_sub(1)..(y + 2).min(h) {
+                for nx in x.saturating_sub(1)..(x + 2).min(w) {
+                    let n = ny * w + nx;
+                    if !seen[n] && coverage[n] != 0 {
+                        seen[n] = true;
+                        stack.push(n);
+                    }
+                }
+            }
+        }
+        if island.len() < area {
+            for &i in &island {
+                out[i] = 0;
+            }
+        }
+    }
+    out
+}
+
+///samples counted in each pixel to 0..255
+fn to_coverage(samples: &[u16]) -> Vec<u8> {
+    let full = (SAMPLES * SAMPLES) as u32;
+    samples.iter().map(|&c| (c as u32 * 255 / full) as u8).collect()
+}
+
+///count the samples of one sample row that fall between x0 and x1
+fn add_span(row: &mut [u16], x0: f32, x1: f32) {
+    let samples = (row.len() * SAMPLES) as isize;
+    //first sample whose center is inside and the one after the last
+    let first = ((x0 * SAMPLES as f32 - 0.5).ceil() as isize).max(0);
+    let last = ((x1 * SAMPLES as f32 - 0.5).ceil() as isize).min(samples);
+    for sx in first..last {
+        row[sx as usize / SAMPLES] += 1;
+    }
+}
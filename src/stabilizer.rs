@@ -0,0 +1,106 @@
+//steadier freehand strokes: the pointer pulls the pen on a rope, so small shakes do not
+//move it, the pen positions are averaged and a Catmull-Rom spline goes through them
+
+use std::collections::VecDeque;
+
+//rope length at full strength and distance between spline knots, in screen pixels
+const ROPE: f32 = 40.0;
+const KNOT: f32 = 2.0;
+
+pub struct Stabilizer {
+    rope: f32,
+    //document units per screen pixel
+    unit: f32,
+    //how many pen positions are averaged
+    window: usize,
+    pointer: (f32, f32),
+    pen: (f32, f32),
+    recent: VecDeque<(f32, f32)>,
+    //last points the spline goes through, the first one twice to start it
+    knots: Vec<(f32, f32)>,
+}
+
+impl Stabilizer {
+    ///stroke starting at x,y; strength 0..1 from none to a long rope, unit is how much
+    ///of the coordinates a screen pixel is (1/zoom for document coordinates)
+    pub fn new(strength: f32, unit: f32, x: f32, y: f32) -> Self {
+        let strength = strength.max(0.0).min(1.0);
+        Stabilizer {
+            rope: strength * ROPE * unit,
+            unit,
+            window: 1 + (strength * 8.0) as usize,
+            pointer: (x, y),
+            pen: (x, y),
+            recent: VecDeque::new(),
+            knots: vec![(x, y), (x, y)],
+        }
+    }
+
+    ///pointer moved to x,y: points the smoothed stroke goes through so far, the start left out
+    pub fn to(&mut self, x: f32, y: f32) -> Vec<(f32, f32)> {
+        self.pointer = (x, y);
+        let d = distance(self.pen, self.pointer);
+        if d <= self.rope {
+            return Vec::new();
+        }
+        let k = (d - self.rope) / d;
+        self.pen = (self.pen.0 + (x - self.pen.0) * k, self.pen.1 + (y - self.pen.1) * k);
+        self.recent.push_back(self.pen);
+        if self.recent.len() > self.window {
+            self.recent.pop_front();
+        }
+        //newer positions weigh more
+        let (mut sx, mut sy, mut sw) = (0.0, 0.0, 0.0);
+        for (n, p) in self.recent.iter().enumerate() {
+            let w = (n + 1) as f32;
+            sx += p.0 * w;
+            sy += p.1 * w;
+            sw += w;
+        }
+        let average = (sx / sw, sy / sw);
+        if distance(average, self.knots[self.knots.len() - 1]) < KNOT * self.unit {
+            return Vec::new();
+        }
+        self.knot(average)
+    }
+
+    ///stroke ends: the rest of the way to where the pointer is now
+    pub fn finish(&mut self) -> Vec<(f32, f32)> {
+        let end = self.pointer;
+        let mut points = Vec::new();
+        if distance(end, self.knots[self.knots.len() - 1]) >= 0.5 * self.unit {
+            points.extend(self.knot(end));
+        }
+        //the last knot twice, so the spline reaches it
+        points.extend(self.knot(end));
+        self.pen = end;
+        self.recent.clear();
+        points
+    }
+
+    ///add a knot, giving the spline segment it completes
+    fn knot(&mut self, p: (f32, f32)) -> Vec<(f32, f32)> {
+        self.knots.push(p);
+        if self.knots.len() < 4 {
+            return Vec::new();
+        }
+        let (p0, p1, p2, p3) = (self.knots[0], self.knots[1], self.knots[2], self.knots[3]);
+        self.knots.remove(0);
+        let step = self.unit.min(1.0);
+        let n = (distance(p1, p2) / step).ceil().max(1.0) as usize;
+        (1..n + 1)
+            .map(|i| {
+                let t = i as f32 / n as f32;
+                let (t2, t3) = (t * t, t * t * t);
+                let at = |a: f32, b: f32, c: f32, d: f32| {
+                    0.5 * (2.0 * b + (c - a) * t + (2.0 * a - 5.0 * b + 4.0 * c - d) * t2 + (3.0 * b - a - 3.0 * c + d) * t3)
+                };
+                (at(p0.0, p1.0, p2.0, p3.0), at(p0.1, p1.1, p2.1, p3.1))
+            })
+            .collect()
+    }
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    ((a.0 - b.0) * (a.0 - b.0) + (a.1 - b.1) * (a.1 - b.1)).sqrt()
+}
@@ -0,0 +1,251 @@
+//brush tips made from images, kept as GIMP brushes: .gbr holds one greyscale or colour
+//image, .gih (a pipe) several images picked in turn, at random or by stroke direction
+//
+//Layout of a .gbr (integers big endian):
+//
+//    header size u32, version u32 (1 or 2), width u32, height u32, bytes per pixel u32 (1 or 4)
+//    version 2 only: magic "GIMP", spacing u32 (percent of the size)
+//    name utf8, zero terminated, up to header size
+//    pixels width*height*bytes, greyscale is how much is painted, colour is rgba
+//
+//A .gih is a name line, a line "cells key:value ..." and then as many .gbr one after the other.
+
+use orbclient::Color;
+use orbimage::Image;
+
+use std::cmp;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use crate::layer::{self, BlendMode};
+use crate::resample::{self, Interpolation};
+
+pub const STAMP_EXTENSIONS: [&str; 2] = ["gbr", "gih"];
+
+//largest brush side read from a file
+const MAX_SIZE: u32 = 4096;
+
+///how a pipe picks the cell of each dab
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pick {
+    Incremental,
+    Random,
+    ///by the direction of the stroke, first cell going right and turning clockwise
+    Angular,
+}
+
+#[derive(Clone)]
+pub struct Stamp {
+    pub name: String,
+    ///one for a plain brush, more for a pipe
+    pub cells: Vec<Image>,
+    ///greyscale cells hold how much is painted in alpha and are tinted with the color of the stroke
+    pub greyscale: bool,
+    ///distance between dabs, fraction of the size
+    pub spacing: f32,
+    pub pick: Pick,
+}
+
+impl Stamp {
+    ///stamp from an image; grey images become greyscale stamps, darker and more opaque painting more
+    pub fn from_image(name: &str, image: &Image) -> Self {
+        let greyscale = image.data().iter().all(|c| c.a() == 0 || (c.r() == c.g() && c.g() == c.b()));
+        let cell = if greyscale {
+            let data: Vec<Color> = image.data().iter()
+                .map(|c| Color::rgba(0, 0, 0, ((255 - c.r() as u32) * c.a() as u32 / 255) as u8))
+                .collect();
+            Image::from_data(image.width(), image.height(), data.into_boxed_slice()).unwrap()
+        } else {
+            image.clone()
+        };
+        Stamp { name: name.to_string(), cells: vec![cell], greyscale, spacing: 0.25, pick: Pick::Incremental }
+    }
+
+    ///load a .gbr or .gih brush, any other image becomes a stamp named as its file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("").to_lowercase();
+        match extension.as_ref() {
+            "gbr" | "gih" => {
+                let mut bytes = Vec::new();
+                File::open(path)?.read_to_end(&mut bytes)?;
+                if extension == "gih" { Stamp::read_gih(&bytes) } else { Stamp::read_gbr(&mut &bytes[..]) }
+            },
+            _ => {
+                let image = Image::from_path(path).map_err(|e| invalid(&e))?;
+                let name = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("brush");
+                Ok(Stamp::from_image(name, &image))
+            },
+        }
+    }
+
+    ///save the first cell as a version 2 .gbr
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let cell = &self.cells[0];
+        let bytes: u32 = if self.greyscale { 1 } else { 4 };
+        let mut buffer = Vec::new();
+        for value in &[28 + self.name.len() as u32 + 1, 2, cell.width(), cell.height(), bytes] {
+            buffer.extend_from_slice(&value.to_be_bytes());
+        }
+        buffer.extend_from_slice(b"GIMP");
+        buffer.extend_from_slice(&((self.spacing * 100.0).round() as u32).to_be_bytes());
+        buffer.extend_from_slice(self.name.as_bytes());
+        buffer.push(0);
+        for c in cell.data().iter() {
+            if self.greyscale {
+                buffer.push(c.a());
+            } else {
+                buffer.extend_from_slice(&[c.r(), c.g(), c.b(), c.a()]);
+            }
+        }
+        File::create(path)?.write_all(&buffer)
+    }
+
+    ///width and height of the largest cell
+    pub fn size(&self) -> (u32, u32) {
+        self.cells.iter().fold((1, 1), |(w, h), cell| (cmp::max(w, cell.width()), cmp::max(h, cell.height())))
+    }
+
+    ///first cell fitted into a size x size square on white, for choosing among stamps
+    pub fn thumbnail(&self, size: u32) -> Image {
+        let mut thumbnail = Image::from_color(size, size, Color::rgb(255, 255, 255));
+        let cell = &self.cells[0];
+        let scale = cmp::max(cell.width(), cell.height()) as f32 / size as f32;
+        let (ox, oy) = ((size as f32 - cell.width() as f32 / scale) / 2.0, (size as f32 - cell.height() as f32 / scale) / 2.0);
+        let data = thumbnail.data_mut();
+        for y in 0..size {
+            for x in 0..size {
+                let u = (x as f32 + 0.5 - ox) * scale - 0.5;
+                let v = (y as f32 + 0.5 - oy) * scale - 0.5;
+                let c = self.color(0, u, v, Color::rgb(0, 0, 0));
+                let i = (y * size + x) as usize;
+                data[i] = layer::blend(BlendMode::Normal, data[i], c, 100);
+            }
+        }
+        thumbnail
+    }
+
+    ///color of cell at u,v (pixel centers), greyscale stamps painting tint
+    pub fn color(&self, cell: usize, u: f32, v: f32, tint: Color) -> Color {
+        let cell = &self.cells[cell % self.cells.len()];
+        let data = cell.data();
+        let p = resample::sample(cell.width() as usize, cell.height() as usize, u, v, Interpolation::Bilinear, |i| {
+            let c = data[i];
+            let a = c.a() as f32 / 255.0;
+            [c.r() as f32 * a, c.g() as f32 * a, c.b() as f32 * a, c.a() as f32]
+        });
+        let alpha = p[3].max(0.0).min(255.0);
+        if alpha < 0.5 {
+            return Color::rgba(0, 0, 0, 0);
+        }
+        if self.greyscale {
+            return Color::rgba(tint.r(), tint.g(), tint.b(), (alpha + 0.5) as u8);
+        }
+        let straight = |v: f32| (v * 255.0 / alpha + 0.5).max(0.0).min(255.0) as u8;
+        Color::rgba(straight(p[0]), straight(p[1]), straight(p[2]), (alpha + 0.5) as u8)
+    }
+
+    fn read_gbr(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let start = *bytes;
+        let header_size = read_u32(bytes)? as usize;
+        let version = read_u32(bytes)?;
+        let width = read_u32(bytes)?;
+        let height = read_u32(bytes)?;
+        let depth = read_u32(bytes)?;
+        let mut spacing = 25;
+        let mut fixed = 20;
+        if version >= 2 {
+            if bytes.len() < 4 || &bytes[..4] != b"GIMP" {
+                return Err(invalid("not a GIMP brush"));
+            }
+            *bytes = &bytes[4..];
+            spacing = read_u32(bytes)?;
+            fixed = 28;
+        }
+        if header_size < fixed || start.len() < header_size {
+            return Err(invalid("invalid brush header"));
+        }
+        let name = String::from_utf8_lossy(&start[fixed..header_size]).trim_end_matches('\0').to_string();
+        if width > MAX_SIZE || height > MAX_SIZE {
+            return Err(invalid("brush too large"));
+        }
+        let count = (width as usize).checked_mul(height as usize).ok_or_else(|| invalid("brush too large"))?;
+        let data = &start[header_size..];
+        if depth != 1 && depth != 4 {
+            return Err(invalid("unsupported brush depth"));
+        }
+        if width == 0 || height == 0 || data.len() < count * depth as usize {
+            return Err(invalid("truncated brush"));
+        }
+        let pixels: Vec<Color> = match depth {
+            1 => data[..count].iter().map(|&a| Color::rgba(0, 0, 0, a)).collect(),
+            _ => data[..count * 4].chunks(4).map(|p| Color::rgba(p[0], p[1], p[2], p[3])).collect(),
+        };
+        *bytes = &data[count * depth as usize..];
+        Ok(Stamp {
+            name,
+            cells: vec![Image::from_data(width, height, pixels.into_boxed_slice()).map_err(|e| invalid(&e))?],
+            greyscale: depth == 1,
+            spacing: spacing as f32 / 100.0,
+            pick: Pick::Incremental,
+        })
+    }
+
+    fn read_gih(bytes: &[u8]) -> Result<Self, Error> {
+        let mut lines = bytes.splitn(3, |&b| b == b'\n');
+        let name = String::from_utf8_lossy(lines.next().unwrap_or(b"")).trim().to_string();
+        let parameters = String::from_utf8_lossy(lines.next().unwrap_or(b"")).to_string();
+        let mut data = lines.next().ok_or_else(|| invalid("truncated brush pipe"))?;
+        let mut words = parameters.split_whitespace();
+        let count = words.next().and_then(|n| n.parse::<usize>().ok()).ok_or_else(|| invalid("invalid brush pipe"))?;
+        let pick = match words.find(|word| word.starts_with("sel0:")) {
+            Some("sel0:random") => Pick::Random,
+            Some("sel0:angular") => Pick::Angular,
+            _ => Pick::Incremental,
+        };
+        let mut stamp = Stamp::read_gbr(&mut data)?;
+        for _ in 1..count {
+            let next = Stamp::read_gbr(&mut data)?;
+            stamp.greyscale &= next.greyscale;
+            stamp.cells.extend(next.cells);
+        }
+        stamp.name = name;
+        stamp.pick = pick;
+        Ok(stamp)
+    }
+}
+
+///stamps of all the brushes in dir sorted by name, files that do not load are skipped
+pub fn library<P: AsRef<Path>>(dir: P) -> Vec<Stamp> {
+    let mut stamps = Vec::new();
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let known = path.extension().and_then(|ext| ext.to_str())
+                .map_or(false, |ext| STAMP_EXTENSIONS.contains(&&ext.to_lowercase()[..]));
+            if known {
+                match Stamp::load(&path) {
+                    Ok(stamp) => stamps.push(stamp),
+                    Err(err) => if cfg!(feature = "debug"){println!("Brush {} not loaded: {}", path.display(), err)},
+                }
+            }
+        }
+    }
+    stamps.sort_by(|a, b| a.name.cmp(&b.name));
+    stamps
+}
+
+fn invalid(text: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, text)
+}
+
+fn read_u32(bytes: &mut &[u8]) -> Result<u32, Error> {
+    if bytes.len() < 4 {
+        return Err(invalid("truncated brush"));
+    }
+    let value = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    *bytes = &bytes[4..];
+    Ok(value)
+}
@@ -0,0 +1,96 @@
+//long operations on a worker thread, the ui polls them for progress and can cancel
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+///how far an operation is, shared between the worker and the ui
+#[derive(Default)]
+pub struct Progress {
+    done: AtomicUsize,
+    total: AtomicUsize,
+    cancelled: AtomicBool,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///the operation is made of total units of work (usually rows)
+    pub fn start(&self, total: usize) {
+        self.done.store(0, Ordering::Relaxed);
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, units: usize) {
+        self.done.fetch_add(units, Ordering::Relaxed);
+    }
+
+    ///0..100
+    pub fn percent(&self) -> i32 {
+        let total = self.total.load(Ordering::Relaxed);
+        if total == 0 {
+            return 0;
+        }
+        let done = self.done.load(Ordering::Relaxed);
+        (done.min(total) * 100 / total) as i32
+    }
+
+    ///ask the operation to stop, it notices at the next strip of work
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+///operation running on its own thread
+pub struct Task<T> {
+    progress: Arc<Progress>,
+    receiver: Receiver<Option<T>>,
+}
+
+impl<T: Send + 'static> Task<T> {
+    ///start work on a new thread, it returns None when cancelled
+    pub fn spawn<F>(work: F) -> Self
+    where
+        F: FnOnce(&Progress) -> Option<T> + Send + 'static,
+    {
+        let progress = Arc::new(Progress::new());
+        let (sender, receiver) = mpsc::channel();
+        let worker_progress = progress.clone();
+        thread::spawn(move || {
+            let result = work(&worker_progress);
+            //nobody is waiting any more if the task was dropped
+            let _ = sender.send(if worker_progress.is_cancelled() { None } else { result });
+        });
+        Task { progress, receiver }
+    }
+
+    pub fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    pub fn cancel(&self) {
+        self.progress.cancel();
+    }
+
+    ///None while running, then Some(result) with result None if cancelled or failed
+    pub fn poll(&self) -> Option<Option<T>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            //the worker panicked
+            Err(TryRecvError::Disconnected) => Some(None),
+        }
+    }
+
+    ///block until the work is over
+    pub fn wait(self) -> Option<T> {
+        self.receiver.recv().unwrap_or(None)
+    }
+}
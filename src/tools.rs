@@ -86,6 +86,28 @@ impl Tools {
         } 
     }
 
+    ///all properties as (tool, property, value)
+    pub fn properties(&self) -> Vec<(String, String, String)> {
+        let mut properties = Vec::new();
+        for (tool_name, tool) in &self.tools {
+            for a in tool {
+                properties.push((tool_name.to_string(), a.name.get(), a.value.get()));
+            }
+        }
+        properties
+    }
+
+    ///set property if tool and property exist, unknown ones and non numbers
+    ///for numeric properties are ignored
+    pub fn restore(&self, tool_name: &str, property: &str, value: &str) {
+        if let Some(old) = self.tools.get(tool_name).and_then(|_| self.get_str(tool_name, property)) {
+            if old.parse::<i32>().is_ok() && value.parse::<i32>().is_err() {
+                return;
+            }
+            self.set(tool_name, property, value.to_string());
+        }
+    }
+
     ///get current active tool
     pub fn current(&self) -> String {
         self.get_str("tool","Current").unwrap()
@@ -0,0 +1,666 @@
+//undo history storing only the tiles changed by each step; steps that add, remove
+//or reorder layers keep the whole layer stack instead
+
+use orbclient::{Color, Renderer};
+use orbimage::Image;
+use crate::layer::Layer;
+use crate::selection::Selection;
+
+use std::cmp;
+use std::mem;
+
+const TILE_SIZE: u32 = 64;
+
+///rectangle of pixels, before the step when on the undo stack and after it on the redo stack
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    data: Vec<Color>,
+}
+
+///change of one image
+enum Delta {
+    //changed tiles of an image that kept its size
+    Tiles(Vec<Tile>),
+    //whole image, when the size changed
+    Image(Image),
+}
+
+impl Delta {
+    fn memory(&self) -> usize {
+        let pixels = match *self {
+            Delta::Tiles(ref tiles) => tiles.iter().map(|tile| tile.data.len()).sum(),
+            Delta::Image(ref image) => image.data().len(),
+        };
+        pixels * mem::size_of::<Color>()
+    }
+
+    ///put the content of the delta in image, leaving the delta as it is
+    fn copy_to(&self, image: &mut Image) {
+        match *self {
+            Delta::Tiles(ref tiles) => {
+                let stride = image.width() as usize;
+                let data = image.data_mut();
+                for tile in tiles {
+                    let width = tile.width as usize;
+                    for row in 0..tile.height as usize {
+                        let start = (tile.y as usize + row) * stride + tile.x as usize;
+                        data[start..start + width].copy_from_slice(&tile.data[row * width..(row + 1) * width]);
+                    }
+                }
+            },
+            Delta::Image(ref old) => *image = old.clone(),
+        }
+    }
+
+    ///exchange the content of the delta with the image, so it can go the other way
+    fn swap(&mut self, image: &mut Image) {
+        match *self {
+            Delta::Tiles(ref mut tiles) => {
+                let stride = image.width() as usize;
+                let data = image.data_mut();
+                for tile in tiles.iter_mut() {
+                    let width = tile.width as usize;
+                    for row in 0..tile.height as usize {
+                        let start = (tile.y as usize + row) * stride + tile.x as usize;
+                        data[start..start + width].swap_with_slice(&mut tile.data[row * width..(row + 1) * width]);
+                    }
+                }
+            },
+            Delta::Image(ref mut old) => {
+                old.mode().set(image.mode().get());
+                mem::swap(old, image);
+            },
+        }
+    }
+}
+
+///image as it was at the last checkpoint, changes are found comparing with it
+struct Track {
+    snapshot: Image,
+}
+
+impl Track {
+    ///changes of image since the last checkpoint, the snapshot is updated to image
+    fn delta(&mut self, image: &Image) -> Option<Delta> {
+        if image.width() != self.snapshot.width() || image.height() != self.snapshot.height() {
+            return Some(Delta::Image(mem::replace(&mut self.snapshot, image.clone())));
+        }
+
+        let width = image.width();
+        let height = image.height();
+        let stride = width as usize;
+        let data = image.data();
+        let snapshot = self.snapshot.data_mut();
+        let mut tiles = Vec::new();
+
+        for ty in (0..height).step_by(TILE_SIZE as usize) {
+            for tx in (0..width).step_by(TILE_SIZE as usize) {
+                let tile_w = cmp::min(TILE_SIZE, width - tx) as usize;
+                let tile_h = cmp::min(TILE_SIZE, height - ty) as usize;
+                let row = |y: usize| {
+                    let start = (ty as usize + y) * stride + tx as usize;
+                    start..start + tile_w
+                };
+                if (0..tile_h).all(|y| same(&data[row(y)], &snapshot[row(y)])) {
+                    continue;
+                }
+                let mut tile_data = Vec::with_capacity(tile_w * tile_h);
+                for y in 0..tile_h {
+                    tile_data.extend_from_slice(&snapshot[row(y)]);
+                    snapshot[row(y)].copy_from_slice(&data[row(y)]);
+                }
+                tiles.push(Tile {
+                    x: tx,
+                    y: ty,
+                    width: tile_w as u32,
+                    height: tile_h as u32,
+                    data: tile_data,
+                });
+            }
+        }
+
+        if tiles.is_empty() {
+            None
+        } else {
+            Some(Delta::Tiles(tiles))
+        }
+    }
+
+    ///apply delta to image and keep the snapshot the same as image
+    fn apply(&mut self, delta: &mut Delta, image: &mut Image) {
+        delta.swap(image);
+        match *delta {
+            Delta::Tiles(ref tiles) => {
+                let stride = image.width() as usize;
+                let data = image.data();
+                let snapshot = self.snapshot.data_mut();
+                for tile in tiles {
+                    for row in 0..tile.height as usize {
+                        let start = (tile.y as usize + row) * stride + tile.x as usize;
+                        let end = start + tile.width as usize;
+                        snapshot[start..end].copy_from_slice(&data[start..end]);
+                    }
+                }
+            },
+            Delta::Image(_) => self.snapshot = image.clone(),
+        }
+    }
+}
+
+///Color equality leaves out alpha, an eraser may change nothing else
+fn same(a: &[Color], b: &[Color]) -> bool {
+    a.iter().zip(b).all(|(a, b)| a.data == b.data)
+}
+
+///one undoable operation
+struct Step {
+    label: String,
+    //layer the image delta belongs to
+    layer: usize,
+    image: Option<Delta>,
+    mask: Option<Delta>,
+    //selection on the other side of the step, when it changed
+    selection: Option<Option<Selection>>,
+    //all layers (with the pixels of the active one) and the active layer on the other
+    //side of the step, when the stack changed
+    stack: Option<(Vec<Layer>, usize)>,
+}
+
+impl Step {
+    fn memory(&self) -> usize {
+        let stack = self.stack.as_ref().map_or(0, |(layers, _)| {
+            layers.iter().map(|layer| layer.image.data().len() * mem::size_of::<Color>()).sum()
+        });
+        self.image.as_ref().map_or(0, |delta| delta.memory()) + self.mask.as_ref().map_or(0, |delta| delta.memory()) + stack
+    }
+}
+
+///what the history keeps track of
+pub struct State<'a> {
+    ///pixels of the active layer
+    pub image: &'a mut Image,
+    pub mask: &'a mut Image,
+    pub selection: &'a mut Option<Selection>,
+    ///layer stack, the pixels of the active layer are in image instead
+    pub layers: &'a mut Vec<Layer>,
+    pub active: &'a mut usize,
+}
+
+impl<'a> State<'a> {
+    ///copy of all layers, including the pixels of the active one
+    fn stack(&self) -> (Vec<Layer>, usize) {
+        let mut layers = self.layers.clone();
+        layers[*self.active].image = self.image.clone();
+        (layers, *self.active)
+    }
+
+    ///make layer n the active one, its pixels go to image
+    fn switch(&mut self, n: usize) {
+        let image = mem::replace(&mut self.layers[n].image, Image::new(0, 0));
+        image.mode().set(self.image.mode().get());
+        self.layers[*self.active].image = mem::replace(self.image, image);
+        *self.active = n;
+    }
+}
+
+///undo and redo stacks for layers, mask and selection, limited by memory instead of number of steps
+pub struct History {
+    //the image track follows the active layer
+    image: Track,
+    layer: usize,
+    mask: Track,
+    selection: Option<Selection>,
+    //label of the operation in progress, it names the next step
+    label: String,
+    //layers before the operation in progress, when it changes the stack
+    stack: Option<(Vec<Layer>, usize)>,
+    undo: Vec<Step>,
+    redo: Vec<Step>,
+    //bytes
+    budget: usize,
+    used: usize,
+}
+
+impl History {
+    ///budget in megabytes, the snapshots of image and mask are not counted
+    pub fn new(image: &Image, mask: &Image, budget: usize) -> Self {
+        History {
+            image: Track { snapshot: image.clone() },
+            layer: 0,
+            mask: Track { snapshot: mask.clone() },
+            selection: None,
+            label: String::new(),
+            stack: None,
+            undo: Vec::new(),
+            redo: Vec::new(),
+            budget: budget * 1024 * 1024,
+            used: 0,
+        }
+    }
+
+    ///forget all steps, state becomes the new starting point
+    pub fn reset(&mut self, state: State) {
+        self.image.snapshot = state.image.clone();
+        self.layer = *state.active;
+        self.mask.snapshot = state.mask.clone();
+        self.selection = state.selection.clone();
+        self.label.clear();
+        self.stack = None;
+        self.undo.clear();
+        self.redo.clear();
+        self.used = 0;
+    }
+
+    ///label of the step undo would revert
+    pub fn undo_label(&self) -> Option<&str> {
+        if !self.label.is_empty() {
+            Some(&self.label)
+        } else {
+            self.undo.last().map(|step| &step.label[..])
+        }
+    }
+
+    ///label of the step redo would apply
+    pub fn redo_label(&self) -> Option<&str> {
+        self.redo.last().map(|step| &step.label[..])
+    }
+
+    ///bytes used by the steps
+    pub fn memory(&self) -> usize {
+        self.used
+    }
+
+    ///start an operation named label, changes made so far are recorded
+    ///under the previous label
+    pub fn begin(&mut self, label: &str, state: State) {
+        self.checkpoint(state);
+        self.label = label.to_owned();
+    }
+
+    ///start an operation named label that adds, removes or reorders layers;
+    ///the next checkpoint records the whole stack as it is now
+    pub fn begin_layers(&mut self, label: &str, state: State) {
+        let state = self.flush(state);
+        self.label = label.to_owned();
+        self.stack = Some(state.stack());
+    }
+
+    ///like begin_layers, but when the last step is one named label right before, it is
+    ///continued, so changes like dragging a slider make one step
+    pub fn continue_layers(&mut self, label: &str, state: State) {
+        let state = self.flush(state);
+        let again = self.redo.is_empty() && self.undo.last().map_or(false, |step| step.label == label && step.stack.is_some());
+        if again {
+            let step = self.undo.pop().unwrap();
+            self.used -= step.memory();
+            self.label = step.label;
+            self.stack = step.stack;
+        } else {
+            self.label = label.to_owned();
+            self.stack = Some(state.stack());
+        }
+    }
+
+    ///make layer n the active one without a step, changes made so far are recorded first
+    pub fn select_layer(&mut self, n: usize, state: State) {
+        let mut state = self.flush(state);
+        state.switch(n);
+        self.image.snapshot = state.image.clone();
+        self.layer = n;
+    }
+
+    ///record changes made since the last checkpoint as one step,
+    ///returns false if nothing changed
+    pub fn checkpoint(&mut self, state: State) -> bool {
+        let label = mem::replace(&mut self.label, String::new());
+        let stack = self.stack.take();
+        //the stack holds the pixels of all layers, the active one included
+        let image = if stack.is_some() {
+            self.image.snapshot = state.image.clone();
+            None
+        } else {
+            self.image.delta(state.image)
+        };
+        let mask = self.mask.delta(state.mask);
+        let selection = if *state.selection != self.selection {
+            Some(mem::replace(&mut self.selection, state.selection.clone()))
+        } else {
+            None
+        };
+        if image.is_none() && mask.is_none() && selection.is_none() && stack.is_none() {
+            return false;
+        }
+        let step = Step {
+            label: if label.is_empty() { "Edit".to_owned() } else { label },
+            layer: self.layer,
+            image,
+            mask,
+            selection,
+            stack,
+        };
+        self.layer = *state.active;
+
+        //a new step makes the redo branch unreachable
+        for step in self.redo.drain(..) {
+            self.used -= step.memory();
+        }
+        self.used += step.memory();
+        self.undo.push(step);
+
+        //drop oldest steps over budget, but always keep the last one
+        while self.used > self.budget && self.undo.len() > 1 {
+            let step = self.undo.remove(0);
+            self.used -= step.memory();
+        }
+        true
+    }
+
+    ///image (or mask) as it was steps undo steps before the last checkpoint, the oldest one
+    ///kept if there are not that many; None if it had another size than the current one;
+    ///for the image, steps before the layer stack changed or on other layers are left out
+    pub fn snapshot(&self, mask: bool, steps: usize) -> Option<Image> {
+        let track = if mask { &self.mask } else { &self.image };
+        let mut image = track.snapshot.clone();
+        for step in self.undo.iter().rev().take(steps) {
+            if !mask && (step.stack.is_some() || step.layer != self.layer) {
+                break;
+            }
+            let delta = if mask { &step.mask } else { &step.image };
+            if let Some(ref delta) = *delta {
+                delta.copy_to(&mut image);
+            }
+        }
+        if image.width() == track.snapshot.width() && image.height() == track.snapshot.height() {
+            Some(image)
+        } else {
+            None
+        }
+    }
+
+    ///go back one step, pending changes are recorded first so they can be redone
+    pub fn undo(&mut self, state: State) -> bool {
+        let state = self.flush(state);
+        match self.undo.pop() {
+            Some(mut step) => {
+                self.apply_counted(&mut step, state);
+                self.redo.push(step);
+                true
+            },
+            None => false,
+        }
+    }
+
+    ///go forward one step, not possible once something changed after undo
+    pub fn redo(&mut self, state: State) -> bool {
+        let state = self.flush(state);
+        match self.redo.pop() {
+            Some(mut step) => {
+                self.apply_counted(&mut step, state);
+                self.undo.push(step);
+                true
+            },
+            None => false,
+        }
+    }
+
+    ///checkpoint and give state back
+    fn flush<'a>(&mut self, state: State<'a>) -> State<'a> {
+        let State { image, mask, selection, layers, active } = state;
+        self.checkpoint(State {
+            image: &mut *image,
+            mask: &mut *mask,
+            selection: &mut *selection,
+            layers: &mut *layers,
+            active: &mut *active,
+        });
+        State { image, mask, selection, layers, active }
+    }
+
+    ///apply, keeping the memory count right: a step holding a whole image or stack
+    ///holds the other size after it
+    fn apply_counted(&mut self, step: &mut Step, state: State) {
+        self.used -= step.memory();
+        self.apply(step, state);
+        self.used += step.memory();
+    }
+
+    fn apply(&mut self, step: &mut Step, mut state: State) {
+        match step.stack {
+            Some((ref mut layers, ref mut active)) => {
+                //back to the whole stack, then the active layer out of it again
+                state.layers[*state.active].image = mem::replace(state.image, Image::new(0, 0));
+                mem::swap(layers, state.layers);
+                mem::swap(active, state.active);
+                let image = mem::replace(&mut state.layers[*state.active].image, Image::new(0, 0));
+                image.mode().set(state.mask.mode().get());
+                *state.image = image;
+                self.image.snapshot = state.image.clone();
+                self.layer = *state.active;
+            },
+            //the step is on another layer, it becomes the active one
+            None if step.layer != *state.active && step.layer < state.layers.len() => {
+                state.switch(step.layer);
+                self.image.snapshot = state.image.clone();
+                self.layer = step.layer;
+            },
+            None => (),
+        }
+        if let Some(ref mut delta) = step.image {
+            self.image.apply(delta, state.image);
+        }
+        if let Some(ref mut delta) = step.mask {
+            self.mask.apply(delta, state.mask);
+        }
+        if let Some(ref mut selection) = step.selection {
+            mem::swap(selection, state.selection);
+            self.selection = state.selection.clone();
+        }
+        step.layer = *state.active;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rect;
+
+    //what a document keeps, for handing to the history as State
+    struct Doc {
+        image: Image,
+        mask: Image,
+        selection: Option<Selection>,
+        layers: Vec<Layer>,
+        active: usize,
+    }
+
+    impl Doc {
+        fn new(width: u32, height: u32) -> Self {
+            Doc {
+                image: Image::from_color(width, height, Color::rgb(255, 255, 255)),
+                mask: Image::from_color(width, height, Color::rgba(255, 0, 0, 25)),
+                selection: None,
+                layers: vec![Layer::new("Background", Image::new(0, 0))],
+                active: 0,
+            }
+        }
+
+        fn state(&mut self) -> State<'_> {
+            State {
+                image: &mut self.image,
+                mask: &mut self.mask,
+                selection: &mut self.selection,
+                layers: &mut self.layers,
+                active: &mut self.active,
+            }
+        }
+
+        fn history(&self, budget: usize) -> History {
+            History::new(&self.image, &self.mask, budget)
+        }
+
+        fn pixels(&self) -> Vec<u32> {
+            self.image.data().iter().map(|c| c.data).collect()
+        }
+    }
+
+    //a line across row y, as one step
+    fn stroke(history: &mut History, doc: &mut Doc, y: i32, color: Color) {
+        history.begin("Pen", doc.state());
+        let width = doc.image.width();
+        doc.image.rect(0, y, width, 1, color);
+        assert!(history.checkpoint(doc.state()));
+    }
+
+    #[test]
+    fn undo_and_redo_all_strokes() {
+        let mut doc = Doc::new(150, 100);
+        let mut history = doc.history(10);
+        let mut states = vec![doc.pixels()];
+        for n in 0..6 {
+            stroke(&mut history, &mut doc, n * 17, Color::rgba(n as u8 * 40, 0, 0, 200));
+            states.push(doc.pixels());
+        }
+        for expected in states.iter().rev().skip(1) {
+            assert!(history.undo(doc.state()));
+            assert_eq!(&doc.pixels(), expected);
+        }
+        assert!(!history.undo(doc.state()));
+        for expected in states.iter().skip(1) {
+            assert!(history.redo(doc.state()));
+            assert_eq!(&doc.pixels(), expected);
+        }
+        assert!(!history.redo(doc.state()));
+    }
+
+    #[test]
+    fn new_edit_drops_redo() {
+        let mut doc = Doc::new(20, 20);
+        let mut history = doc.history(10);
+        stroke(&mut history, &mut doc, 1, Color::rgb(255, 0, 0));
+        stroke(&mut history, &mut doc, 2, Color::rgb(0, 255, 0));
+        history.undo(doc.state());
+        assert_eq!(history.redo_label(), Some("Pen"));
+        stroke(&mut history, &mut doc, 3, Color::rgb(0, 0, 255));
+        assert_eq!(history.redo_label(), None);
+        assert!(!history.redo(doc.state()));
+        //the green line is gone for good
+        assert!(same(&doc.image.data()[40..60], &[Color::rgb(255, 255, 255); 20]));
+    }
+
+    #[test]
+    fn budget_keeps_last_step() {
+        let mut doc = Doc::new(100, 100);
+        let mut history = doc.history(0);
+        let before = doc.pixels();
+        stroke(&mut history, &mut doc, 10, Color::rgb(255, 0, 0));
+        let middle = doc.pixels();
+        stroke(&mut history, &mut doc, 20, Color::rgb(0, 255, 0));
+        assert!(history.memory() > 0);
+        assert!(history.undo(doc.state()));
+        assert_eq!(doc.pixels(), middle);
+        //the first step went over the budget
+        assert!(!history.undo(doc.state()));
+        assert_ne!(doc.pixels(), before);
+    }
+
+    #[test]
+    fn size_change_is_undone() {
+        let mut doc = Doc::new(30, 20);
+        let mut history = doc.history(10);
+        stroke(&mut history, &mut doc, 5, Color::rgb(255, 0, 0));
+        let before = doc.pixels();
+        history.begin("Resize", doc.state());
+        doc.image = Image::from_color(60, 10, Color::rgb(0, 0, 255));
+        doc.mask = Image::from_color(60, 10, Color::rgba(255, 0, 0, 25));
+        assert!(history.checkpoint(doc.state()));
+        assert!(history.undo(doc.state()));
+        assert_eq!((doc.image.width(), doc.image.height()), (30, 20));
+        assert_eq!((doc.mask.width(), doc.mask.height()), (30, 20));
+        assert_eq!(doc.pixels(), before);
+        assert!(history.redo(doc.state()));
+        assert_eq!((doc.image.width(), doc.image.height()), (60, 10));
+        assert_eq!((doc.mask.width(), doc.mask.height()), (60, 10));
+    }
+
+    #[test]
+    fn selection_only_steps() {
+        let mut doc = Doc::new(20, 20);
+        let mut history = doc.history(10);
+        let first = Some(Selection::rectangle(Rect::new(1, 1, 5, 5)));
+        let second = Some(Selection::rectangle(Rect::new(2, 3, 10, 4)));
+        history.begin("Select", doc.state());
+        doc.selection = first.clone();
+        assert!(history.checkpoint(doc.state()));
+        history.begin("Select", doc.state());
+        doc.selection = second.clone();
+        assert!(history.checkpoint(doc.state()));
+        assert_eq!(history.memory(), 0);
+        history.undo(doc.state());
+        assert_eq!(doc.selection, first);
+        history.undo(doc.state());
+        assert_eq!(doc.selection, None);
+        history.redo(doc.state());
+        history.redo(doc.state());
+        assert_eq!(doc.selection, second);
+        //nothing changed, no step
+        history.begin("Select", doc.state());
+        assert!(!history.checkpoint(doc.state()));
+    }
+
+    #[test]
+    fn labels() {
+        let mut doc = Doc::new(20, 20);
+        let mut history = doc.history(10);
+        assert_eq!(history.undo_label(), None);
+        stroke(&mut history, &mut doc, 1, Color::rgb(255, 0, 0));
+        history.begin("Fill", doc.state());
+        //named by the operation in progress
+        assert_eq!(history.undo_label(), Some("Fill"));
+        doc.image.set(Color::rgb(0, 0, 0));
+        history.checkpoint(doc.state());
+        //unnamed changes
+        doc.image.set(Color::rgb(0, 0, 255));
+        history.checkpoint(doc.state());
+        assert_eq!(history.undo_label(), Some("Edit"));
+        history.undo(doc.state());
+        assert_eq!((history.undo_label(), history.redo_label()), (Some("Fill"), Some("Edit")));
+        history.undo(doc.state());
+        assert_eq!((history.undo_label(), history.redo_label()), (Some("Pen"), Some("Fill")));
+    }
+
+    #[test]
+    fn layer_steps() {
+        let mut doc = Doc::new(20, 20);
+        let mut history = doc.history(10);
+        history.begin_layers("New layer", doc.state());
+        doc.layers[0].image = mem::replace(&mut doc.image, Image::from_color(20, 20, Color::rgba(0, 0, 0, 0)));
+        doc.layers.push(Layer::new("Layer 2", Image::new(0, 0)));
+        doc.active = 1;
+        assert!(history.checkpoint(doc.state()));
+        stroke(&mut history, &mut doc, 4, Color::rgb(255, 0, 0));
+        let top = doc.pixels();
+
+        //painting the bottom layer, undo goes back to the top one
+        history.select_layer(0, doc.state());
+        assert_eq!(doc.active, 0);
+        stroke(&mut history, &mut doc, 8, Color::rgb(0, 255, 0));
+        assert!(history.undo(doc.state()));
+        assert!(history.undo(doc.state()));
+        assert_eq!(doc.active, 1);
+        assert!(doc.pixels().iter().all(|&c| c == 0));
+        assert!(history.undo(doc.state()));
+        assert_eq!((doc.layers.len(), doc.active), (1, 0));
+        assert_eq!(doc.pixels(), vec![Color::rgb(255, 255, 255).data; 400]);
+
+        assert!(history.redo(doc.state()));
+        assert!(history.redo(doc.state()));
+        assert_eq!((doc.layers.len(), doc.active), (2, 1));
+        assert_eq!(doc.pixels(), top);
+        assert!(history.redo(doc.state()));
+        assert_eq!(doc.active, 0);
+        assert_eq!(history.redo_label(), None);
+    }
+}